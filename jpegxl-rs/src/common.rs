@@ -20,11 +20,50 @@ along with jpegxl-rs.  If not, see <https://www.gnu.org/licenses/>.
 use byteorder::{ByteOrder, NativeEndian, BE, LE};
 use half::f16;
 
-use jpegxl_sys::types::{JxlDataType, JxlPixelFormat};
+use std::mem::MaybeUninit;
+
+use jpegxl_sys::{
+    color_encoding::JxlColorEncoding,
+    encode::{JxlColorEncodingSetToLinearSRGB, JxlColorEncodingSetToSRGB},
+    types::{JxlDataType, JxlPixelFormat},
+};
 
 /// Endianness of the pixels
 pub type Endianness = jpegxl_sys::types::JxlEndianness;
 
+/// Color profile of the encoder input, or the one the decoder converts its output to
+#[derive(Debug, Clone, Copy)]
+pub enum ColorEncoding {
+    /// SRGB, default for uint pixel types
+    Srgb,
+    /// Linear SRGB, default for float pixel types
+    LinearSrgb,
+    /// SRGB, images with only luma channel
+    SrgbLuma,
+    /// Linear SRGB with only luma channel
+    LinearSrgbLuma,
+}
+
+impl From<ColorEncoding> for JxlColorEncoding {
+    fn from(val: ColorEncoding) -> Self {
+        use ColorEncoding::{LinearSrgb, LinearSrgbLuma, Srgb, SrgbLuma};
+
+        let mut color_encoding = MaybeUninit::uninit();
+
+        unsafe {
+            match val {
+                Srgb => JxlColorEncodingSetToSRGB(color_encoding.as_mut_ptr(), false),
+                LinearSrgb => JxlColorEncodingSetToLinearSRGB(color_encoding.as_mut_ptr(), false),
+                SrgbLuma => JxlColorEncodingSetToSRGB(color_encoding.as_mut_ptr(), true),
+                LinearSrgbLuma => {
+                    JxlColorEncodingSetToLinearSRGB(color_encoding.as_mut_ptr(), true);
+                }
+            }
+            color_encoding.assume_init()
+        }
+    }
+}
+
 mod private {
     pub trait Sealed {}
 
@@ -35,7 +74,23 @@ mod private {
 }
 
 /// Pixel data type.
-/// Currently `u8`, `u16`, `f16` and `f32` are supported.
+///
+/// The trait is sealed, so only the types matching a [`JxlDataType`] of `libjxl` implement it:
+///
+/// | Type  | [`pixel_type`](Self::pixel_type) | [`bits_per_sample`](Self::bits_per_sample) |
+/// |-------|----------------------------------|--------------------------------------------|
+/// | `u8`  | [`JxlDataType::Uint8`]           | `(8, 0)`                                   |
+/// | `u16` | [`JxlDataType::Uint16`]          | `(16, 0)`                                  |
+/// | `f16` | [`JxlDataType::Float16`]         | `(16, 5)`                                  |
+/// | `f32` | [`JxlDataType::Float`]           | `(32, 8)`                                  |
+///
+/// Other types are rejected at compile time:
+///
+/// ```compile_fail
+/// # use jpegxl_rs::decoder_builder;
+/// let decoder = decoder_builder().build().unwrap();
+/// decoder.decode_with::<i32>(&[]);
+/// ```
 pub trait PixelType: private::Sealed + Sized {
     /// Return the C const
     fn pixel_type() -> JxlDataType;
@@ -44,7 +99,25 @@ pub trait PixelType: private::Sealed + Sized {
     fn bits_per_sample() -> (u32, u32);
 
     /// Convert the data to the pixel type
-    fn convert(data: &[u8], pixel_format: &JxlPixelFormat) -> Vec<Self>;
+    #[must_use]
+    fn convert(data: &[u8], pixel_format: &JxlPixelFormat) -> Vec<Self> {
+        let mut buf = vec![];
+        Self::convert_into(data, pixel_format, &mut buf);
+        buf
+    }
+
+    /// Convert the data to the pixel type into `buf`, reusing its allocation
+    fn convert_into(data: &[u8], pixel_format: &JxlPixelFormat, buf: &mut Vec<Self>);
+
+    /// Luminance of the `r`, `g` and `b` samples with the Rec. 709 weights, i.e.
+    /// `0.2126 * r + 0.7152 * g + 0.0722 * b`, rounded for integer types
+    #[must_use]
+    fn luma(r: Self, g: Self, b: Self) -> Self;
+}
+
+/// Rec. 709 luminance
+fn rec709(r: f32, g: f32, b: f32) -> f32 {
+    0.0722f32.mul_add(b, 0.2126f32.mul_add(r, 0.7152 * g))
 }
 
 impl PixelType for u8 {
@@ -56,8 +129,15 @@ impl PixelType for u8 {
         (8, 0)
     }
 
-    fn convert(data: &[u8], _pixel_format: &JxlPixelFormat) -> Vec<Self> {
-        data.to_vec()
+    fn convert_into(data: &[u8], _pixel_format: &JxlPixelFormat, buf: &mut Vec<Self>) {
+        buf.clear();
+        buf.extend_from_slice(data);
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn luma(r: Self, g: Self, b: Self) -> Self {
+        // The weights sum to 1, so the luminance is in range
+        rec709(r.into(), g.into(), b.into()).round() as Self
     }
 }
 
@@ -70,14 +150,19 @@ impl PixelType for u16 {
         (16, 0)
     }
 
-    fn convert(data: &[u8], pixel_format: &JxlPixelFormat) -> Vec<Self> {
-        let mut buf = vec![u16::default(); data.len() / std::mem::size_of::<u16>()];
+    fn convert_into(data: &[u8], pixel_format: &JxlPixelFormat, buf: &mut Vec<Self>) {
+        buf.resize(data.len() / std::mem::size_of::<u16>(), u16::default());
         match pixel_format.endianness {
             Endianness::Native => NativeEndian::read_u16_into(data, buf.as_mut_slice()),
             Endianness::Little => LE::read_u16_into(data, buf.as_mut_slice()),
             Endianness::Big => BE::read_u16_into(data, buf.as_mut_slice()),
         }
-        buf
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn luma(r: Self, g: Self, b: Self) -> Self {
+        // The weights sum to 1, so the luminance is in range
+        rec709(r.into(), g.into(), b.into()).round() as Self
     }
 }
 
@@ -91,14 +176,17 @@ impl PixelType for f32 {
         (32, 8)
     }
 
-    fn convert(data: &[u8], pixel_format: &JxlPixelFormat) -> Vec<Self> {
-        let mut buf = vec![f32::default(); data.len() / std::mem::size_of::<f32>()];
+    fn convert_into(data: &[u8], pixel_format: &JxlPixelFormat, buf: &mut Vec<Self>) {
+        buf.resize(data.len() / std::mem::size_of::<f32>(), f32::default());
         match pixel_format.endianness {
             Endianness::Native => NativeEndian::read_f32_into(data, buf.as_mut_slice()),
             Endianness::Little => LE::read_f32_into(data, buf.as_mut_slice()),
             Endianness::Big => BE::read_f32_into(data, buf.as_mut_slice()),
         }
-        buf
+    }
+
+    fn luma(r: Self, g: Self, b: Self) -> Self {
+        rec709(r, g, b)
     }
 }
 
@@ -111,15 +199,41 @@ impl PixelType for f16 {
         (16, 5)
     }
 
-    fn convert(data: &[u8], pixel_format: &JxlPixelFormat) -> Vec<Self> {
-        data.chunks_exact(std::mem::size_of::<f16>())
-            .map(|v| {
-                f16::from_bits(match pixel_format.endianness {
-                    Endianness::Native => NativeEndian::read_u16(v),
-                    Endianness::Little => LE::read_u16(v),
-                    Endianness::Big => BE::read_u16(v),
-                })
+    fn convert_into(data: &[u8], pixel_format: &JxlPixelFormat, buf: &mut Vec<Self>) {
+        buf.clear();
+        buf.extend(data.chunks_exact(std::mem::size_of::<f16>()).map(|v| {
+            f16::from_bits(match pixel_format.endianness {
+                Endianness::Native => NativeEndian::read_u16(v),
+                Endianness::Little => LE::read_u16(v),
+                Endianness::Big => BE::read_u16(v),
             })
-            .collect()
+        }));
+    }
+
+    fn luma(r: Self, g: Self, b: Self) -> Self {
+        f16::from_f32(rec709(r.into(), g.into(), b.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_types() {
+        assert_eq!(u8::pixel_type(), JxlDataType::Uint8);
+        assert_eq!(u16::pixel_type(), JxlDataType::Uint16);
+        assert_eq!(f16::pixel_type(), JxlDataType::Float16);
+        assert_eq!(f32::pixel_type(), JxlDataType::Float);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn luma() {
+        assert_eq!(u8::luma(255, 255, 255), 255);
+        assert_eq!(u8::luma(255, 0, 0), 54);
+        assert_eq!(u16::luma(0, 65535, 0), 46871);
+        assert_eq!(f32::luma(0.0, 0.0, 1.0), 0.0722);
+        assert_eq!(f16::luma(f16::ONE, f16::ONE, f16::ONE), f16::ONE);
     }
 }
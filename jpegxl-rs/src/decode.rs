@@ -17,25 +17,45 @@ along with jpegxl-rs.  If not, see <https://www.gnu.org/licenses/>.
 
 //! Decoder of JPEG XL format
 
-use std::{mem::MaybeUninit, ptr::null};
+use std::{
+    collections::BTreeMap,
+    ffi::c_void,
+    io::{ErrorKind, Read, Write},
+    mem::MaybeUninit,
+    ops::ControlFlow,
+    ptr::null,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+};
 
 #[allow(clippy::wildcard_imports)]
 use jpegxl_sys::{
-    codestream_header::{JxlBasicInfo, JxlOrientation},
+    codestream_header::{JxlBasicInfo, JxlExtraChannelInfo, JxlExtraChannelType, JxlOrientation},
+    color_encoding::{JxlColorEncoding, JxlColorSpace, JxlRenderingIntent, JxlTransferFunction},
     decode::*,
-    types::{JxlDataType, JxlPixelFormat},
+    types::{JxlBool, JxlBoxType, JxlDataType, JxlPixelFormat},
 };
 
 use crate::{
     common::{Endianness, PixelType},
-    errors::{check_dec_status, DecodeError},
+    encode::BlendMode,
+    errors::{check_dec_status, from_status, DecodeError},
     memory::MemoryManager,
     parallel::JxlParallelRunner,
     utils::check_valid_signature,
 };
 
+mod exif;
 mod result;
+mod stream;
+pub use crate::common::ColorEncoding;
 pub use result::*;
+pub use stream::{ChunkedDecoder, DecoderEvent};
+use stream::{Event, StreamingDecoder};
+#[cfg(feature = "futures")]
+pub use stream::{FrameStream, ReaderFrameStream};
 
 /// Basic information
 pub type BasicInfo = JxlBasicInfo;
@@ -43,6 +63,30 @@ pub type BasicInfo = JxlBasicInfo;
 pub type ProgressiveDetail = JxlProgressiveDetail;
 /// Orientation
 pub type Orientation = JxlOrientation;
+/// Rendering intent
+pub type RenderingIntent = JxlRenderingIntent;
+/// Information of an extra channel
+pub type ExtraChannelInfo = JxlExtraChannelInfo;
+/// Type of an extra channel
+pub type ExtraChannelType = JxlExtraChannelType;
+/// Transfer function of a color encoding, see [`HdrInfo::transfer_function`]
+pub type TransferFunction = JxlTransferFunction;
+/// How [`JxlDecoder::decode_tiles`] handles the tiles at the right and bottom edges of
+/// images whose dimensions are not multiples of the tile size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileEdge {
+    /// Edge tiles only hold the pixels of the image, and are smaller than the other tiles
+    Clip,
+    /// Edge tiles have the size of the other tiles, the pixels outside of the image being
+    /// zero, e.g. for GPU textures of a fixed size
+    Pad,
+}
+
+/// Color transform applied to the decoded pixels, see [`JxlDecoder::color_transform_fn`]
+pub type ColorTransformFn<'a> = dyn Fn(&mut [u8], &JxlPixelFormat, Option<&JxlColorEncoding>) + 'a;
+
+/// Callback reporting the [`Progress`] of a decoding, see [`JxlDecoder::on_progress`]
+pub type ProgressFn<'a> = dyn Fn(Progress) + 'a;
 
 /// Desired Pixel Format
 #[derive(Clone, Copy, Debug)]
@@ -55,22 +99,50 @@ pub struct PixelFormat {
     /// 4. trichromatic + alpha
     ///
     /// # Default
-    /// 0, which means determined automatically from color channels and alpha bits
+    /// 0, which means determined automatically from color channels and the alpha channel
     pub num_channels: u32,
     /// Whether multi-byte data types are represented in big endian or little
-    /// endian format. This applies to `u16`, `f16`, and `f32`.
+    /// endian format. This applies to `u16`, `f16`, and `f32`, and is a no-op for `u8`.
+    ///
+    /// The samples are converted back to native values when they are returned as `Vec<T>`,
+    /// so this only changes the layout of the buffer written by `libjxl`.
     ///
     /// # Default
     /// [`Endianness::Native`]
     pub endianness: Endianness,
-    /// Align scanlines to a multiple of align bytes. _Currently not supported_
+    /// Align scanlines to a multiple of align bytes, see [`PixelFormat::row_bytes`].
+    /// The padding at the end of the rows is returned as part of the pixels
     ///
     /// # Default
     /// 0, which means requiring no alignment (which has the same effect as value 1)
-    // TODO: support custom scanlines alignment
     pub align: usize,
 }
 
+impl PixelFormat {
+    /// Number of bytes of a pixel of type `T`, i.e. `num_channels * size_of::<T>()`
+    ///
+    /// `num_channels` must be set, as it is 0 when determined automatically.
+    #[must_use]
+    pub fn bytes_per_pixel<T: PixelType>(&self) -> usize {
+        self.num_channels as usize * std::mem::size_of::<T>()
+    }
+
+    /// Number of bytes of a row of `width` pixels of type `T`, i.e.
+    /// [`bytes_per_pixel`](Self::bytes_per_pixel)` * width` rounded up to a multiple of `align`.
+    ///
+    /// The last row is not padded, so the output has
+    /// `row_bytes * (height - 1) + bytes_per_pixel * width` bytes.
+    #[must_use]
+    pub fn row_bytes<T: PixelType>(&self, width: u32) -> usize {
+        let row = self.bytes_per_pixel::<T>() * width as usize;
+        if self.align > 1 {
+            row.next_multiple_of(self.align)
+        } else {
+            row
+        }
+    }
+}
+
 impl Default for PixelFormat {
     fn default() -> Self {
         Self {
@@ -81,10 +153,69 @@ impl Default for PixelFormat {
     }
 }
 
+/// Options of a single decoding taking precedence over those of the decoder, so that methods
+/// decoding in a fixed format do not change its configuration
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Overrides {
+    /// Instead of [`JxlDecoder::pixel_format`]
+    pub(crate) pixel_format: Option<PixelFormat>,
+    /// Instead of [`JxlDecoder::output_color`], `Some(None)` keeping the color profile of the
+    /// image
+    #[allow(clippy::option_option)]
+    pub(crate) output_color: Option<Option<ColorEncoding>>,
+    /// Pass the pixels to the callback with its opaque pointer, instead of collecting them
+    pub(crate) image_out_callback: Option<(JxlImageOutCallback, *mut c_void)>,
+}
+
+/// Shared flag cancelling decodings, e.g. when the user scrolls away from an image, see
+/// [`JxlDecoder::decode_cancellable`]
+///
+/// Clones share the same flag, so a handle can be kept by the UI while the decoding runs on
+/// another thread. Giving it to [`PoolRunner::with_cancel`](crate::PoolRunner::with_cancel) as
+/// well stops the parallel jobs of the decoding in progress.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    /// Create a handle, not cancelled
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the decodings using this handle
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether [`cancel`](Self::cancel) was called since the last [`reset`](Self::reset)
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clear the flag, to decode again with this handle
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+impl AsRef<AtomicBool> for CancelHandle {
+    fn as_ref(&self) -> &AtomicBool {
+        &self.0
+    }
+}
+
 /// JPEG XL Decoder
+///
+/// # Color models
+/// Grayscale and RGB images, with or without alpha, are supported. CMYK images store the black
+/// channel as an extra channel, which cannot be converted to RGB without a color management
+/// system, so decoding them returns [`DecodeError::UnsupportedColorModel`]
 #[derive(Builder)]
 #[builder(build_fn(skip, error = "None"))]
 #[builder(setter(strip_option))]
+#[allow(clippy::struct_excessive_bools)]
 pub struct JxlDecoder<'pr, 'mm> {
     /// Opaque pointer to the underlying decoder
     #[builder(setter(skip))]
@@ -93,14 +224,38 @@ pub struct JxlDecoder<'pr, 'mm> {
     /// Override desired pixel format
     pub pixel_format: Option<PixelFormat>,
 
+    /// Override the number of color channels of the image, either 1 (grayscale) or 3 (color),
+    /// when the channel count of the output is chosen automatically, i.e. when
+    /// [`PixelFormat::num_channels`] is 0. The alpha channel is still added if present.
+    ///
+    /// # Warning
+    /// This is an escape hatch for unusual data, it does not change how the image is encoded.
+    /// Forcing 3 channels on a grayscale image replicates the gray value, while `libjxl` refuses
+    /// to convert a color image to grayscale, so forcing 1 channel on it fails with
+    /// [`DecodeError::UnsupportedChannelLayout`]. Any other value returns
+    /// [`DecodeError::UnsupportedColorChannels`].
+    ///
+    /// # Default
+    /// `None`, and the number of color channels signaled in the codestream is used
+    pub num_color_channels: Option<u32>,
+
     /// Enables or disables preserving of as-in-bitstream pixel data orientation.
-    /// If it is set to `true`, the decoder will skip applying the transformation
+    /// If it is set to `true`, the decoder will skip applying the transformation, which is
+    /// left to the caller with [`Metadata::orientation`], e.g. to do it on the GPU
     ///
     /// # Default
-    /// `false`, and the returned pixel data is re-oriented
+    /// `false`, and the returned pixel data is re-oriented for display, with the width and
+    /// height swapped for the rotations by 90 or 270 degrees
     pub skip_reorientation: Option<bool>,
     /// Enables or disables preserving of associated alpha channels.
-    /// If it is set to `true`, the colors will be unpremultiplied based on the alpha channel
+    /// If it is set to `true`, the colors will be unpremultiplied based on the alpha channel,
+    /// so callers get straight alpha whatever the image stores; leave it `false` to get the
+    /// premultiplied colors of images with associated alpha.
+    ///
+    /// # Note
+    /// `libjxl` cannot premultiply an image with straight alpha, and this is a no-op for
+    /// images without an alpha channel or with straight alpha.
+    /// Check [`BasicInfo::alpha_premultiplied`] to know how the alpha of the image is stored.
     ///
     /// # Default
     /// `false`, and return the pixel data "as is".
@@ -126,6 +281,66 @@ pub struct JxlDecoder<'pr, 'mm> {
     /// is not meant to be considered authoritative in any way. It may change from version
     /// to version
     pub desired_intensity_target: Option<f32>,
+    /// Tone map HDR images to the 255 nits of SDR when decoding them to `u8`, instead of
+    /// clipping their highlights, by setting the desired intensity target of `libjxl` to 255.
+    /// An explicit `desired_intensity_target` takes precedence, and SDR images are unchanged.
+    ///
+    /// # Default
+    /// `false`
+    pub tone_map_to_sdr: bool,
+    /// Convert the decoded pixels to this color profile, whatever profile the image was encoded
+    /// with, e.g. to always get sRGB output from Display P3 or Rec. 2020 images.
+    ///
+    /// # Note
+    /// This performs a color transform. Without a color management system, `libjxl` can only
+    /// convert XYB encoded images, i.e. lossy images not encoded with their original profile.
+    /// For other images, decoding returns [`DecodeError::GenericError`]
+    ///
+    /// # Default
+    /// `None`, and the pixels are returned in the color profile of the image
+    pub output_color: Option<ColorEncoding>,
+    /// Rendering intent of the conversion to `output_color`, i.e. how colors outside of its
+    /// gamut are mapped, e.g. when converting wide-gamut images for print.
+    ///
+    /// # Default
+    /// [`RenderingIntent::Relative`]
+    pub rendering_intent: RenderingIntent,
+    /// Apply a color transform of the application, e.g. with its own color management system,
+    /// to the pixels of [`decode`](JxlDecoder::decode),
+    /// [`decode_with`](JxlDecoder::decode_with) and [`reconstruct`](JxlDecoder::reconstruct),
+    /// instead of relying on `libjxl`.
+    ///
+    /// The callback is given the pixel buffer once the image is fully decoded, with its pixel
+    /// format and the color encoding of the pixels. The buffer is laid out as rows of `width`
+    /// pixels of `num_channels` interleaved samples of `data_type`, in `endianness`, each row
+    /// padded to a multiple of `align` bytes. The color encoding is `None` when the image is
+    /// only described by an ICC profile, which can be requested with `icc_profile`.
+    ///
+    /// # Default
+    /// `None`, and the pixels are returned as decoded
+    pub color_transform_fn: Option<&'pr ColorTransformFn<'pr>>,
+    /// Report the [`Progress`] of [`decode`](JxlDecoder::decode),
+    /// [`decode_with`](JxlDecoder::decode_with) and [`reconstruct`](JxlDecoder::reconstruct),
+    /// e.g. to show a progress bar for large images.
+    ///
+    /// The callback is called as the input is consumed, as progressive passes are reached, and
+    /// as frames are completed. The input is then given to `libjxl` in chunks, to know how much
+    /// of it is consumed.
+    ///
+    /// # Default
+    /// `None`, and no progress is reported
+    pub on_progress: Option<&'pr ProgressFn<'pr>>,
+    /// Replace the NaN and infinite samples of the decoded pixels with this value, e.g. from
+    /// corrupt or extreme HDR images, so that they do not propagate downstream.
+    /// Applied last, after `color_transform_fn`, by the same methods.
+    ///
+    /// # Note
+    /// This only applies to floating point pixel types, i.e. `f32` and `f16`, as integer
+    /// samples are always finite
+    ///
+    /// # Default
+    /// `None`, and the samples are returned as decoded
+    pub sanitize_floats: Option<f32>,
     /// Configures whether to get boxes in raw mode or in decompressed mode.
     ///
     /// # Default
@@ -133,31 +348,132 @@ pub struct JxlDecoder<'pr, 'mm> {
     pub decompress: Option<bool>,
 
     /// Configures at which progressive steps in frame decoding
+    /// [`JxlDecoder::decode_progressive`] returns a preview
     ///
     /// # Default
     /// [`ProgressiveDetail::DC`]
     pub progressive_detail: Option<JxlProgressiveDetail>,
 
-    /// Set if need ICC profile
+    /// Set if need ICC profile, returned in [`Metadata::icc_profile`] along with the color
+    /// encoding in [`Metadata::color_encoding`]
     ///
     /// # Default
     /// `false`
     pub icc_profile: bool,
 
+    /// Opt-in check that the ICC profile agrees with the color encoding signaled in the
+    /// codestream, i.e. the color space in the ICC header matches the number of color channels
+    /// and the enumerated color space. A mismatch is a sign of a malformed file, and decoding
+    /// returns [`DecodeError::InconsistentColorProfile`] instead of silently producing wrong colors.
+    ///
+    /// # Default
+    /// `false`
+    pub strict_color_profile: bool,
+
+    /// Largest ICC profile, in bytes, the decoder reads, checked before allocating it.
+    /// Larger profiles return [`DecodeError::IccProfileTooLarge`], so that a malicious file
+    /// cannot exhaust the memory with a gigantic profile.
+    /// For untrusted input, 16 MiB is recommended, i.e. `max_icc_bytes(16 << 20)`, far above
+    /// the size of real profiles, which rarely exceed a few hundred KiB.
+    ///
+    /// # Default
+    /// `usize::MAX`, i.e. no limit
+    pub max_icc_bytes: usize,
+
+    /// Expect the image to have these `(width, height)` dimensions, checked as soon as the
+    /// basic info is decoded, before any pixel.
+    /// Decoding returns [`DecodeError::UnexpectedDimensions`] for images of other sizes.
+    ///
+    /// # Default
+    /// `None`, and images of any size are decoded
+    pub expect_dimensions: Option<(u32, u32)>,
+
+    /// Require the image to be of this color type, checked as soon as the basic info is
+    /// decoded, before any pixel.
+    /// Decoding returns [`DecodeError::UnexpectedColorType`] for images of other color types.
+    ///
+    /// # Default
+    /// `None`, and images of any color type are decoded
+    pub require_color_type: Option<ColorType>,
+
+    /// Largest number of pixels, i.e. width times height, of the images to decode, checked as
+    /// soon as the basic info is decoded, before any pixel.
+    /// Decoding returns [`DecodeError::TooManyPixels`] for larger images, so that untrusted
+    /// input with huge dimensions is rejected before `libjxl` allocates its own buffers,
+    /// which [`max_buffer_bytes`](Self::max_buffer_bytes) does not limit.
+    ///
+    /// # Default
+    /// `None`, and images of any size are decoded
+    pub max_pixels: Option<u64>,
+
+    /// Stop [`JxlDecoder::decode_frames`] after this number of frames, e.g. to preview the
+    /// start of a long animation, without decoding the rest of the input.
+    /// Images with fewer frames return all of them, and 0 returns no frame but still the
+    /// basic info.
+    ///
+    /// # Default
+    /// `None`, and all the frames are decoded
+    pub max_frames: Option<usize>,
+
+    /// Reject containers with boxes unknown to the JPEG XL specification, with
+    /// [`DecodeError::UnknownBox`], instead of skipping them. Malformed boxes are always errors.
+    ///
+    /// This suits archival tools validating files, while viewers should stay lenient,
+    /// as unknown boxes are allowed by the specification.
+    ///
+    /// # Default
+    /// `false`
+    pub strict_boxes: bool,
+
     /// Set initial buffer for JPEG reconstruction
     /// Larger buffer could make reconstruction faster by doing fewer reallocations
     ///
     /// Default: 512 KiB
     pub init_jpeg_buffer: usize,
 
+    /// Largest pixel buffer, in bytes, the decoder allocates. Images whose pixels need more
+    /// return [`DecodeError::ImageTooLarge`] instead of attempting the allocation, so that
+    /// untrusted input with huge dimensions cannot exhaust the memory.
+    /// For untrusted input, 4 GiB or less is recommended, e.g. `max_buffer_bytes(4 << 30)`.
+    ///
+    /// # Default
+    /// `u64::MAX`, i.e. no limit besides the address space of the platform
+    pub max_buffer_bytes: u64,
+
     /// Set parallel runner
+    ///
+    /// # Default
+    /// `None`, indicating single thread execution. No runner is ever created implicitly,
+    /// so decoders only spawn threads when given one
     pub parallel_runner: Option<&'pr dyn JxlParallelRunner>,
 
+    /// Decode images with fewer pixels than this without the parallel runner, as setting up
+    /// the threads costs more than decoding e.g. an icon. 65536, i.e. a 256x256 image, is a
+    /// good threshold: `libjxl` splits the pixels in groups of that size, and decodes the
+    /// groups in parallel, so smaller images hardly benefit from threads.
+    ///
+    /// `libjxl` takes the runner before decoding starts, so the basic info is then read in an
+    /// extra pass over the start of the input, to know the size of the image. Only used by the
+    /// methods decoding the whole input at once, e.g. [`JxlDecoder::decode`] or
+    /// [`JxlDecoder::decode_with`], and unused without a runner.
+    ///
+    /// # Default
+    /// 0, and the runner is always used
+    pub auto_single_thread_below: u64,
+
     /// Set memory manager
     pub memory_manager: Option<&'mm dyn MemoryManager>,
 }
 
 impl<'pr, 'mm> JxlDecoderBuilder<'pr, 'mm> {
+    /// Decode to grayscale, with the alpha channel if the image has one, by setting
+    /// [`num_color_channels`](JxlDecoder::num_color_channels) to 1. Color images fail with
+    /// [`DecodeError::UnsupportedChannelLayout`], as `libjxl` does not convert them to grayscale
+    pub fn grayscale(&mut self) -> &mut Self {
+        self.num_color_channels = Some(Some(1));
+        self
+    }
+
     /// Build a [`JxlDecoder`]
     ///
     /// # Errors
@@ -178,23 +494,64 @@ impl<'pr, 'mm> JxlDecoderBuilder<'pr, 'mm> {
         Ok(JxlDecoder {
             dec,
             pixel_format: self.pixel_format.flatten(),
+            num_color_channels: self.num_color_channels.flatten(),
             skip_reorientation: self.skip_reorientation.flatten(),
             unpremul_alpha: self.unpremul_alpha.flatten(),
             render_spotcolors: self.render_spotcolors.flatten(),
             coalescing: self.coalescing.flatten(),
             desired_intensity_target: self.desired_intensity_target.flatten(),
+            tone_map_to_sdr: self.tone_map_to_sdr.unwrap_or_default(),
+            output_color: self.output_color.flatten(),
+            rendering_intent: self.rendering_intent.unwrap_or(RenderingIntent::Relative),
+            color_transform_fn: self.color_transform_fn.flatten(),
+            on_progress: self.on_progress.flatten(),
+            sanitize_floats: self.sanitize_floats.flatten(),
             decompress: self.decompress.flatten(),
             progressive_detail: self.progressive_detail.flatten(),
             icc_profile: self.icc_profile.unwrap_or_default(),
+            strict_color_profile: self.strict_color_profile.unwrap_or_default(),
+            max_icc_bytes: self.max_icc_bytes.unwrap_or(usize::MAX),
+            expect_dimensions: self.expect_dimensions.flatten(),
+            require_color_type: self.require_color_type.flatten(),
+            max_pixels: self.max_pixels.flatten(),
+            max_frames: self.max_frames.flatten(),
+            strict_boxes: self.strict_boxes.unwrap_or_default(),
             init_jpeg_buffer: self.init_jpeg_buffer.unwrap_or(512 * 1024),
+            max_buffer_bytes: self.max_buffer_bytes.unwrap_or(u64::MAX),
             parallel_runner: self.parallel_runner.flatten(),
+            auto_single_thread_below: self.auto_single_thread_below.unwrap_or_default(),
             memory_manager: mm,
         })
     }
 }
 
 impl<'pr, 'mm> JxlDecoder<'pr, 'mm> {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn decode_internal(
+        &self,
+        data: &[u8],
+        data_type: Option<JxlDataType>,
+        with_icc_profile: bool,
+        reconstruct_jpeg_buffer: Option<&mut Vec<u8>>,
+        format: *mut JxlPixelFormat,
+        pixels: &mut Vec<u8>,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<Metadata, DecodeError> {
+        self.decode_internal_with(
+            data,
+            data_type,
+            with_icc_profile,
+            reconstruct_jpeg_buffer,
+            format,
+            pixels,
+            cancel,
+            Overrides::default(),
+        )
+    }
+
+    /// [`decode_internal`](Self::decode_internal) with some options of the decoder overridden
+    #[allow(clippy::too_many_arguments, clippy::too_many_lines)]
+    pub(crate) fn decode_internal_with(
         &self,
         data: &[u8],
         data_type: Option<JxlDataType>,
@@ -202,6 +559,8 @@ impl<'pr, 'mm> JxlDecoder<'pr, 'mm> {
         mut reconstruct_jpeg_buffer: Option<&mut Vec<u8>>,
         format: *mut JxlPixelFormat,
         pixels: &mut Vec<u8>,
+        cancel: Option<&AtomicBool>,
+        overrides: Overrides,
     ) -> Result<Metadata, DecodeError> {
         let Some(sig) = check_valid_signature(data) else {
             return Err(DecodeError::InvalidInput);
@@ -212,38 +571,92 @@ impl<'pr, 'mm> JxlDecoder<'pr, 'mm> {
 
         let mut basic_info = MaybeUninit::uninit();
         let mut icc = if with_icc_profile { Some(vec![]) } else { None };
+        let output_color = overrides.output_color.unwrap_or(self.output_color);
 
-        self.setup_decoder(with_icc_profile, reconstruct_jpeg_buffer.is_some())?;
+        let events = {
+            use JxlDecoderStatus::{BasicInfo, ColorEncoding, FullImage, JpegReconstruction};
 
-        let next_in = data.as_ptr();
-        let avail_in = std::mem::size_of_val(data) as _;
+            let mut events = BasicInfo as i32 | FullImage as i32;
+            if self.on_progress.is_some() {
+                events |= JxlDecoderStatus::FrameProgression as i32;
+            }
+            if with_icc_profile
+                || self.strict_color_profile
+                || output_color.is_some()
+                || self.color_transform_fn.is_some()
+            {
+                events |= ColorEncoding as i32;
+            }
+            if reconstruct_jpeg_buffer.is_some() {
+                events |= JpegReconstruction as i32;
+            }
 
-        check_dec_status(unsafe { JxlDecoderSetInput(self.dec, next_in, avail_in) })?;
-        unsafe { JxlDecoderCloseInput(self.dec) };
+            events
+        };
+        let runner = self.runner_for(data);
+        self.setup_decoder_with(events, runner)?;
+
+        // The input is given in chunks to report the progress, as the consumed input is only
+        // known when the decoder asks for more
+        let mut progress = Progress {
+            total_bytes: data.len(),
+            ..Progress::default()
+        };
+        let mut end = if self.on_progress.is_some() {
+            data.len().min(PROGRESS_CHUNK_SIZE)
+        } else {
+            data.len()
+        };
+        check_dec_status(unsafe { JxlDecoderSetInput(self.dec, data.as_ptr(), end) })?;
+        if end == data.len() {
+            unsafe { JxlDecoderCloseInput(self.dec) };
+        }
 
+        let mut has_alpha_channel = false;
+        let mut encoding = None;
         let mut status;
         loop {
             use JxlDecoderStatus as s;
 
+            if cancel.is_some_and(|c| c.load(Ordering::Relaxed)) {
+                unsafe { JxlDecoderReset(self.dec) };
+                return Err(DecodeError::Cancelled);
+            }
+
             status = unsafe { JxlDecoderProcessInput(self.dec) };
 
             match status {
-                s::NeedMoreInput | s::Error => return Err(DecodeError::GenericError),
-
                 // Get the basic info
                 s::BasicInfo => {
                     check_dec_status(unsafe {
                         JxlDecoderGetBasicInfo(self.dec, basic_info.as_mut_ptr())
                     })?;
 
-                    if let Some(pr) = self.parallel_runner {
+                    self.check_basic_info(unsafe { &*basic_info.as_ptr() })?;
+                    self.tone_map(
+                        unsafe { &*basic_info.as_ptr() },
+                        data_type,
+                        overrides.pixel_format.or(self.pixel_format),
+                    )?;
+                    has_alpha_channel = self
+                        .alpha_channel(unsafe { &*basic_info.as_ptr() })?
+                        .is_some();
+
+                    if let Some(pr) = runner {
                         pr.callback_basic_info(unsafe { &*basic_info.as_ptr() });
                     }
                 }
 
                 // Get color encoding
                 s::ColorEncoding => {
-                    self.get_icc_profile(unsafe { icc.as_mut().unwrap_unchecked() })?;
+                    self.color_encoding(
+                        unsafe { &*basic_info.as_ptr() },
+                        icc.as_mut(),
+                        output_color,
+                    )?;
+                    if with_icc_profile || self.color_transform_fn.is_some() {
+                        encoding = self.encoded_profile();
+                    }
                 }
 
                 // Get JPEG reconstruction buffer
@@ -272,11 +685,50 @@ impl<'pr, 'mm> JxlDecoder<'pr, 'mm> {
 
                 // Get the output buffer
                 s::NeedImageOutBuffer => {
-                    self.output(unsafe { &*basic_info.as_ptr() }, data_type, format, pixels)?;
+                    let info = unsafe { &*basic_info.as_ptr() };
+                    let pixel_format = overrides.pixel_format.or(self.pixel_format);
+                    if let Some((callback, opaque)) = overrides.image_out_callback {
+                        let pixel_format = self.output_format_as(info, data_type, pixel_format)?;
+                        check_dec_status(unsafe {
+                            JxlDecoderSetImageOutCallback(
+                                self.dec,
+                                std::ptr::from_ref(&pixel_format),
+                                callback,
+                                opaque,
+                            )
+                        })?;
+                        unsafe { *format = pixel_format };
+                    } else {
+                        self.output(info, data_type, pixel_format, format, pixels)?;
+                    }
                 }
 
-                s::FullImage => continue,
+                s::FrameProgression => {
+                    progress.pass += 1;
+                    self.report_progress(progress);
+                }
+                s::FullImage => {
+                    progress.pass = 0;
+                    progress.frames_completed += 1;
+                    self.report_progress(progress);
+                }
+                s::NeedMoreInput if end < data.len() => {
+                    let start = end - unsafe { JxlDecoderReleaseInput(self.dec) };
+                    progress.bytes_consumed = start;
+                    self.report_progress(progress);
+
+                    end = data.len().min(end + PROGRESS_CHUNK_SIZE);
+                    check_dec_status(unsafe {
+                        JxlDecoderSetInput(self.dec, data[start..].as_ptr(), end - start)
+                    })?;
+                    if end == data.len() {
+                        unsafe { JxlDecoderCloseInput(self.dec) };
+                    }
+                }
                 s::Success => {
+                    progress.bytes_consumed = data.len();
+                    self.report_progress(progress);
+
                     if let Some(buf) = reconstruct_jpeg_buffer.as_mut() {
                         let remaining = unsafe { JxlDecoderReleaseJPEGBuffer(self.dec) };
 
@@ -284,6 +736,14 @@ impl<'pr, 'mm> JxlDecoder<'pr, 'mm> {
                         buf.shrink_to_fit();
                     }
 
+                    // The pixel format is set along with the buffer
+                    if let (Some(transform), false) = (self.color_transform_fn, pixels.is_empty()) {
+                        transform(pixels, unsafe { &*format }, encoding.as_ref());
+                    }
+                    if let (Some(fill), false) = (self.sanitize_floats, pixels.is_empty()) {
+                        sanitize_floats(pixels, unsafe { &*format }, fill);
+                    }
+
                     unsafe { JxlDecoderReset(self.dec) };
 
                     let info = unsafe { basic_info.assume_init() };
@@ -294,40 +754,53 @@ impl<'pr, 'mm> JxlDecoder<'pr, 'mm> {
                         min_nits: info.min_nits,
                         orientation: info.orientation,
                         num_color_channels: info.num_color_channels,
-                        has_alpha_channel: info.alpha_bits > 0,
+                        has_alpha_channel,
                         intrinsic_width: info.intrinsic_xsize,
                         intrinsic_height: info.intrinsic_ysize,
+                        bits_per_sample: info.bits_per_sample,
+                        exponent_bits_per_sample: info.exponent_bits_per_sample,
+                        uses_original_profile: info.uses_original_profile == JxlBool::True,
                         icc_profile: icc,
+                        color_encoding: encoding.filter(|_| with_icc_profile),
                     });
                 }
-                s::NeedPreviewOutBuffer => todo!(),
-                s::BoxNeedMoreOutput => todo!(),
-                s::PreviewImage => todo!(),
-                s::Frame => todo!(),
-                s::Box => todo!(),
-                s::FrameProgression => todo!(),
+                s::Box => self.check_box()?,
+                // The parallel runner fails its jobs when cancelled
+                s::Error if cancel.is_some_and(|c| c.load(Ordering::Relaxed)) => {
+                    unsafe { JxlDecoderReset(self.dec) };
+                    return Err(DecodeError::Cancelled);
+                }
+                s::NeedMoreInput | s::Error => {
+                    return Err(self.truncation(data).unwrap_or_else(|| from_status(status)));
+                }
+                status => return Err(from_status(status)),
             }
         }
     }
 
-    fn setup_decoder(&self, icc: bool, reconstruct_jpeg: bool) -> Result<(), DecodeError> {
-        if let Some(runner) = self.parallel_runner {
+    fn setup_decoder(&self, events: i32) -> Result<(), DecodeError> {
+        self.setup_decoder_with(events, self.parallel_runner)
+    }
+
+    /// Set up the decoder as [`setup_decoder`](Self::setup_decoder), with `runner` instead of
+    /// the parallel runner of the options
+    fn setup_decoder_with(
+        &self,
+        events: i32,
+        runner: Option<&dyn JxlParallelRunner>,
+    ) -> Result<(), DecodeError> {
+        // Start from a clean state, even if the previous decoding failed midway
+        unsafe { JxlDecoderReset(self.dec) };
+
+        if let Some(runner) = runner {
             check_dec_status(unsafe {
                 JxlDecoderSetParallelRunner(self.dec, runner.runner(), runner.as_opaque_ptr())
             })?;
         }
 
-        let events = {
-            use JxlDecoderStatus::{BasicInfo, ColorEncoding, FullImage, JpegReconstruction};
-
-            let mut events = BasicInfo as i32 | FullImage as i32;
-            if icc {
-                events |= ColorEncoding as i32;
-            }
-            if reconstruct_jpeg {
-                events |= JpegReconstruction as i32;
-            }
-
+        let events = if self.strict_boxes {
+            events | JxlDecoderStatus::Box as i32
+        } else {
             events
         };
         check_dec_status(unsafe { JxlDecoderSubscribeEvents(self.dec, events) })?;
@@ -347,121 +820,1962 @@ impl<'pr, 'mm> JxlDecoder<'pr, 'mm> {
         if let Some(val) = self.desired_intensity_target {
             check_dec_status(unsafe { JxlDecoderSetDesiredIntensityTarget(self.dec, val) })?;
         }
+        if let Some(val) = self.progressive_detail {
+            check_dec_status(unsafe { JxlDecoderSetProgressiveDetail(self.dec, val) })?;
+        }
 
         Ok(())
     }
 
-    fn get_icc_profile(&self, icc_profile: &mut Vec<u8>) -> Result<(), DecodeError> {
+    fn get_icc_profile(
+        &self,
+        target: JxlColorProfileTarget,
+        icc_profile: &mut Vec<u8>,
+    ) -> Result<(), DecodeError> {
         let mut icc_size = 0;
-        check_dec_status(unsafe {
-            JxlDecoderGetICCProfileSize(self.dec, JxlColorProfileTarget::Data, &mut icc_size)
-        })?;
+        check_dec_status(unsafe { JxlDecoderGetICCProfileSize(self.dec, target, &mut icc_size) })?;
+        if icc_size > self.max_icc_bytes {
+            return Err(DecodeError::IccProfileTooLarge {
+                size: icc_size,
+                max: self.max_icc_bytes,
+            });
+        }
         icc_profile.resize(icc_size, 0);
 
         check_dec_status(unsafe {
-            JxlDecoderGetColorAsICCProfile(
-                self.dec,
-                JxlColorProfileTarget::Data,
-                icc_profile.as_mut_ptr(),
-                icc_size,
-            )
+            JxlDecoderGetColorAsICCProfile(self.dec, target, icc_profile.as_mut_ptr(), icc_size)
         })?;
 
         Ok(())
     }
 
-    fn output(
+    fn check_color_profile(&self, info: &BasicInfo) -> Result<(), DecodeError> {
+        let mut icc = vec![];
+        self.get_icc_profile(JxlColorProfileTarget::Original, &mut icc)?;
+
+        let mut encoding = MaybeUninit::uninit();
+        let color_space = (unsafe {
+            JxlDecoderGetColorAsEncodedProfile(
+                self.dec,
+                JxlColorProfileTarget::Original,
+                encoding.as_mut_ptr(),
+            )
+        } == JxlDecoderStatus::Success)
+            .then(|| unsafe { encoding.assume_init() }.color_space);
+
+        if is_consistent_color_profile(&icc, info.num_color_channels, color_space) {
+            Ok(())
+        } else {
+            Err(DecodeError::InconsistentColorProfile)
+        }
+    }
+
+    /// The parallel runner to decode `data` with, `None` for images smaller than
+    /// `auto_single_thread_below`
+    fn runner_for(&self, data: &[u8]) -> Option<&'pr dyn JxlParallelRunner> {
+        let runner = self.parallel_runner?;
+        if self.auto_single_thread_below == 0 {
+            return Some(runner);
+        }
+
+        // Invalid input is reported when decoding it
+        let small = self.info(data).is_ok_and(|info| {
+            u64::from(info.xsize) * u64::from(info.ysize) < self.auto_single_thread_below
+        });
+        (!small).then_some(runner)
+    }
+
+    /// Read the basic info of the image only, and pass it to `f` while the decoder can still
+    /// be queried for the extra channels
+    pub(crate) fn with_basic_info<R>(
         &self,
-        info: &BasicInfo,
-        data_type: Option<JxlDataType>,
-        format: *mut JxlPixelFormat,
-        pixels: &mut Vec<u8>,
-    ) -> Result<(), DecodeError> {
-        let data_type = match data_type {
-            Some(v) => v,
-            None => match (info.bits_per_sample, info.exponent_bits_per_sample) {
-                (x, 0) if x <= 8 => JxlDataType::Uint8,
-                (x, 0) if x <= 16 => JxlDataType::Uint16,
-                (16, _) => JxlDataType::Float16,
-                (32, _) => JxlDataType::Float,
-                (x, _) => return Err(DecodeError::UnsupportedBitWidth(x)),
-            },
-        };
+        data: &[u8],
+        f: impl FnOnce(&BasicInfo) -> Result<R, DecodeError>,
+    ) -> Result<R, DecodeError> {
+        if check_valid_signature(data) != Some(true) {
+            return Err(DecodeError::InvalidInput);
+        }
 
-        let f = self.pixel_format.unwrap_or_default();
-        let pixel_format = JxlPixelFormat {
-            num_channels: if f.num_channels == 0 {
-                info.num_color_channels + u32::from(info.alpha_bits > 0)
-            } else {
-                f.num_channels
-            },
-            data_type,
-            endianness: f.endianness,
-            align: 0, // TODO: support align
+        self.setup_decoder(JxlDecoderStatus::BasicInfo as i32)?;
+        check_dec_status(unsafe { JxlDecoderSetInput(self.dec, data.as_ptr(), data.len()) })?;
+        unsafe { JxlDecoderCloseInput(self.dec) };
+
+        let res = match unsafe { JxlDecoderProcessInput(self.dec) } {
+            JxlDecoderStatus::BasicInfo => {
+                let mut info = MaybeUninit::uninit();
+                check_dec_status(unsafe { JxlDecoderGetBasicInfo(self.dec, info.as_mut_ptr()) })
+                    .and_then(|()| f(unsafe { info.assume_init_ref() }))
+            }
+            _ => Err(DecodeError::GenericError),
         };
+        unsafe { JxlDecoderReset(self.dec) };
 
-        let mut size = 0;
-        check_dec_status(unsafe {
-            JxlDecoderImageOutBufferSize(self.dec, &pixel_format, &mut size)
-        })?;
-        pixels.resize(size, 0);
+        res
+    }
 
-        check_dec_status(unsafe {
-            JxlDecoderSetImageOutBuffer(self.dec, &pixel_format, pixels.as_mut_ptr().cast(), size)
-        })?;
+    /// Tone map HDR images decoded to `u8` in `pixel_format` to SDR,
+    /// see [`JxlDecoder::tone_map_to_sdr`]
+    fn tone_map(
+        &self,
+        info: &BasicInfo,
+        data_type: Option<JxlDataType>,
+        pixel_format: Option<PixelFormat>,
+    ) -> Result<(), DecodeError> {
+        if !self.tone_map_to_sdr
+            || self.desired_intensity_target.is_some()
+            || info.intensity_target <= 255.0
+            || self
+                .output_format_as(info, data_type, pixel_format)?
+                .data_type
+                != JxlDataType::Uint8
+        {
+            return Ok(());
+        }
 
-        unsafe { *format = pixel_format };
-        Ok(())
+        check_dec_status(unsafe { JxlDecoderSetDesiredIntensityTarget(self.dec, 255.0) })
     }
 
-    /// Decode a JPEG XL image
-    ///
-    /// # Errors
-    /// Return a [`DecodeError`] when internal decoder fails
-    pub fn decode(&self, data: &[u8]) -> Result<(Metadata, Pixels), DecodeError> {
-        let mut buffer = vec![];
-        let mut pixel_format = MaybeUninit::uninit();
-        let metadata = self.decode_internal(
-            data,
-            None,
-            self.icc_profile,
-            None,
-            pixel_format.as_mut_ptr(),
-            &mut buffer,
-        )?;
-        Ok((
-            metadata,
-            Pixels::new(buffer, unsafe { &pixel_format.assume_init() }),
-        ))
+    /// Call [`on_progress`](Self::on_progress) if it is set
+    fn report_progress(&self, progress: Progress) {
+        if let Some(on_progress) = self.on_progress {
+            on_progress(progress);
+        }
     }
 
-    /// Decode a JPEG XL image to a specific pixel type
-    ///
-    /// # Errors
-    /// Return a [`DecodeError`] when internal decoder fails
-    pub fn decode_with<T: PixelType>(
-        &self,
-        data: &[u8],
-    ) -> Result<(Metadata, Vec<T>), DecodeError> {
-        let mut buffer = vec![];
-        let mut pixel_format = MaybeUninit::uninit();
-        let metadata = self.decode_internal(
-            data,
-            Some(T::pixel_type()),
-            self.icc_profile,
-            None,
-            pixel_format.as_mut_ptr(),
-            &mut buffer,
-        )?;
+    /// Tell why an input failed to decode if it is cut short, see
+    /// [`DecodeError::CodestreamTruncated`] and [`DecodeError::ContainerIncomplete`]
+    fn truncation(&self, data: &[u8]) -> Option<DecodeError> {
+        const CONTAINER_SIGNATURE: [u8; 8] = [0, 0, 0, 0x0c, b'J', b'X', b'L', b' '];
 
-        // Safety: type `T` is set by user and provide to the decoder to determine output data type
-        let buf = unsafe {
-            let pixel_format = pixel_format.assume_init();
-            debug_assert!(T::pixel_type() == pixel_format.data_type);
-            T::convert(&buffer, &pixel_format)
-        };
+        if data.starts_with(&CONTAINER_SIGNATURE) {
+            if let Some(err) = check_container(data) {
+                return Some(err);
+            }
+        }
 
-        Ok((metadata, buf))
+        // Without closing the input, the decoder asks for more of a truncated codestream.
+        // Frames are skipped, as no full image is subscribed to
+        let truncated = self.setup_decoder(JxlDecoderStatus::Frame as i32).is_ok()
+            && check_dec_status(unsafe { JxlDecoderSetInput(self.dec, data.as_ptr(), data.len()) })
+                .is_ok()
+            && loop {
+                match unsafe { JxlDecoderProcessInput(self.dec) } {
+                    JxlDecoderStatus::Frame => {}
+                    status => break status == JxlDecoderStatus::NeedMoreInput,
+                }
+            };
+        unsafe { JxlDecoderReset(self.dec) };
+
+        truncated.then_some(DecodeError::CodestreamTruncated)
+    }
+
+    /// Index of the alpha channel among the extra channels, identified by its type, as it is
+    /// not necessarily the first one
+    fn alpha_channel(&self, info: &BasicInfo) -> Result<Option<u32>, DecodeError> {
+        for i in 0..info.num_extra_channels {
+            let mut channel = MaybeUninit::uninit();
+            check_dec_status(unsafe {
+                JxlDecoderGetExtraChannelInfo(self.dec, i as usize, channel.as_mut_ptr())
+            })?;
+
+            if unsafe { channel.assume_init() }.type_ == JxlExtraChannelType::Alpha {
+                return Ok(Some(i));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Check the basic info against the expectations of the caller, and reject color models
+    /// whose pixels could not be returned correctly, i.e. CMYK
+    fn check_basic_info(&self, info: &BasicInfo) -> Result<(), DecodeError> {
+        if let Some(expected) = self.expect_dimensions {
+            let actual = (info.xsize, info.ysize);
+            if actual != expected {
+                return Err(DecodeError::UnexpectedDimensions { expected, actual });
+            }
+        }
+
+        if let Some(expected) = self.require_color_type {
+            let actual = ColorType::from_basic_info(info);
+            if actual != expected {
+                return Err(DecodeError::UnexpectedColorType { expected, actual });
+            }
+        }
+
+        if let Some(max) = self.max_pixels {
+            let pixels = u64::from(info.xsize) * u64::from(info.ysize);
+            if pixels > max {
+                return Err(DecodeError::TooManyPixels { pixels, max });
+            }
+        }
+
+        for i in 0..info.num_extra_channels {
+            let mut channel = MaybeUninit::uninit();
+            check_dec_status(unsafe {
+                JxlDecoderGetExtraChannelInfo(self.dec, i as usize, channel.as_mut_ptr())
+            })?;
+
+            if unsafe { channel.assume_init() }.type_ == JxlExtraChannelType::Black {
+                return Err(DecodeError::UnsupportedColorModel);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Info and name of the extra channel at `index`, once the basic info is decoded
+    pub(crate) fn extra_channel(&self, index: u32) -> Result<ExtraChannel, DecodeError> {
+        let mut info = MaybeUninit::uninit();
+        check_dec_status(unsafe {
+            JxlDecoderGetExtraChannelInfo(self.dec, index as usize, info.as_mut_ptr())
+        })?;
+        let info = unsafe { info.assume_init() };
+
+        let name = if info.name_length > 0 {
+            // Including the terminating null character
+            let mut name = vec![0u8; info.name_length as usize + 1];
+            check_dec_status(unsafe {
+                JxlDecoderGetExtraChannelName(
+                    self.dec,
+                    index as usize,
+                    name.as_mut_ptr().cast(),
+                    name.len(),
+                )
+            })?;
+            name.pop();
+            Some(String::from_utf8_lossy(&name).into_owned())
+        } else {
+            None
+        };
+
+        Ok(ExtraChannel { info, name })
+    }
+
+    /// Reject boxes unknown to the JPEG XL specification
+    #[allow(clippy::cast_sign_loss)]
+    fn check_box(&self) -> Result<(), DecodeError> {
+        let mut box_type = JxlBoxType::default();
+        check_dec_status(unsafe { JxlDecoderGetBoxType(self.dec, &mut box_type, JxlBool::False) })?;
+
+        let box_type = box_type.map(|c| c as u8);
+        if KNOWN_BOXES.contains(&&box_type) {
+            Ok(())
+        } else {
+            Err(DecodeError::UnknownBox(box_type))
+        }
+    }
+
+    fn color_encoding(
+        &self,
+        info: &BasicInfo,
+        icc: Option<&mut Vec<u8>>,
+        output_color: Option<ColorEncoding>,
+    ) -> Result<(), DecodeError> {
+        if self.strict_color_profile {
+            self.check_color_profile(info)?;
+        }
+        if let Some(color) = output_color {
+            let mut encoding = JxlColorEncoding::from(color);
+            encoding.rendering_intent = self.rendering_intent;
+            check_dec_status(unsafe {
+                JxlDecoderSetPreferredColorProfile(self.dec, std::ptr::from_ref(&encoding))
+            })?;
+        }
+        if let Some(icc) = icc {
+            self.get_icc_profile(JxlColorProfileTarget::Data, icc)?;
+        }
+
+        Ok(())
+    }
+
+    /// Color encoding of the pixels, or `None` if it is only described by an ICC profile
+    fn encoded_profile(&self) -> Option<JxlColorEncoding> {
+        let mut encoding = MaybeUninit::uninit();
+        check_dec_status(unsafe {
+            JxlDecoderGetColorAsEncodedProfile(
+                self.dec,
+                JxlColorProfileTarget::Data,
+                encoding.as_mut_ptr(),
+            )
+        })
+        .ok()
+        .map(|()| unsafe { encoding.assume_init() })
+    }
+
+    /// Pixel format of the output, from the options and the basic info of the image
+    pub(crate) fn output_format(
+        &self,
+        info: &BasicInfo,
+        data_type: Option<JxlDataType>,
+    ) -> Result<JxlPixelFormat, DecodeError> {
+        self.output_format_as(info, data_type, self.pixel_format)
+    }
+
+    /// Pixel format of the output, from `pixel_format` instead of
+    /// [`pixel_format`](Self::pixel_format)
+    fn output_format_as(
+        &self,
+        info: &BasicInfo,
+        data_type: Option<JxlDataType>,
+        pixel_format: Option<PixelFormat>,
+    ) -> Result<JxlPixelFormat, DecodeError> {
+        let data_type = match data_type {
+            Some(v) => v,
+            None => match (info.bits_per_sample, info.exponent_bits_per_sample) {
+                (x, 0) if x <= 8 => JxlDataType::Uint8,
+                (x, 0) if x <= 16 => JxlDataType::Uint16,
+                (16, _) => JxlDataType::Float16,
+                (32, _) => JxlDataType::Float,
+                (x, _) => return Err(DecodeError::UnsupportedBitWidth(x)),
+            },
+        };
+
+        let num_color_channels = match self.num_color_channels {
+            None => info.num_color_channels,
+            Some(n @ (1 | 3)) => n,
+            Some(n) => return Err(DecodeError::UnsupportedColorChannels(n)),
+        };
+
+        let f = pixel_format.unwrap_or_default();
+        let num_channels = if f.num_channels == 0 {
+            num_color_channels + u32::from(self.alpha_channel(info)?.is_some())
+        } else {
+            f.num_channels
+        };
+        // `libjxl` does not convert color to grayscale
+        if num_channels > 4 || (num_channels < 3 && info.num_color_channels == 3) {
+            return Err(DecodeError::UnsupportedChannelLayout {
+                requested: num_channels,
+                color_channels: info.num_color_channels,
+            });
+        }
+
+        let pixel_format = JxlPixelFormat {
+            num_channels,
+            data_type,
+            endianness: f.endianness,
+            align: f.align,
+        };
+
+        Ok(pixel_format)
+    }
+
+    fn output(
+        &self,
+        info: &BasicInfo,
+        data_type: Option<JxlDataType>,
+        pixel_format: Option<PixelFormat>,
+        format: *mut JxlPixelFormat,
+        pixels: &mut Vec<u8>,
+    ) -> Result<(), DecodeError> {
+        let pixel_format = self.output_format_as(info, data_type, pixel_format)?;
+
+        let mut size = 0;
+        check_dec_status(unsafe {
+            JxlDecoderImageOutBufferSize(self.dec, &pixel_format, &mut size)
+        })?;
+        let size = self.check_buffer_size(size as u64)?;
+        pixels.resize(size, 0);
+
+        check_dec_status(unsafe {
+            JxlDecoderSetImageOutBuffer(self.dec, &pixel_format, pixels.as_mut_ptr().cast(), size)
+        })?;
+
+        unsafe { *format = pixel_format };
+        Ok(())
+    }
+
+    /// Check that a pixel buffer of `requested` bytes is within `max_buffer_bytes`, and can be
+    /// addressed on this platform
+    fn check_buffer_size(&self, requested: u64) -> Result<usize, DecodeError> {
+        let too_large = DecodeError::ImageTooLarge {
+            requested,
+            max: self.max_buffer_bytes,
+        };
+        if requested > self.max_buffer_bytes {
+            return Err(too_large);
+        }
+        usize::try_from(requested).map_err(|_| too_large)
+    }
+
+    /// Decode a JPEG XL image
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn decode(&self, data: &[u8]) -> Result<(Metadata, Pixels), DecodeError> {
+        let mut buffer = vec![];
+        let mut pixel_format = MaybeUninit::uninit();
+        let metadata = self.decode_internal(
+            data,
+            None,
+            self.icc_profile,
+            None,
+            pixel_format.as_mut_ptr(),
+            &mut buffer,
+            None,
+        )?;
+        Ok((
+            metadata,
+            Pixels::new(buffer, unsafe { &pixel_format.assume_init() }),
+        ))
+    }
+
+    /// Decode a JPEG XL image, checking `cancel` between every step of the decoder.
+    ///
+    /// When `cancel` is set, the decoding stops at the next event emitted by `libjxl`, and the
+    /// decoder is reset, so it can be used again. A [`CancelHandle`] can be given with
+    /// [`as_ref`](AsRef::as_ref), and is also checked by the jobs of a
+    /// [`PoolRunner`](crate::PoolRunner) using it.
+    ///
+    /// # Errors
+    /// Return [`DecodeError::Cancelled`] if the decoding is cancelled,
+    /// or a [`DecodeError`] when internal decoder fails
+    pub fn decode_cancellable(
+        &self,
+        data: &[u8],
+        cancel: &AtomicBool,
+    ) -> Result<(Metadata, Pixels), DecodeError> {
+        let mut buffer = vec![];
+        let mut pixel_format = MaybeUninit::uninit();
+        let metadata = self.decode_internal(
+            data,
+            None,
+            self.icc_profile,
+            None,
+            pixel_format.as_mut_ptr(),
+            &mut buffer,
+            Some(cancel),
+        )?;
+        Ok((
+            metadata,
+            Pixels::new(buffer, unsafe { &pixel_format.assume_init() }),
+        ))
+    }
+
+    /// Decode a JPEG XL image to a specific pixel type
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn decode_with<T: PixelType>(
+        &self,
+        data: &[u8],
+    ) -> Result<(Metadata, Vec<T>), DecodeError> {
+        let mut buffer = vec![];
+        let mut pixel_format = MaybeUninit::uninit();
+        let metadata = self.decode_internal(
+            data,
+            Some(T::pixel_type()),
+            self.icc_profile,
+            None,
+            pixel_format.as_mut_ptr(),
+            &mut buffer,
+            None,
+        )?;
+
+        // Safety: type `T` is set by user and provide to the decoder to determine output data type
+        let buf = unsafe {
+            let pixel_format = pixel_format.assume_init();
+            debug_assert!(T::pixel_type() == pixel_format.data_type);
+            T::convert(&buffer, &pixel_format)
+        };
+
+        Ok((metadata, buf))
+    }
+
+    /// Decode a JPEG XL image to a specific pixel type, as a [`PixelBuffer`] giving access
+    /// to both the samples and their bytes
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn decode_buffer<T: PixelType>(
+        &self,
+        data: &[u8],
+    ) -> Result<(Metadata, PixelBuffer<T>), DecodeError> {
+        let (metadata, pixels) = self.decode_with::<T>(data)?;
+        Ok((metadata, PixelBuffer::new(pixels)))
+    }
+
+    /// Decode a JPEG XL image to `(width, height, pixels)` with 8-bit RGBA pixels, whatever
+    /// the [`pixel_format`](Self::pixel_format), e.g. to fill a canvas `ImageData` on the web.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn decode_rgba8(&self, data: &[u8]) -> Result<(u32, u32, Vec<u8>), DecodeError> {
+        let mut buffer = vec![];
+        let mut pixel_format = MaybeUninit::uninit();
+        let overrides = Overrides {
+            pixel_format: Some(PixelFormat {
+                num_channels: 4,
+                endianness: Endianness::Native,
+                align: 0,
+            }),
+            ..Overrides::default()
+        };
+        let metadata = self.decode_internal_with(
+            data,
+            Some(JxlDataType::Uint8),
+            self.icc_profile,
+            None,
+            pixel_format.as_mut_ptr(),
+            &mut buffer,
+            None,
+            overrides,
+        )?;
+
+        // Unpadded, and one byte per sample
+        Ok((metadata.width, metadata.height, buffer))
+    }
+
+    /// Decode a JPEG XL image to a specific pixel type, as a [`DecodedImage`] carrying the
+    /// layout of the pixels, including the row padding required by [`PixelFormat::align`].
+    ///
+    /// `align` should be a multiple of `size_of::<T>()` for the rows to start on a sample.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn decode_image<T: PixelType>(
+        &self,
+        data: &[u8],
+    ) -> Result<(Metadata, DecodedImage<T>), DecodeError> {
+        let mut buffer = vec![];
+        let mut pixel_format = MaybeUninit::uninit();
+        let metadata = self.decode_internal(
+            data,
+            Some(T::pixel_type()),
+            self.icc_profile,
+            None,
+            pixel_format.as_mut_ptr(),
+            &mut buffer,
+            None,
+        )?;
+
+        // Safety: type `T` is set by user and provide to the decoder to determine output data type
+        let pixel_format = unsafe { pixel_format.assume_init() };
+        debug_assert!(T::pixel_type() == pixel_format.data_type);
+        let pixels = T::convert(&buffer, &pixel_format);
+
+        let stride = PixelFormat {
+            num_channels: pixel_format.num_channels,
+            endianness: Endianness::Native,
+            align: pixel_format.align,
+        }
+        .row_bytes::<T>(metadata.width)
+            / std::mem::size_of::<T>();
+
+        let image = DecodedImage {
+            width: metadata.width,
+            height: metadata.height,
+            num_channels: pixel_format.num_channels,
+            stride,
+            data: pixels,
+        };
+        Ok((metadata, image))
+    }
+
+    /// Decode a JPEG XL image to a specific pixel type, along with the `(min, max)` range of
+    /// every channel of the pixels, e.g. to auto-contrast or normalize them for display.
+    ///
+    /// The ranges are computed in a single scan over the decoded samples, in the order of the
+    /// channels of a pixel, including alpha. `NaN` samples are skipped, as is the padding of the
+    /// rows set by [`PixelFormat::align`], which is still part of the returned pixels like with
+    /// [`decode_with`](Self::decode_with).
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    #[allow(clippy::type_complexity)]
+    pub fn decode_with_range<T: PixelType + PartialOrd + Copy>(
+        &self,
+        data: &[u8],
+    ) -> Result<(Metadata, Vec<T>, Vec<(T, T)>), DecodeError> {
+        let mut buffer = vec![];
+        let mut pixel_format = MaybeUninit::uninit();
+        let metadata = self.decode_internal(
+            data,
+            Some(T::pixel_type()),
+            self.icc_profile,
+            None,
+            pixel_format.as_mut_ptr(),
+            &mut buffer,
+            None,
+        )?;
+
+        let pixel_format = unsafe { pixel_format.assume_init() };
+        let buf = T::convert(&buffer, &pixel_format);
+
+        let num_channels = pixel_format.num_channels as usize;
+        let mut ranges: Vec<Option<(T, T)>> = vec![None; num_channels];
+        let samples = unpadded_rows(&buf, &pixel_format, metadata.width);
+        for pixel in samples.flat_map(|row| row.chunks_exact(num_channels)) {
+            for (range, &v) in ranges.iter_mut().zip(pixel) {
+                // Skip NaN
+                if v.partial_cmp(&v).is_none() {
+                    continue;
+                }
+                *range = Some(match *range {
+                    Some((min, max)) => {
+                        (if v < min { v } else { min }, if v > max { v } else { max })
+                    }
+                    None => (v, v),
+                });
+            }
+        }
+
+        // A channel has no range only if all its samples are NaN
+        let ranges = ranges
+            .into_iter()
+            .enumerate()
+            .map(|(c, range)| range.unwrap_or((buf[c], buf[c])))
+            .collect();
+
+        Ok((metadata, buf, ranges))
+    }
+
+    /// Decode a JPEG XL image to a specific pixel type as a single channel of luminance,
+    /// e.g. to preprocess it for machine learning, and return its `(width, height, pixels)`.
+    ///
+    /// Color images are converted with the Rec. 709 weights, i.e.
+    /// `0.2126 * R + 0.7152 * G + 0.0722 * B`, applied to the samples as decoded, i.e. in the
+    /// color profile of the image or `output_color`. Grayscale images are returned as is,
+    /// and the alpha channel is dropped.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn decode_to_luma<T: PixelType + Copy>(
+        &self,
+        data: &[u8],
+    ) -> Result<(u32, u32, Vec<T>), DecodeError> {
+        let mut buffer = vec![];
+        let mut pixel_format = MaybeUninit::uninit();
+        let metadata = self.decode_internal(
+            data,
+            Some(T::pixel_type()),
+            false,
+            None,
+            pixel_format.as_mut_ptr(),
+            &mut buffer,
+            None,
+        )?;
+
+        let pixel_format = unsafe { pixel_format.assume_init() };
+        let buf = T::convert(&buffer, &pixel_format);
+
+        let luma: Vec<_> = unpadded_rows(&buf, &pixel_format, metadata.width)
+            .flat_map(|row| row.chunks_exact(pixel_format.num_channels as usize))
+            .map(|pixel| match *pixel {
+                [r, g, b, ..] => T::luma(r, g, b),
+                [y, ..] => y,
+                [] => unreachable!("pixels have at least one channel"),
+            })
+            .collect();
+        debug_assert_eq!(
+            luma.len(),
+            metadata.width as usize * metadata.height as usize
+        );
+
+        Ok((metadata.width, metadata.height, luma))
+    }
+
+    /// Decode a JPEG XL image to a specific pixel type, split in tiles of `tile_width` x
+    /// `tile_height` pixels, e.g. for texture streaming or map tiles.
+    ///
+    /// The tiles are returned row by row, from the top-left one, and the tiles at the right and
+    /// bottom edges are clipped or padded as `edge`.
+    ///
+    /// # Panics
+    /// Panic if `tile_width` or `tile_height` is 0
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn decode_tiles<T: PixelType + Copy + Default>(
+        &self,
+        data: &[u8],
+        tile_width: u32,
+        tile_height: u32,
+        edge: TileEdge,
+    ) -> Result<(Metadata, Vec<Tile<T>>), DecodeError> {
+        assert!(
+            tile_width > 0 && tile_height > 0,
+            "tiles must have at least one pixel"
+        );
+
+        let mut buffer = vec![];
+        let mut pixel_format = MaybeUninit::uninit();
+        let metadata = self.decode_internal(
+            data,
+            Some(T::pixel_type()),
+            self.icc_profile,
+            None,
+            pixel_format.as_mut_ptr(),
+            &mut buffer,
+            None,
+        )?;
+        let pixel_format = unsafe { pixel_format.assume_init() };
+        let pixels = T::convert(&buffer, &pixel_format);
+
+        let channels = pixel_format.num_channels as usize;
+        let (width, height) = (metadata.width, metadata.height);
+        let stride = row_stride::<T>(&pixel_format, width);
+
+        let mut tiles = vec![];
+        for y in (0..height).step_by(tile_height as usize) {
+            for x in (0..width).step_by(tile_width as usize) {
+                let clipped = (tile_width.min(width - x), tile_height.min(height - y));
+                let (w, h) = match edge {
+                    TileEdge::Clip => clipped,
+                    TileEdge::Pad => (tile_width, tile_height),
+                };
+
+                let mut data = vec![T::default(); w as usize * h as usize * channels];
+                let row_len = clipped.0 as usize * channels;
+                for dy in 0..clipped.1 as usize {
+                    let src = (y as usize + dy) * stride + x as usize * channels;
+                    let dst = dy * w as usize * channels;
+                    data[dst..dst + row_len].copy_from_slice(&pixels[src..src + row_len]);
+                }
+
+                tiles.push(Tile {
+                    column: x / tile_width,
+                    row: y / tile_height,
+                    width: w,
+                    height: h,
+                    data,
+                });
+            }
+        }
+
+        Ok((metadata, tiles))
+    }
+
+    /// Decode only the `width` x `height` region of a JPEG XL image at `(x, y)`, e.g. for tile
+    /// servers or deep-zoom viewers showing small windows of giant images.
+    ///
+    /// `libjxl` has no region decoding, so the whole image is still decoded, but its pixels
+    /// are cropped as they are output, without allocating a buffer for the whole image.
+    ///
+    /// # Errors
+    /// Return [`DecodeError::RegionOutOfBounds`] if the region is not within the image,
+    /// or a [`DecodeError`] when internal decoder fails
+    pub fn decode_region<T: PixelType + Copy + Default + Send>(
+        &self,
+        data: &[u8],
+        (x, y): (u32, u32),
+        (width, height): (u32, u32),
+    ) -> Result<(BasicInfo, Vec<T>), DecodeError> {
+        struct Region<T> {
+            pixels: Mutex<Vec<T>>,
+            format: JxlPixelFormat,
+            x: usize,
+            y: usize,
+            width: usize,
+            height: usize,
+        }
+
+        extern "C" fn crop<T: PixelType + Copy>(
+            opaque: *mut c_void,
+            px: usize,
+            py: usize,
+            num_pixels: usize,
+            pixels: *const c_void,
+        ) {
+            // Safety: `opaque` points to the region for the whole decoding
+            let ctx = unsafe { &*opaque.cast::<Region<T>>() };
+            if !(ctx.y..ctx.y + ctx.height).contains(&py) {
+                return;
+            }
+            let (start, end) = (px.max(ctx.x), (px + num_pixels).min(ctx.x + ctx.width));
+            if start >= end {
+                return;
+            }
+
+            let pixel_len = ctx.format.num_channels as usize * std::mem::size_of::<T>();
+            let bytes =
+                unsafe { std::slice::from_raw_parts(pixels.cast::<u8>(), num_pixels * pixel_len) };
+            let cropped = T::convert(
+                &bytes[(start - px) * pixel_len..(end - px) * pixel_len],
+                &ctx.format,
+            );
+
+            let dst = ((py - ctx.y) * ctx.width + start - ctx.x) * ctx.format.num_channels as usize;
+            let mut region = ctx.pixels.lock().unwrap_or_else(PoisonError::into_inner);
+            region[dst..dst + cropped.len()].copy_from_slice(&cropped);
+        }
+
+        let info = self.info(data)?;
+        let image = (info.xsize, info.ysize);
+        let within = |start: u32, len: u32, max: u32| {
+            len > 0 && start.checked_add(len).is_some_and(|end| end <= max)
+        };
+        if !within(x, width, image.0) || !within(y, height, image.1) {
+            return Err(DecodeError::RegionOutOfBounds {
+                region: (x, y, width, height),
+                image,
+            });
+        }
+
+        let format = self.output_format(&info, Some(T::pixel_type()))?;
+        let requested = u64::from(width) * u64::from(height) * u64::from(format.num_channels);
+        let len = self.check_buffer_size(requested * std::mem::size_of::<T>() as u64)?;
+
+        let ctx = Region {
+            pixels: Mutex::new(vec![T::default(); len / std::mem::size_of::<T>().max(1)]),
+            format,
+            x: x as usize,
+            y: y as usize,
+            width: width as usize,
+            height: height as usize,
+        };
+        let overrides = Overrides {
+            image_out_callback: Some((crop::<T>, std::ptr::from_ref(&ctx).cast_mut().cast())),
+            ..Overrides::default()
+        };
+        let mut format = MaybeUninit::uninit();
+        self.decode_internal_with(
+            data,
+            Some(T::pixel_type()),
+            false,
+            None,
+            format.as_mut_ptr(),
+            &mut vec![],
+            None,
+            overrides,
+        )?;
+
+        let region = ctx
+            .pixels
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        Ok((info, region))
+    }
+
+    /// Decode a tiny `width` x `height` RGBA8 placeholder of a JPEG XL image, e.g. to display
+    /// while the image loads in a progressive UI.
+    ///
+    /// The decoding stops as soon as the DC, i.e. a 1:8 downscaled version of the image, is
+    /// available, which is then box filtered to the requested size. Images without progressive
+    /// DC, e.g. lossless ones, are fully decoded first. The placeholder is only an approximation
+    /// of the image, and ignores its aspect ratio.
+    ///
+    /// This is also the fast path for thumbnails: `libjxl` has no downsampled decoding, and
+    /// renders the DC upsampled to the full resolution, so a full-size buffer is still needed,
+    /// but most of the decoding work is skipped.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn decode_placeholder(
+        &self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<u8>, DecodeError> {
+        use JxlDecoderStatus as s;
+
+        const FORMAT: JxlPixelFormat = JxlPixelFormat {
+            num_channels: 4,
+            data_type: JxlDataType::Uint8,
+            endianness: Endianness::Native,
+            align: 0,
+        };
+
+        if check_valid_signature(data) != Some(true) {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        self.setup_decoder(s::BasicInfo as i32 | s::FrameProgression as i32 | s::FullImage as i32)?;
+        check_dec_status(unsafe {
+            JxlDecoderSetProgressiveDetail(self.dec, JxlProgressiveDetail::DC)
+        })?;
+        check_dec_status(unsafe { JxlDecoderSetInput(self.dec, data.as_ptr(), data.len()) })?;
+        unsafe { JxlDecoderCloseInput(self.dec) };
+
+        let mut size = (0, 0);
+        let mut pixels = Vec::<u8>::new();
+        let res = loop {
+            match unsafe { JxlDecoderProcessInput(self.dec) } {
+                s::BasicInfo => {
+                    let mut info = MaybeUninit::uninit();
+                    if let Err(e) = check_dec_status(unsafe {
+                        JxlDecoderGetBasicInfo(self.dec, info.as_mut_ptr())
+                    }) {
+                        break Err(e);
+                    }
+                    let info = unsafe { info.assume_init() };
+                    if let Err(e) = self.check_basic_info(&info) {
+                        break Err(e);
+                    }
+                    size = (info.xsize as usize, info.ysize as usize);
+                }
+                s::NeedImageOutBuffer => {
+                    let requested = (size.0 as u64)
+                        .checked_mul(size.1 as u64)
+                        .and_then(|len| len.checked_mul(4))
+                        .unwrap_or(u64::MAX);
+                    match self.check_buffer_size(requested) {
+                        Ok(len) => pixels.resize(len, 0),
+                        Err(e) => break Err(e),
+                    }
+                    if let Err(e) = check_dec_status(unsafe {
+                        JxlDecoderSetImageOutBuffer(
+                            self.dec,
+                            &FORMAT,
+                            pixels.as_mut_ptr().cast(),
+                            pixels.len(),
+                        )
+                    }) {
+                        break Err(e);
+                    }
+                }
+                // Stop at the DC if it can be rendered, or else at the full image
+                s::FrameProgression => {
+                    if unsafe { JxlDecoderFlushImage(self.dec) } == s::Success {
+                        break Ok(());
+                    }
+                }
+                s::FullImage => break Ok(()),
+                s::Box => {
+                    if let Err(e) = self.check_box() {
+                        break Err(e);
+                    }
+                }
+                status => break Err(from_status(status)),
+            }
+        };
+        unsafe { JxlDecoderReset(self.dec) };
+        res?;
+
+        // Box filter, every output pixel averaging the source pixels it covers
+        let (src_width, src_height) = size;
+        let (width, height) = (width as usize, height as usize);
+        let mut placeholder = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            let y0 = y * src_height / height;
+            let y1 = ((y + 1) * src_height / height).max(y0 + 1);
+            for x in 0..width {
+                let x0 = x * src_width / width;
+                let x1 = ((x + 1) * src_width / width).max(x0 + 1);
+
+                let mut sum = [0usize; 4];
+                for row in y0..y1 {
+                    for pixel in pixels[(row * src_width + x0) * 4..(row * src_width + x1) * 4]
+                        .chunks_exact(4)
+                    {
+                        for (s, &v) in sum.iter_mut().zip(pixel) {
+                            *s += usize::from(v);
+                        }
+                    }
+                }
+
+                let count = (y1 - y0) * (x1 - x0);
+                #[allow(clippy::cast_possible_truncation)]
+                placeholder.extend(sum.map(|s| (s / count) as u8));
+            }
+        }
+
+        Ok(placeholder)
+    }
+
+    /// Decode the preview image embedded in a JPEG XL image, a small version of it stored
+    /// separately, e.g. to show thumbnails in a gallery. The decoding stops after the preview,
+    /// so the full image is never decoded.
+    ///
+    /// Return the `(width, height, pixels)` of the preview, in the pixel format of the image,
+    /// or `None` if the image has no preview.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    #[allow(clippy::type_complexity)]
+    pub fn decode_preview<T: PixelType>(
+        &self,
+        data: &[u8],
+    ) -> Result<Option<(u32, u32, Vec<T>)>, DecodeError> {
+        use JxlDecoderStatus as s;
+
+        if check_valid_signature(data) != Some(true) {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        self.setup_decoder(s::BasicInfo as i32 | s::PreviewImage as i32)?;
+        check_dec_status(unsafe { JxlDecoderSetInput(self.dec, data.as_ptr(), data.len()) })?;
+        unsafe { JxlDecoderCloseInput(self.dec) };
+
+        let mut info = MaybeUninit::<BasicInfo>::uninit();
+        let mut format = None;
+        let mut pixels = Vec::<u8>::new();
+        let res = loop {
+            match unsafe { JxlDecoderProcessInput(self.dec) } {
+                s::BasicInfo => {
+                    if let Err(e) = check_dec_status(unsafe {
+                        JxlDecoderGetBasicInfo(self.dec, info.as_mut_ptr())
+                    }) {
+                        break Err(e);
+                    }
+                    if let Err(e) = self.check_basic_info(unsafe { info.assume_init_ref() }) {
+                        break Err(e);
+                    }
+                    if unsafe { info.assume_init_ref() }.have_preview != JxlBool::True {
+                        break Ok(false);
+                    }
+                }
+                s::NeedPreviewOutBuffer => {
+                    let info = unsafe { info.assume_init_ref() };
+                    let res =
+                        self.output_format(info, Some(T::pixel_type()))
+                            .and_then(|pixel_format| {
+                                let mut size = 0;
+                                check_dec_status(unsafe {
+                                    JxlDecoderPreviewOutBufferSize(
+                                        self.dec,
+                                        std::ptr::from_ref(&pixel_format),
+                                        std::ptr::from_mut(&mut size),
+                                    )
+                                })?;
+                                pixels.resize(self.check_buffer_size(size as u64)?, 0);
+                                check_dec_status(unsafe {
+                                    JxlDecoderSetPreviewOutBuffer(
+                                        self.dec,
+                                        std::ptr::from_ref(&pixel_format),
+                                        pixels.as_mut_ptr().cast(),
+                                        pixels.len(),
+                                    )
+                                })?;
+                                format = Some(pixel_format);
+                                Ok(())
+                            });
+                    if let Err(e) = res {
+                        break Err(e);
+                    }
+                }
+                s::PreviewImage => break Ok(true),
+                s::Box => {
+                    if let Err(e) = self.check_box() {
+                        break Err(e);
+                    }
+                }
+                status => break Err(from_status(status)),
+            }
+        };
+        unsafe { JxlDecoderReset(self.dec) };
+
+        match (res?, format) {
+            (true, Some(format)) => {
+                let preview = unsafe { info.assume_init() }.preview;
+                Ok(Some((
+                    preview.xsize,
+                    preview.ysize,
+                    T::convert(&pixels, &format),
+                )))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Read the physical `(x, y)` resolution of a JPEG XL image in dots per inch, or `None` if
+    /// it has none, e.g. to print it at its intended size.
+    ///
+    /// The resolution comes from the `XResolution` and `YResolution` tags of the `Exif` box,
+    /// or else, for recompressed JPEG files, from the Exif or JFIF segment of the reconstructed
+    /// JPEG. Resolutions in dots per centimeter are converted, and those without a unit, e.g.
+    /// only giving the aspect ratio of the pixels, are ignored.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn resolution(&self, data: &[u8]) -> Result<Option<(f64, f64)>, DecodeError> {
+        let mut has_jbrd = false;
+        for (box_type, contents) in self.read_boxes(data, &[b"Exif", b"jbrd"])? {
+            match &box_type {
+                b"Exif" => {
+                    if let Some(resolution) = exif::box_resolution(&contents) {
+                        return Ok(Some(resolution));
+                    }
+                }
+                _ => has_jbrd = true,
+            }
+        }
+
+        if has_jbrd {
+            if let (_, Data::Jpeg(jpeg)) = self.reconstruct(data)? {
+                return Ok(exif::jpeg_resolution(&jpeg));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Read the raw Exif, XMP and JUMBF metadata of a JPEG XL image, without decoding the
+    /// pixels, e.g. to preserve the capture data when converting it. A bare codestream has
+    /// no metadata.
+    ///
+    /// Compressed `brob` boxes are decompressed only if `libjxl` is built with `brotli`;
+    /// otherwise they are missing from the result.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn metadata_boxes(&self, data: &[u8]) -> Result<MetadataBoxes, DecodeError> {
+        let mut metadata = MetadataBoxes::default();
+        for (box_type, contents) in self.read_boxes(data, &[b"Exif", b"xml ", b"jumb"])? {
+            match &box_type {
+                b"Exif" => metadata.exif = metadata.exif.or(Some(contents)),
+                b"xml " => metadata.xmp = metadata.xmp.or(Some(contents)),
+                _ => metadata.jumbf.push(contents),
+            }
+        }
+
+        Ok(metadata)
+    }
+
+    /// Read the contents of the boxes of the types in `wanted`, in the order of the file, with
+    /// compressed `brob` boxes decompressed, without decoding the pixels
+    fn read_boxes(
+        &self,
+        data: &[u8],
+        wanted: &[&[u8; 4]],
+    ) -> Result<Vec<BoxContents>, DecodeError> {
+        const CHUNK_SIZE: usize = 64 * 1024;
+
+        if check_valid_signature(data) != Some(true) {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        self.setup_decoder(JxlDecoderStatus::Box as i32)?;
+        // Without `brotli` support, `brob` boxes are returned as is, and are not matched
+        unsafe { JxlDecoderSetDecompressBoxes(self.dec, JxlBool::True) };
+        check_dec_status(unsafe { JxlDecoderSetInput(self.dec, data.as_ptr(), data.len()) })?;
+        unsafe { JxlDecoderCloseInput(self.dec) };
+
+        let mut boxes: Vec<BoxContents> = vec![];
+        // Whether the last box is being read
+        let mut reading = false;
+        let res = loop {
+            let status = unsafe { JxlDecoderProcessInput(self.dec) };
+
+            if reading && matches!(status, JxlDecoderStatus::Box | JxlDecoderStatus::Success) {
+                let remaining = unsafe { JxlDecoderReleaseBoxBuffer(self.dec) };
+                // Safety: a box is being read
+                let contents = unsafe { &mut boxes.last_mut().unwrap_unchecked().1 };
+                contents.truncate(contents.len() - remaining);
+                reading = false;
+            }
+
+            match status {
+                JxlDecoderStatus::Box => {
+                    let mut box_type = JxlBoxType::default();
+                    if let Err(e) = check_dec_status(unsafe {
+                        JxlDecoderGetBoxType(self.dec, &mut box_type, JxlBool::True)
+                    }) {
+                        break Err(e);
+                    }
+                    #[allow(clippy::cast_sign_loss)]
+                    let box_type = box_type.map(|c| c as u8);
+
+                    if wanted.contains(&&box_type) {
+                        let mut contents = vec![0; CHUNK_SIZE];
+                        if let Err(e) = check_dec_status(unsafe {
+                            JxlDecoderSetBoxBuffer(self.dec, contents.as_mut_ptr(), contents.len())
+                        }) {
+                            break Err(e);
+                        }
+                        boxes.push((box_type, contents));
+                        reading = true;
+                    } else if self.strict_boxes {
+                        if let Err(e) = self.check_box() {
+                            break Err(e);
+                        }
+                    }
+                }
+                JxlDecoderStatus::BoxNeedMoreOutput => {
+                    let remaining = unsafe { JxlDecoderReleaseBoxBuffer(self.dec) };
+                    // Safety: a box is being read
+                    let contents = unsafe { &mut boxes.last_mut().unwrap_unchecked().1 };
+                    let used = contents.len() - remaining;
+                    contents.resize(contents.len() + CHUNK_SIZE, 0);
+                    if let Err(e) = check_dec_status(unsafe {
+                        JxlDecoderSetBoxBuffer(
+                            self.dec,
+                            contents[used..].as_mut_ptr(),
+                            contents.len() - used,
+                        )
+                    }) {
+                        break Err(e);
+                    }
+                }
+                JxlDecoderStatus::Success => break Ok(()),
+                status => break Err(from_status(status)),
+            }
+        };
+        unsafe { JxlDecoderReset(self.dec) };
+
+        res.map(|()| boxes)
+    }
+
+    /// Count the frames of a JPEG XL image without decoding their pixels, e.g. to know whether
+    /// it is animated. With `coalescing`, the default, only the displayed frames are counted,
+    /// so a still image has 1 frame whatever its number of layers.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn count_frames(&self, data: &[u8]) -> Result<usize, DecodeError> {
+        if check_valid_signature(data) != Some(true) {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        self.setup_decoder(JxlDecoderStatus::Frame as i32)?;
+        check_dec_status(unsafe { JxlDecoderSetInput(self.dec, data.as_ptr(), data.len()) })?;
+        unsafe { JxlDecoderCloseInput(self.dec) };
+
+        // Without subscribing to full images, the pixels of the frames are skipped
+        let mut count = 0;
+        let res = loop {
+            match unsafe { JxlDecoderProcessInput(self.dec) } {
+                JxlDecoderStatus::Frame => count += 1,
+                JxlDecoderStatus::Box => {
+                    if let Err(e) = self.check_box() {
+                        break Err(e);
+                    }
+                }
+                JxlDecoderStatus::Success => break Ok(count),
+                status => break Err(from_status(status)),
+            }
+        };
+        unsafe { JxlDecoderReset(self.dec) };
+
+        res
+    }
+
+    /// Reset the decoder, releasing the memory held by `libjxl` for the last image.
+    ///
+    /// Every decoding starts from a clean state anyway, even after a failed one,
+    /// so this is only needed to free the memory early when the decoder is kept around.
+    pub fn reset(&mut self) {
+        unsafe { JxlDecoderReset(self.dec) };
+    }
+
+    /// Read the HDR info of a JPEG XL image, i.e. its luminance range and transfer function,
+    /// without decoding the pixels, e.g. to pick a display path.
+    /// SDR-only consumers can decode HDR images with
+    /// [`tone_map_to_sdr`](Self::tone_map_to_sdr) to get correct-looking output.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn hdr_info(&self, data: &[u8]) -> Result<HdrInfo, DecodeError> {
+        use JxlDecoderStatus as s;
+
+        if check_valid_signature(data) != Some(true) {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        self.setup_decoder(s::BasicInfo as i32 | s::ColorEncoding as i32)?;
+        check_dec_status(unsafe { JxlDecoderSetInput(self.dec, data.as_ptr(), data.len()) })?;
+        unsafe { JxlDecoderCloseInput(self.dec) };
+
+        let mut info = MaybeUninit::<BasicInfo>::uninit();
+        let res = loop {
+            match unsafe { JxlDecoderProcessInput(self.dec) } {
+                s::BasicInfo => {
+                    if let Err(e) = check_dec_status(unsafe {
+                        JxlDecoderGetBasicInfo(self.dec, info.as_mut_ptr())
+                    }) {
+                        break Err(e);
+                    }
+                }
+                s::ColorEncoding => {
+                    let mut encoding = MaybeUninit::uninit();
+                    // Images with an ICC profile have no encoded transfer function
+                    let transfer_function = (unsafe {
+                        JxlDecoderGetColorAsEncodedProfile(
+                            self.dec,
+                            JxlColorProfileTarget::Original,
+                            encoding.as_mut_ptr(),
+                        )
+                    } == s::Success)
+                        .then(|| unsafe { encoding.assume_init() }.transfer_function);
+
+                    let info = unsafe { info.assume_init_ref() };
+                    break Ok(HdrInfo {
+                        intensity_target: info.intensity_target,
+                        min_nits: info.min_nits,
+                        linear_below: info.linear_below,
+                        relative_to_max_display: info.relative_to_max_display == JxlBool::True,
+                        transfer_function,
+                    });
+                }
+                s::Box => {
+                    if let Err(e) = self.check_box() {
+                        break Err(e);
+                    }
+                }
+                status => break Err(from_status(status)),
+            }
+        };
+        unsafe { JxlDecoderReset(self.dec) };
+
+        res
+    }
+
+    /// Read the basic info of a JPEG XL image only, e.g. its dimensions, bit depth, alpha and
+    /// animation flags, without decoding the pixels. The decoding stops as soon as the basic
+    /// info is known, so only the start of the input is needed.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn info(&self, data: &[u8]) -> Result<BasicInfo, DecodeError> {
+        self.with_basic_info(data, |info| Ok(info.clone()))
+    }
+
+    /// Return a short human readable summary of the image, for logging and CLI output,
+    /// without decoding the pixels.
+    ///
+    /// The format is `{width}x{height} {color type} {bits}-bit`, followed by ` float` for
+    /// floating point samples and `, animated` for animations,
+    /// e.g. `1920x1080 RGBA 8-bit, animated`. The color type is formatted as in [`ColorType`].
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn info_summary(&self, data: &[u8]) -> Result<String, DecodeError> {
+        let info = self.info(data)?;
+
+        let mut summary = format!(
+            "{}x{} {} {}-bit",
+            info.xsize,
+            info.ysize,
+            ColorType::from_basic_info(&info),
+            info.bits_per_sample
+        );
+        if info.exponent_bits_per_sample > 0 {
+            summary.push_str(" float");
+        }
+        if info.have_animation == JxlBool::True {
+            summary.push_str(", animated");
+        }
+
+        Ok(summary)
+    }
+
+    /// Decode a JPEG XL image twice: to 8-bit sRGB pixels for display, and to pixels in the
+    /// original color profile and bit depth for editing. Return the metadata, the display
+    /// pixels, and the original pixels.
+    ///
+    /// # Note
+    /// `libjxl` produces one color profile per decoding, so this costs two decodings, and holds
+    /// both buffers in memory, i.e. up to 5 bytes per channel with 32-bit original pixels.
+    /// See [`JxlDecoderBuilder::output_color`] for which images can be converted to sRGB.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn decode_srgb_and_original(
+        &self,
+        data: &[u8],
+    ) -> Result<(Metadata, Vec<u8>, Pixels), DecodeError> {
+        let decode = |data_type, output_color| {
+            let mut buffer = vec![];
+            let mut pixel_format = MaybeUninit::uninit();
+            let overrides = Overrides {
+                output_color: Some(output_color),
+                ..Overrides::default()
+            };
+            let metadata = self.decode_internal_with(
+                data,
+                data_type,
+                self.icc_profile,
+                None,
+                pixel_format.as_mut_ptr(),
+                &mut buffer,
+                None,
+                overrides,
+            )?;
+            Ok::<_, DecodeError>((metadata, buffer, unsafe { pixel_format.assume_init() }))
+        };
+
+        let (metadata, original, format) = decode(None, None)?;
+        let (_, display, format_u8) = decode(Some(JxlDataType::Uint8), Some(ColorEncoding::Srgb))?;
+        Ok((
+            metadata,
+            u8::convert(&display, &format_u8),
+            Pixels::new(original, &format),
+        ))
+    }
+
+    /// Decode a JPEG XL image to a specific pixel type, grouping the samples of each pixel
+    /// into an array of `N` channels, e.g. `Vec<[u8; 4]>` for RGBA.
+    /// The padding of the rows set by [`PixelFormat::align`] is skipped.
+    ///
+    /// # Errors
+    /// Return [`DecodeError::ChannelMismatch`] if `N` is not the number of channels of the output
+    /// pixel format, or a [`DecodeError`] when internal decoder fails
+    pub fn decode_pixels<T: PixelType + Copy, const N: usize>(
+        &self,
+        data: &[u8],
+    ) -> Result<(Metadata, Vec<[T; N]>), DecodeError> {
+        let mut buffer = vec![];
+        let mut pixel_format = MaybeUninit::uninit();
+        let metadata = self.decode_internal(
+            data,
+            Some(T::pixel_type()),
+            self.icc_profile,
+            None,
+            pixel_format.as_mut_ptr(),
+            &mut buffer,
+            None,
+        )?;
+
+        let pixel_format = unsafe { pixel_format.assume_init() };
+        if pixel_format.num_channels as usize != N {
+            return Err(DecodeError::ChannelMismatch {
+                expected: N,
+                actual: pixel_format.num_channels,
+            });
+        }
+
+        let buf = T::convert(&buffer, &pixel_format);
+        let pixels = unpadded_rows(&buf, &pixel_format, metadata.width)
+            .flat_map(|row| row.chunks_exact(N))
+            .map(|p| std::array::from_fn(|i| p[i]))
+            .collect();
+        Ok((metadata, pixels))
+    }
+
+    /// Decode all the frames of a JPEG XL image to a specific pixel type, e.g. the frames of an
+    /// animation, together with the basic info of the image.
+    ///
+    /// The loop count of an animation is `info.animation.num_loops`, where 0 means it loops
+    /// forever, and [`Frame::duration_ms`] converts the frame durations to milliseconds.
+    /// Only the first [`max_frames`](JxlDecoder::max_frames) frames are decoded if it is set.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn decode_frames<T: PixelType>(
+        &mut self,
+        data: &[u8],
+    ) -> Result<(BasicInfo, Vec<Frame<T>>), DecodeError> {
+        if check_valid_signature(data) != Some(true) {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        let max_frames = self.max_frames.unwrap_or(usize::MAX);
+        let mut decoder = StreamingDecoder::new(self)?;
+
+        let mut frames = vec![];
+        // Dropping the decoder resets it, skipping the remaining frames
+        decoder.decode_all(data, |decoder, event| {
+            if let Event::Frame = event {
+                frames.push(decoder.frame());
+            }
+            Ok(if frames.len() == max_frames {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            })
+        })?;
+
+        let info = decoder.basic_info().ok_or(DecodeError::GenericError)?;
+        Ok((info.clone(), frames))
+    }
+
+    /// Decode the frame at `index` of a JPEG XL image to a specific pixel type, e.g. to seek
+    /// in an animation, together with the basic info of the image.
+    ///
+    /// The earlier frames are skipped without allocating their pixels. With `coalescing`,
+    /// the default, the indices are those of [`decode_frames`](Self::decode_frames), and the
+    /// frame is returned as displayed, blended with the frames it depends on. Without it,
+    /// the frame is returned as is, which may only be a layer to blend over earlier frames.
+    ///
+    /// # Errors
+    /// Return [`DecodeError::FrameIndexOutOfRange`] if the image has no frame at `index`,
+    /// or a [`DecodeError`] when internal decoder fails
+    pub fn decode_frame_at<T: PixelType>(
+        &mut self,
+        data: &[u8],
+        index: usize,
+    ) -> Result<(BasicInfo, Vec<T>), DecodeError> {
+        if check_valid_signature(data) != Some(true) {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        let mut decoder = StreamingDecoder::new(self)?;
+        decoder.skip_frames(index);
+
+        let found = decoder.decode_all(data, |_, event| {
+            Ok(match event {
+                Event::Frame => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            })
+        })?;
+        if found.is_none() {
+            return Err(DecodeError::FrameIndexOutOfRange {
+                index,
+                count: index - decoder.frames_to_skip(),
+            });
+        }
+
+        let info = decoder.basic_info().ok_or(DecodeError::GenericError)?;
+        Ok((info.clone(), decoder.frame().data))
+    }
+
+    /// Decode all the frames of a JPEG XL image to a specific pixel type, calling `f` on each
+    /// of them as soon as it is decoded, and return the basic info of the image.
+    ///
+    /// Unlike [`decode_frames`](Self::decode_frames), a single output buffer is reused across
+    /// frames instead of allocating for each of them, which matters for long animations.
+    /// This requires the frames to have the same size, as is the case with coalescing enabled;
+    /// the buffer is grown whenever a frame is larger than the previous ones.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn for_each_frame<T: PixelType, F: FnMut(&Frame<T>)>(
+        &mut self,
+        data: &[u8],
+        mut f: F,
+    ) -> Result<BasicInfo, DecodeError> {
+        if check_valid_signature(data) != Some(true) {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        let mut decoder = StreamingDecoder::new(self)?;
+
+        let mut frame = Frame {
+            width: 0,
+            height: 0,
+            x: 0,
+            y: 0,
+            blend_mode: BlendMode::Replace,
+            duration: 0,
+            is_last: false,
+            name: None,
+            data: vec![],
+        };
+        decoder.decode_all(data, |decoder, event| {
+            if let Event::Frame = event {
+                decoder.frame_into(&mut frame);
+                f(&frame);
+            }
+            Ok(ControlFlow::<()>::Continue(()))
+        })?;
+
+        decoder
+            .basic_info()
+            .cloned()
+            .ok_or(DecodeError::GenericError)
+    }
+
+    /// Decode a JPEG XL image to a specific pixel type, passing the pixels to `callback` as
+    /// soon as they are decoded instead of collecting them, and return the basic info of
+    /// the image. This allows processing images too large to fit in memory, e.g. by streaming
+    /// them to disk.
+    ///
+    /// The callback receives `(x, y, num_pixels, pixels)`: a horizontal strip of `num_pixels`
+    /// pixels starting at column `x` of row `y`, where `pixels` holds their interleaved
+    /// channels. Strips arrive in no particular order, and are received again for every frame
+    /// of an animation. [`decode_to_writer`](Self::decode_to_writer) writes the rows in order
+    /// instead.
+    ///
+    /// # Note
+    /// With a parallel runner, `callback` is called from the threads of the runner, one at a
+    /// time. It must not panic, as unwinding into `libjxl` aborts the process
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn decode_with_callback<T, F>(
+        &mut self,
+        data: &[u8],
+        callback: F,
+    ) -> Result<BasicInfo, DecodeError>
+    where
+        T: PixelType,
+        F: FnMut(usize, usize, usize, &[T]) + Send,
+    {
+        struct Context<F> {
+            callback: Mutex<F>,
+            format: JxlPixelFormat,
+        }
+
+        extern "C" fn trampoline<T, F>(
+            opaque: *mut c_void,
+            x: usize,
+            y: usize,
+            num_pixels: usize,
+            pixels: *const c_void,
+        ) where
+            T: PixelType,
+            F: FnMut(usize, usize, usize, &[T]) + Send,
+        {
+            // Safety: `opaque` points to the context for the whole decoding, and is only
+            // written before any pixel is decoded
+            let ctx = unsafe { &*opaque.cast::<Context<F>>() };
+            let len = num_pixels * ctx.format.num_channels as usize * std::mem::size_of::<T>();
+            let pixels = T::convert(
+                unsafe { std::slice::from_raw_parts(pixels.cast(), len) },
+                &ctx.format,
+            );
+
+            let mut callback = ctx.callback.lock().unwrap_or_else(PoisonError::into_inner);
+            callback(x, y, num_pixels, &pixels);
+        }
+
+        if check_valid_signature(data) != Some(true) {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        let mut ctx = Context {
+            callback: Mutex::new(callback),
+            format: JxlPixelFormat {
+                num_channels: 0,
+                data_type: T::pixel_type(),
+                endianness: Endianness::Native,
+                align: 0,
+            },
+        };
+        let opaque = std::ptr::from_mut(&mut ctx);
+
+        let mut decoder = StreamingDecoder::<T>::new(self)?;
+        unsafe { decoder.set_image_out_callback(trampoline::<T, F>, opaque.cast()) };
+
+        decoder.decode_all(data, |decoder, event| {
+            if let Event::BasicInfo = event {
+                // Safety: no pixel is decoded yet
+                unsafe { (*opaque).format = decoder.output_format()? };
+            }
+            Ok(ControlFlow::<()>::Continue(()))
+        })?;
+
+        decoder
+            .basic_info()
+            .cloned()
+            .ok_or(DecodeError::GenericError)
+    }
+
+    /// Decode a JPEG XL image to a specific pixel type, writing the raw pixels to `writer`
+    /// as they are decoded, e.g. to pipe them to another process, and return the basic info
+    /// of the image.
+    ///
+    /// The bytes are rows of `width` pixels of interleaved channels, from top to bottom,
+    /// without padding, in the endianness of [`PixelFormat`]. This is the layout of
+    /// [`decode_into`](Self::decode_into) with an `align` of 0. The frames of an animation
+    /// are written one after the other, so coalescing should be enabled, the default.
+    ///
+    /// `libjxl` decodes the image in groups of 256x256 pixels, in no particular order, so the
+    /// rows are written once all the groups they cross are decoded, and only the rows not yet
+    /// written are held in memory.
+    ///
+    /// # Errors
+    /// Return [`DecodeError::Io`] if it fails to write to `writer`,
+    /// or a [`DecodeError`] when internal decoder fails
+    pub fn decode_to_writer<T: PixelType, W: Write + Send>(
+        &mut self,
+        data: &[u8],
+        writer: W,
+    ) -> Result<BasicInfo, DecodeError> {
+        struct Rows<W> {
+            writer: W,
+            error: Option<std::io::Error>,
+            /// Bytes of a row
+            row_len: usize,
+            pixel_len: usize,
+            /// Rows being decoded, with the number of pixels decoded so far
+            pending: BTreeMap<usize, (usize, Vec<u8>)>,
+            next_row: usize,
+        }
+
+        impl<W: Write> Rows<W> {
+            fn write(&mut self, row: &[u8]) {
+                if self.error.is_none() {
+                    self.error = self.writer.write_all(row).err();
+                }
+            }
+
+            /// Write the next rows if they are complete, or all the pending rows with `all`
+            fn flush(&mut self, all: bool) {
+                while let Some(entry) = self.pending.first_entry() {
+                    let (filled, _) = entry.get();
+                    if !all && (*entry.key() != self.next_row || *filled < self.row_len) {
+                        break;
+                    }
+                    let (_, row) = entry.remove();
+                    self.write(&row);
+                    self.next_row += 1;
+                }
+            }
+        }
+
+        extern "C" fn trampoline<W: Write>(
+            opaque: *mut c_void,
+            x: usize,
+            y: usize,
+            num_pixels: usize,
+            pixels: *const c_void,
+        ) {
+            // Safety: `opaque` points to the rows for the whole decoding, and is only
+            // written before any pixel is decoded
+            let rows = unsafe { &*opaque.cast::<Mutex<Rows<W>>>() };
+            let mut rows = rows.lock().unwrap_or_else(PoisonError::into_inner);
+            let (pixel_len, row_len) = (rows.pixel_len, rows.row_len);
+            let len = num_pixels * pixel_len;
+            let pixels = unsafe { std::slice::from_raw_parts(pixels.cast::<u8>(), len) };
+
+            let (filled, row) = rows
+                .pending
+                .entry(y)
+                .or_insert_with(|| (0, vec![0; row_len]));
+            row[x * pixel_len..x * pixel_len + len].copy_from_slice(pixels);
+            *filled += len;
+            rows.flush(false);
+        }
+
+        if check_valid_signature(data) != Some(true) {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        let mut rows = Mutex::new(Rows {
+            writer,
+            error: None,
+            row_len: 0,
+            pixel_len: 0,
+            pending: BTreeMap::new(),
+            next_row: 0,
+        });
+        let opaque = std::ptr::from_mut(&mut rows);
+
+        let mut decoder = StreamingDecoder::<T>::new(self)?;
+        unsafe { decoder.set_image_out_callback(trampoline::<W>, opaque.cast()) };
+
+        decoder.decode_all(data, |decoder, event| {
+            match event {
+                Event::BasicInfo => {
+                    let format = decoder.output_format()?;
+                    let info = decoder.basic_info().ok_or(DecodeError::GenericError)?;
+                    // Safety: no pixel is decoded yet
+                    let rows = unsafe { &mut *opaque }.get_mut();
+                    let rows = rows.unwrap_or_else(PoisonError::into_inner);
+                    rows.pixel_len = format.num_channels as usize * std::mem::size_of::<T>();
+                    rows.row_len = rows.pixel_len * info.xsize as usize;
+                }
+                // The next frame starts again from the top
+                Event::Frame => {
+                    // Safety: the pixels of the frame are all decoded
+                    let rows = unsafe { &mut *opaque }.get_mut();
+                    let rows = rows.unwrap_or_else(PoisonError::into_inner);
+                    rows.flush(true);
+                    rows.next_row = 0;
+                }
+                _ => {}
+            }
+            Ok(ControlFlow::<()>::Continue(()))
+        })?;
+
+        let info = decoder
+            .basic_info()
+            .cloned()
+            .ok_or(DecodeError::GenericError)?;
+        drop(decoder);
+
+        let rows = rows.into_inner().unwrap_or_else(PoisonError::into_inner);
+        let mut writer = rows.writer;
+        match rows.error {
+            Some(e) => Err(e.into()),
+            None => Ok(writer.flush().map(|()| info)?),
+        }
+    }
+
+    /// Decode a JPEG XL image to a specific pixel type, writing the raw pixels to `buffer`
+    /// instead of allocating them, and return the basic info of the image. The pixels are
+    /// laid out as described by [`PixelFormat`], in the requested endianness, and are
+    /// overwritten by every frame of an animation.
+    ///
+    /// [`required_buffer_size`](Self::required_buffer_size) returns the number of bytes needed.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails, or
+    /// [`DecodeError::BufferTooSmall`] if `buffer` cannot hold the pixels
+    pub fn decode_into<T: PixelType>(
+        &mut self,
+        data: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<BasicInfo, DecodeError> {
+        if check_valid_signature(data) != Some(true) {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        let mut decoder = StreamingDecoder::<T>::new(self)?;
+        // Safety: `buffer` is borrowed until the decoder is dropped
+        unsafe { decoder.set_image_out_buffer(buffer.as_mut_ptr(), buffer.len()) };
+        decoder.decode_all(data, |_, _| Ok(ControlFlow::<()>::Continue(())))?;
+
+        decoder
+            .basic_info()
+            .cloned()
+            .ok_or(DecodeError::GenericError)
+    }
+
+    /// Decode a JPEG XL image to a specific pixel type, writing the samples to `buffer` instead
+    /// of allocating them, as [`decode_into`](Self::decode_into) but with a typed buffer.
+    ///
+    /// The samples are written in the requested endianness, so the pixel format must have native
+    /// endianness, as by default, for them to be read back as values of `T`.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails, or
+    /// [`DecodeError::BufferTooSmall`] if `buffer` cannot hold the pixels, with sizes in bytes
+    pub fn decode_into_slice<T: PixelType>(
+        &mut self,
+        data: &[u8],
+        buffer: &mut [T],
+    ) -> Result<BasicInfo, DecodeError> {
+        // Safety: the pixel types are plain numbers, without padding, and any bit pattern is a
+        // valid value of them
+        let bytes = unsafe {
+            std::slice::from_raw_parts_mut(
+                buffer.as_mut_ptr().cast::<u8>(),
+                std::mem::size_of_val(buffer),
+            )
+        };
+        self.decode_into::<T>(data, bytes)
+    }
+
+    /// Number of bytes needed by [`decode_into`](Self::decode_into) to decode the image
+    /// to a specific pixel type
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails, or
+    /// [`DecodeError::ImageTooLarge`] if it exceeds `max_buffer_bytes`
+    pub fn required_buffer_size<T: PixelType>(&self, data: &[u8]) -> Result<usize, DecodeError> {
+        let (info, format) = self.with_basic_info(data, |info| {
+            Ok((
+                info.clone(),
+                self.output_format(info, Some(T::pixel_type()))?,
+            ))
+        })?;
+
+        // Already swapped by `libjxl` when the image is reoriented, computed in `u64` as the
+        // dimensions may overflow `usize` on 32-bit platforms
+        let (width, height) = (u64::from(info.xsize), u64::from(info.ysize));
+        let pixel = u64::from(format.num_channels) * std::mem::size_of::<T>() as u64;
+        let row = if format.align > 1 {
+            (pixel * width).next_multiple_of(format.align as u64)
+        } else {
+            pixel * width
+        };
+
+        self.check_buffer_size(row * height.saturating_sub(1) + pixel * width)
+    }
+
+    /// Decode a JPEG XL image to a specific pixel type into `file`, which is resized to the
+    /// output and memory-mapped. This keeps large images out of the heap, the operating
+    /// system paging the pixels to and from the file as needed. The pixels are laid out as
+    /// with [`decode_into`](Self::decode_into).
+    ///
+    /// # Safety
+    /// The file must not be modified or truncated, by this or another process, while the
+    /// returned map is alive, as the map would then alias or point past the file
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails, or [`DecodeError::Io`] if the
+    /// file cannot be resized or mapped
+    #[cfg(feature = "memmap2")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "memmap2")))]
+    pub unsafe fn decode_to_file<T: PixelType>(
+        &mut self,
+        data: &[u8],
+        file: &std::fs::File,
+    ) -> Result<(BasicInfo, memmap2::MmapMut), DecodeError> {
+        let size = self.required_buffer_size::<T>(data)?;
+        file.set_len(size as u64)?;
+
+        let mut map = unsafe { memmap2::MmapMut::map_mut(file)? };
+        let info = self.decode_into::<T>(data, &mut map)?;
+
+        Ok((info, map))
+    }
+
+    /// Decode a JPEG XL image to a specific pixel type from a reader, e.g. a file or a network
+    /// stream, without holding the whole input in memory.
+    ///
+    /// The reader is only read when `libjxl` needs more input, and the bytes already consumed by
+    /// the decoder are discarded. For animations, the last frame is returned.
+    ///
+    /// # Errors
+    /// Return [`DecodeError::Io`] if it fails to read from `reader`,
+    /// or a [`DecodeError`] when internal decoder fails
+    pub fn decode_reader<T: PixelType, R: Read>(
+        &mut self,
+        mut reader: R,
+    ) -> Result<(BasicInfo, Vec<T>), DecodeError> {
+        const CHUNK_SIZE: usize = 64 * 1024;
+
+        let mut decoder = StreamingDecoder::new(self)?;
+        let mut chunk = vec![0; CHUNK_SIZE];
+
+        let mut pixels = None;
+        loop {
+            match decoder.next_event()? {
+                Event::BasicInfo | Event::Progress => {}
+                Event::Frame => pixels = Some(decoder.frame().data),
+                Event::Finished => break,
+                Event::NeedMoreInput => match reader.read(&mut chunk) {
+                    Ok(0) => decoder.close(),
+                    Ok(n) => decoder.feed(&chunk[..n])?,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e.into()),
+                },
+            }
+        }
+
+        match (decoder.basic_info(), pixels) {
+            (Some(info), Some(pixels)) => Ok((info.clone(), pixels)),
+            _ => Err(DecodeError::GenericError),
+        }
+    }
+
+    /// List the extra channels of a JPEG XL image, e.g. alpha, depth, thermal or spot colors,
+    /// without decoding the pixels
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn extra_channels(&self, data: &[u8]) -> Result<Vec<ExtraChannel>, DecodeError> {
+        self.with_basic_info(data, |info| {
+            (0..info.num_extra_channels)
+                .map(|i| self.extra_channel(i))
+                .collect()
+        })
+    }
+
+    /// Decode a JPEG XL image to a specific pixel type, along with each of its extra channels
+    /// as a separate plane of one sample per pixel, and return the basic info of the image.
+    /// The planes are in the order of [`extra_channels`](Self::extra_channels), and are
+    /// upsampled to the size of the image. The alpha channel is also in the pixels if the
+    /// output has one. For animations, the last frame is returned.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    #[allow(clippy::type_complexity)]
+    pub fn decode_extra_channels<T: PixelType>(
+        &mut self,
+        data: &[u8],
+    ) -> Result<(BasicInfo, Vec<T>, Vec<(ExtraChannel, Vec<T>)>), DecodeError> {
+        if check_valid_signature(data) != Some(true) {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        let mut decoder = StreamingDecoder::<T>::new(self)?;
+        decoder.request_extra_channels();
+
+        let mut channels = vec![];
+        let mut res = None;
+        decoder.decode_all(data, |decoder, event| {
+            match event {
+                Event::BasicInfo => channels = decoder.extra_channels()?,
+                Event::Frame => {
+                    let planes = (0..channels.len()).map(|i| decoder.extra_channel(i));
+                    res = Some((decoder.frame().data, planes.collect::<Vec<_>>()));
+                }
+                _ => {}
+            }
+            Ok(ControlFlow::<()>::Continue(()))
+        })?;
+
+        let info = decoder
+            .basic_info()
+            .ok_or(DecodeError::GenericError)?
+            .clone();
+        let (pixels, planes) = res.ok_or(DecodeError::GenericError)?;
+        Ok((info, pixels, channels.into_iter().zip(planes).collect()))
     }
 
     /// Reconstruct JPEG data. Fallback to pixels if JPEG reconstruction fails
@@ -482,6 +2796,7 @@ impl<'pr, 'mm> JxlDecoder<'pr, 'mm> {
             Some(&mut jpeg_buf),
             pixel_format.as_mut_ptr(),
             &mut buffer,
+            None,
         )?;
 
         Ok((
@@ -493,6 +2808,19 @@ impl<'pr, 'mm> JxlDecoder<'pr, 'mm> {
             },
         ))
     }
+
+    /// Reconstruct the original JPEG of an image losslessly transcoded from JPEG, bit for bit.
+    /// Unlike [`reconstruct`](Self::reconstruct), there is no fallback to pixels.
+    ///
+    /// # Errors
+    /// Return [`DecodeError::NoJpegReconstruction`] if the image was not transcoded from JPEG,
+    /// or a [`DecodeError`] when internal decoder fails
+    pub fn decode_to_jpeg(&self, data: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        match self.reconstruct(data)? {
+            (_, Data::Jpeg(jpeg)) => Ok(jpeg),
+            (_, Data::Pixels(_)) => Err(DecodeError::NoJpegReconstruction),
+        }
+    }
 }
 
 impl<'prl, 'mm> Drop for JxlDecoder<'prl, 'mm> {
@@ -501,7 +2829,141 @@ impl<'prl, 'mm> Drop for JxlDecoder<'prl, 'mm> {
     }
 }
 
+/// Type and contents of a box
+type BoxContents = ([u8; 4], Vec<u8>);
+
+/// Size of the chunks of input given to `libjxl` when reporting the progress
+const PROGRESS_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Box types defined by the JPEG XL container format
+const KNOWN_BOXES: [&[u8; 4]; 11] = [
+    b"JXL ", b"ftyp", b"jxll", b"jxli", b"jxlc", b"jxlp", b"jbrd", b"Exif", b"xml ", b"jumb",
+    b"brob",
+];
+
+/// Walk the boxes of a container, and tell whether its last box is cut in the codestream or
+/// elsewhere, or whether boxes are missing after the last complete one.
+/// Return `None` if the boxes and the codestream are complete
+fn check_container(data: &[u8]) -> Option<DecodeError> {
+    let mut rest = data;
+    let mut codestream_complete = false;
+
+    while !rest.is_empty() {
+        if rest.len() < 8 {
+            return Some(DecodeError::ContainerIncomplete);
+        }
+        let (header, size) = match u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) {
+            // The box extends to the end of the file
+            0 => (8, rest.len()),
+            // The size is stored after the type
+            1 => {
+                let size = rest.get(8..16)?;
+                (
+                    16,
+                    usize::try_from(u64::from_be_bytes(size.try_into().ok()?)).ok()?,
+                )
+            }
+            size => (8, size as usize),
+        };
+        if size < header {
+            // Corrupt rather than truncated
+            return None;
+        }
+
+        let box_type = &rest[4..8];
+        let is_codestream = box_type == b"jxlc" || box_type == b"jxlp";
+        if size > rest.len() {
+            return Some(if is_codestream {
+                DecodeError::CodestreamTruncated
+            } else {
+                DecodeError::ContainerIncomplete
+            });
+        }
+
+        if box_type == b"jxlc" {
+            codestream_complete = true;
+        } else if box_type == b"jxlp" {
+            // The highest bit of the index of a partial codestream box marks the last one
+            codestream_complete = rest.get(header).is_some_and(|index| index & 0x80 != 0);
+        }
+        rest = &rest[size..];
+    }
+
+    (!codestream_complete).then_some(DecodeError::ContainerIncomplete)
+}
+
+/// Number of samples of type `T` from the start of a row of `width` pixels to the start of the
+/// next one, in pixels output by `libjxl` in `format`
+fn row_stride<T: PixelType>(format: &JxlPixelFormat, width: u32) -> usize {
+    let format = PixelFormat {
+        num_channels: format.num_channels,
+        endianness: format.endianness,
+        align: format.align,
+    };
+    format.row_bytes::<T>(width) / std::mem::size_of::<T>()
+}
+
+/// Rows of `width` pixels of `pixels` output by `libjxl` in `format`, without their padding
+fn unpadded_rows<'a, T: PixelType>(
+    pixels: &'a [T],
+    format: &JxlPixelFormat,
+    width: u32,
+) -> impl Iterator<Item = &'a [T]> {
+    let row_len = width as usize * format.num_channels as usize;
+    // The last row is not padded
+    pixels
+        .chunks(row_stride::<T>(format, width).max(1))
+        .map(move |row| &row[..row_len])
+}
+
+/// Replace the non-finite samples of floating point `pixels` with `fill`
+fn sanitize_floats(pixels: &mut [u8], format: &JxlPixelFormat, fill: f32) {
+    macro_rules! sanitize {
+        ($ty:ty, $fill:expr) => {{
+            let (from_bytes, to_bytes): (fn(_) -> $ty, fn($ty) -> _) = match format.endianness {
+                Endianness::Native => (<$ty>::from_ne_bytes, <$ty>::to_ne_bytes),
+                Endianness::Little => (<$ty>::from_le_bytes, <$ty>::to_le_bytes),
+                Endianness::Big => (<$ty>::from_be_bytes, <$ty>::to_be_bytes),
+            };
+            for sample in pixels.chunks_exact_mut(std::mem::size_of::<$ty>()) {
+                // Safety: the chunks have the size of the type
+                let bytes = unsafe { sample.try_into().unwrap_unchecked() };
+                if !from_bytes(bytes).is_finite() {
+                    sample.copy_from_slice(&to_bytes($fill));
+                }
+            }
+        }};
+    }
+
+    match format.data_type {
+        JxlDataType::Float => sanitize!(f32, fill),
+        JxlDataType::Float16 => sanitize!(half::f16, half::f16::from_f32(fill)),
+        JxlDataType::Uint8 | JxlDataType::Uint16 => {}
+    }
+}
+
+/// Check the data color space in the header of an ICC profile against the number of color
+/// channels and the enumerated color space, if there is one
+fn is_consistent_color_profile(
+    icc: &[u8],
+    num_color_channels: u32,
+    color_space: Option<JxlColorSpace>,
+) -> bool {
+    let Some(signature) = icc.get(16..20) else {
+        return false;
+    };
+
+    matches!(
+        (signature, num_color_channels, color_space),
+        (b"GRAY", 1, None | Some(JxlColorSpace::Gray))
+            | (b"RGB " | b"CMYK", 3, None | Some(JxlColorSpace::Rgb))
+    )
+}
+
 /// Return a [`JxlDecoderBuilder`] with default settings
+///
+/// The builder is [`Clone`], so a common configuration can be forked into several decoders,
+/// sharing the parallel runner and memory manager it references
 #[must_use]
 pub fn decoder_builder<'prl, 'mm>() -> JxlDecoderBuilder<'prl, 'mm> {
     JxlDecoderBuilder::default()
@@ -519,4 +2981,79 @@ mod tests {
 
         _ = decoder_builder().clone();
     }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn sanitize() {
+        let format = |data_type, endianness| JxlPixelFormat {
+            num_channels: 1,
+            data_type,
+            endianness,
+            align: 0,
+        };
+
+        let mut pixels: Vec<_> = [1.5, f32::NAN, f32::INFINITY, f32::NEG_INFINITY]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+        sanitize_floats(
+            &mut pixels,
+            &format(JxlDataType::Float, Endianness::Big),
+            0.25,
+        );
+        let samples: Vec<_> = pixels
+            .chunks_exact(4)
+            .map(|b| f32::from_be_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(samples, [1.5, 0.25, 0.25, 0.25]);
+
+        let mut pixels: Vec<_> = [half::f16::NAN, half::f16::ONE]
+            .iter()
+            .flat_map(|v| v.to_ne_bytes())
+            .collect();
+        sanitize_floats(
+            &mut pixels,
+            &format(JxlDataType::Float16, Endianness::Native),
+            0.5,
+        );
+        assert_eq!(pixels[..2], half::f16::from_f32(0.5).to_ne_bytes());
+        assert_eq!(pixels[2..], half::f16::ONE.to_ne_bytes());
+
+        let mut pixels = vec![0xFF; 4];
+        sanitize_floats(
+            &mut pixels,
+            &format(JxlDataType::Uint8, Endianness::Native),
+            0.0,
+        );
+        assert_eq!(pixels, [0xFF; 4]);
+    }
+
+    #[test]
+    fn color_profile_consistency() {
+        let mut rgb = vec![0; 128];
+        rgb[16..20].copy_from_slice(b"RGB ");
+        let mut gray = vec![0; 128];
+        gray[16..20].copy_from_slice(b"GRAY");
+
+        assert!(is_consistent_color_profile(&rgb, 3, None));
+        assert!(is_consistent_color_profile(
+            &rgb,
+            3,
+            Some(JxlColorSpace::Rgb)
+        ));
+        assert!(is_consistent_color_profile(
+            &gray,
+            1,
+            Some(JxlColorSpace::Gray)
+        ));
+
+        assert!(!is_consistent_color_profile(&rgb, 1, None));
+        assert!(!is_consistent_color_profile(&gray, 3, None));
+        assert!(!is_consistent_color_profile(
+            &rgb,
+            3,
+            Some(JxlColorSpace::Gray)
+        ));
+        assert!(!is_consistent_color_profile(&rgb[..16], 3, None));
+    }
 }
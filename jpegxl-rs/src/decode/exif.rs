@@ -0,0 +1,190 @@
+/*
+This file is part of jpegxl-rs.
+
+jpegxl-rs is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+jpegxl-rs is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with jpegxl-rs.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Resolution metadata of Exif and JFIF
+
+/// Centimeters per inch
+const CM_PER_INCH: f64 = 2.54;
+
+/// Parse the `(x, y)` resolution in DPI of the contents of an `Exif` box, i.e. a big endian
+/// offset to the TIFF header followed by the Exif data
+pub(crate) fn box_resolution(data: &[u8]) -> Option<(f64, f64)> {
+    let offset = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    tiff_resolution(data.get(4 + offset..)?)
+}
+
+/// Parse the `(x, y)` resolution in DPI of a JPEG file, from its Exif, or else JFIF, segment
+pub(crate) fn jpeg_resolution(data: &[u8]) -> Option<(f64, f64)> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut jfif = None;
+    let mut pos = 2;
+    while let [0xFF, marker, high, low, ..] = data.get(pos..)? {
+        // Stop at the start of scan, as no metadata follows
+        if *marker == 0xDA {
+            break;
+        }
+        let len = usize::from(u16::from_be_bytes([*high, *low]));
+        let segment = data.get(pos + 4..pos + 2 + len)?;
+
+        match (*marker, segment) {
+            (0xE1, [b'E', b'x', b'i', b'f', 0, 0, tiff @ ..]) => {
+                if let Some(resolution) = tiff_resolution(tiff) {
+                    return Some(resolution);
+                }
+            }
+            (0xE0, [b'J', b'F', b'I', b'F', 0, _, _, units, x1, x2, y1, y2, ..]) => {
+                let (x, y) = (
+                    u16::from_be_bytes([*x1, *x2]),
+                    u16::from_be_bytes([*y1, *y2]),
+                );
+                jfif = match units {
+                    1 => Some((f64::from(x), f64::from(y))),
+                    2 => Some((f64::from(x) * CM_PER_INCH, f64::from(y) * CM_PER_INCH)),
+                    // Only the aspect ratio is known
+                    _ => None,
+                };
+            }
+            _ => {}
+        }
+        pos += 2 + len;
+    }
+
+    jfif
+}
+
+/// Parse the `XResolution`, `YResolution` and `ResolutionUnit` tags of the first IFD of TIFF
+/// data, as found in Exif
+fn tiff_resolution(tiff: &[u8]) -> Option<(f64, f64)> {
+    let little_endian = match tiff.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let u16_at = |pos: usize| {
+        let bytes = tiff.get(pos..pos + 2)?.try_into().ok()?;
+        Some(if little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    };
+    let u32_at = |pos: usize| {
+        let bytes = tiff.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+    let rational_at = |pos: usize| {
+        let (num, den) = (u32_at(pos)?, u32_at(pos + 4)?);
+        (den != 0).then(|| f64::from(num) / f64::from(den))
+    };
+
+    let ifd = u32_at(4)? as usize;
+    let (mut x, mut y, mut unit) = (None, None, 2);
+    for i in 0..usize::from(u16_at(ifd)?) {
+        let entry = ifd + 2 + i * 12;
+        match u16_at(entry)? {
+            0x011A => x = rational_at(u32_at(entry + 8)? as usize),
+            0x011B => y = rational_at(u32_at(entry + 8)? as usize),
+            0x0128 => unit = u16_at(entry + 8)?,
+            _ => {}
+        }
+    }
+
+    let (x, y) = (x?, y?);
+    match unit {
+        2 => Some((x, y)),
+        3 => Some((x * CM_PER_INCH, y * CM_PER_INCH)),
+        // No absolute unit
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    /// Big endian TIFF data with a single IFD holding the resolution tags
+    fn tiff(x: u32, y: u32, unit: u16) -> Vec<u8> {
+        let mut tiff = b"MM\0\x2a".to_vec();
+        tiff.extend_from_slice(&8u32.to_be_bytes());
+
+        // 3 entries, then the offset of the next IFD
+        let values = 8 + 2 + 3 * 12 + 4;
+        tiff.extend_from_slice(&3u16.to_be_bytes());
+        for (tag, ty, value) in [
+            (0x011Au16, 5u16, values),
+            (0x011B, 5, values + 8),
+            (0x0128, 3, u32::from(unit) << 16),
+        ] {
+            tiff.extend_from_slice(&tag.to_be_bytes());
+            tiff.extend_from_slice(&ty.to_be_bytes());
+            tiff.extend_from_slice(&1u32.to_be_bytes());
+            tiff.extend_from_slice(&value.to_be_bytes());
+        }
+        tiff.extend_from_slice(&0u32.to_be_bytes());
+
+        for num in [x, y] {
+            tiff.extend_from_slice(&num.to_be_bytes());
+            tiff.extend_from_slice(&1u32.to_be_bytes());
+        }
+        tiff
+    }
+
+    #[test]
+    fn exif_box() {
+        let mut data = 0u32.to_be_bytes().to_vec();
+        data.extend(tiff(300, 150, 2));
+        assert_eq!(box_resolution(&data), Some((300.0, 150.0)));
+
+        let mut data = 0u32.to_be_bytes().to_vec();
+        data.extend(tiff(100, 100, 3));
+        assert_eq!(box_resolution(&data), Some((254.0, 254.0)));
+
+        let mut data = 0u32.to_be_bytes().to_vec();
+        data.extend(tiff(72, 72, 1));
+        assert_eq!(box_resolution(&data), None);
+
+        assert_eq!(box_resolution(&[0, 0, 0, 0, b'I', b'I']), None);
+    }
+
+    #[test]
+    fn jpeg() {
+        assert_eq!(
+            jpeg_resolution(crate::tests::SAMPLE_JPEG),
+            Some((118.0 * CM_PER_INCH, 118.0 * CM_PER_INCH))
+        );
+
+        let tiff = tiff(240, 240, 2);
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        data.extend_from_slice(&u16::try_from(tiff.len() + 8).unwrap().to_be_bytes());
+        data.extend_from_slice(b"Exif\0\0");
+        data.extend(tiff);
+        data.extend_from_slice(&[0xFF, 0xDA]);
+        assert_eq!(jpeg_resolution(&data), Some((240.0, 240.0)));
+
+        assert_eq!(jpeg_resolution(&[0xFF, 0xD8, 0xFF, 0xDA]), None);
+        assert_eq!(jpeg_resolution(crate::tests::SAMPLE_PNG), None);
+    }
+}
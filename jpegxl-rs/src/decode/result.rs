@@ -15,11 +15,16 @@ You should have received a copy of the GNU General Public License
 along with jpegxl-rs.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+use std::fmt;
+
 use half::f16;
-use jpegxl_sys::types::{JxlDataType, JxlPixelFormat};
+use jpegxl_sys::{
+    color_encoding::JxlColorEncoding,
+    types::{JxlBool, JxlDataType, JxlPixelFormat},
+};
 
-use super::Orientation;
-use crate::common::PixelType;
+use super::{BasicInfo, ExtraChannelInfo, Orientation, TransferFunction};
+use crate::{common::PixelType, encode::BlendMode};
 
 /// Result of decoding
 #[derive(Debug)]
@@ -32,7 +37,8 @@ pub struct Metadata {
     pub intensity_target: f32,
     /// Lower bound on the intensity level present in the image
     pub min_nits: f32,
-    /// Orientation
+    /// Orientation, already applied to the pixels unless
+    /// [`skip_reorientation`](crate::decode::JxlDecoder::skip_reorientation) is set
     pub orientation: Orientation,
     /// Number of color channels per pixel _without_ alpha channel, from metadata
     pub num_color_channels: u32,
@@ -44,8 +50,50 @@ pub struct Metadata {
     /// Intrinsic height of the image.
     /// Applications are advised to resample the decoded image to the intrinsic dimensions
     pub intrinsic_height: u32,
+    /// Number of bits per sample of the original image, from metadata
+    pub bits_per_sample: u32,
+    /// Number of exponent bits per sample of the original image, 0 for integer samples,
+    /// from metadata
+    pub exponent_bits_per_sample: u32,
+    /// Whether the image is stored in its original color profile instead of XYB,
+    /// e.g. losslessly, from metadata
+    pub uses_original_profile: bool,
     /// ICC profile
     pub icc_profile: Option<Vec<u8>>,
+    /// Color encoding of the pixels, requested along with the ICC profile.
+    /// `None` if the color space is only described by the ICC profile
+    pub color_encoding: Option<JxlColorEncoding>,
+}
+
+impl Metadata {
+    /// Whether the image is HDR, i.e. its peak brightness in [`Metadata::intensity_target`]
+    /// exceeds the 255 nits of SDR content, which display pipelines need to tone map
+    #[must_use]
+    pub fn is_hdr(&self) -> bool {
+        self.intensity_target > 255.0
+    }
+
+    /// The smallest pixel type holding the samples of the image without losing precision,
+    /// e.g. to pick the output type of a generic loader
+    #[must_use]
+    pub fn recommended_pixel_type(&self) -> RecommendedType {
+        match (self.bits_per_sample, self.exponent_bits_per_sample) {
+            (0..=8, 0) => RecommendedType::U8,
+            (9..=16, 0) => RecommendedType::U16,
+            _ => RecommendedType::F32,
+        }
+    }
+}
+
+/// Pixel type recommended by [`Metadata::recommended_pixel_type`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecommendedType {
+    /// `u8`, for samples of at most 8 bits
+    U8,
+    /// `u16`, for samples of at most 16 bits
+    U16,
+    /// `f32`, for floating point samples or integer samples wider than 16 bits
+    F32,
 }
 
 /// Pixels returned from the decoder
@@ -72,6 +120,247 @@ impl Pixels {
     }
 }
 
+/// Color type of an image, i.e. its color channels and whether it has an alpha channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    /// Grayscale
+    Gray,
+    /// Grayscale with alpha
+    GrayAlpha,
+    /// RGB
+    Rgb,
+    /// RGB with alpha
+    Rgba,
+}
+
+impl ColorType {
+    pub(crate) fn from_basic_info(info: &BasicInfo) -> Self {
+        match (info.num_color_channels, info.alpha_bits > 0) {
+            (1, false) => Self::Gray,
+            (1, true) => Self::GrayAlpha,
+            (_, false) => Self::Rgb,
+            (_, true) => Self::Rgba,
+        }
+    }
+}
+
+impl fmt::Display for ColorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Gray => "Gray",
+            Self::GrayAlpha => "GrayA",
+            Self::Rgb => "RGB",
+            Self::Rgba => "RGBA",
+        })
+    }
+}
+
+/// A frame of a decoded image
+#[derive(Debug)]
+pub struct Frame<T> {
+    /// Width of the frame
+    pub width: u32,
+    /// Height of the frame
+    pub height: u32,
+    /// Horizontal offset of the frame in the image, which may be negative. Only layers decoded
+    /// without coalescing have an offset, e.g. those added with
+    /// [`MultiFrames::add_layer`](crate::encode::MultiFrames::add_layer)
+    pub x: i32,
+    /// Vertical offset of the frame in the image, see [`Frame::x`]
+    pub y: i32,
+    /// How the frame is blended onto the previous ones. Frames decoded with coalescing are
+    /// already blended, and are always [`BlendMode::Replace`]
+    pub blend_mode: BlendMode,
+    /// Duration of the frame in ticks of the animation
+    pub duration: u32,
+    /// Whether it is the last frame of the image
+    pub is_last: bool,
+    /// Name of the frame, e.g. the name of a layer in an image editor, `None` if it has none.
+    /// Names are UTF-8, invalid sequences being replaced with `U+FFFD`
+    pub name: Option<String>,
+    /// Pixels of the frame
+    pub data: Vec<T>,
+}
+
+impl<T> Frame<T> {
+    /// Duration of the frame in milliseconds, using the ticks per second of the animation
+    /// in `info`. Return 0 if the image is not animated
+    #[must_use]
+    pub fn duration_ms(&self, info: &BasicInfo) -> f64 {
+        let animation = &info.animation;
+        if info.have_animation == JxlBool::False || animation.tps_numerator == 0 {
+            return 0.0;
+        }
+
+        f64::from(self.duration) * 1000.0 * f64::from(animation.tps_denominator)
+            / f64::from(animation.tps_numerator)
+    }
+}
+
+/// An extra channel of an image, e.g. alpha, depth or a spot color, see
+/// [`JxlDecoder::extra_channels`](super::JxlDecoder::extra_channels)
+#[derive(Debug, Clone)]
+pub struct ExtraChannel {
+    /// Info of the channel, e.g. its type, bit depth, or the color of a spot color channel
+    pub info: ExtraChannelInfo,
+    /// Name of the channel, `None` if it has none.
+    /// Names are UTF-8, invalid sequences being replaced with `U+FFFD`
+    pub name: Option<String>,
+}
+
+/// HDR info of an image, see [`JxlDecoder::hdr_info`](super::JxlDecoder::hdr_info)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HdrInfo {
+    /// Upper bound of the intensity level present in the image, in nits.
+    /// 255 for SDR images
+    pub intensity_target: f32,
+    /// Lower bound of the intensity level present in the image, in nits
+    pub min_nits: f32,
+    /// Intensity below which the tone mapping is linear, in nits, or as a ratio of
+    /// `intensity_target` if `relative_to_max_display` is set
+    pub linear_below: f32,
+    /// Whether `linear_below` is a ratio instead of nits
+    pub relative_to_max_display: bool,
+    /// Transfer function of the color encoding, e.g. PQ or HLG,
+    /// `None` if the color is described by an ICC profile
+    pub transfer_function: Option<TransferFunction>,
+}
+
+impl HdrInfo {
+    /// Whether the image is HDR, i.e. it has a PQ or HLG transfer function, or its intensity
+    /// target exceeds the 255 nits of SDR
+    #[must_use]
+    pub fn is_hdr(&self) -> bool {
+        matches!(
+            self.transfer_function,
+            Some(TransferFunction::Pq | TransferFunction::Hlg)
+        ) || self.intensity_target > 255.0
+    }
+}
+
+/// Raw payloads of the metadata boxes of a container, see
+/// [`JxlDecoder::metadata_boxes`](super::JxlDecoder::metadata_boxes).
+/// Boxes compressed in `brob` boxes are decompressed
+#[derive(Debug, Clone, Default)]
+pub struct MetadataBoxes {
+    /// Contents of the `Exif` box, i.e. a big endian offset to the TIFF header followed by
+    /// the Exif data, `None` if it has none
+    pub exif: Option<Vec<u8>>,
+    /// Contents of the `xml ` box, i.e. the XMP packet, `None` if it has none
+    pub xmp: Option<Vec<u8>>,
+    /// Contents of the `jumb` boxes, e.g. C2PA manifests, in the order of the file
+    pub jumbf: Vec<Vec<u8>>,
+}
+
+/// Progress of a decoding, see [`JxlDecoder::on_progress`](super::JxlDecoder::on_progress)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Number of bytes of the input consumed by the decoder
+    pub bytes_consumed: usize,
+    /// Size of the input
+    pub total_bytes: usize,
+    /// Number of progressive passes reached in the current frame
+    pub pass: u32,
+    /// Number of frames fully decoded
+    pub frames_completed: u32,
+}
+
+/// A tile of a decoded image, see [`JxlDecoder::decode_tiles`](super::JxlDecoder::decode_tiles)
+#[derive(Debug, Clone)]
+pub struct Tile<T> {
+    /// Column of the tile in the grid
+    pub column: u32,
+    /// Row of the tile in the grid
+    pub row: u32,
+    /// Width of the tile, less than the tile width for clipped tiles at the right edge
+    pub width: u32,
+    /// Height of the tile, less than the tile height for clipped tiles at the bottom edge
+    pub height: u32,
+    /// Pixels of the tile, with the same channels as the image
+    pub data: Vec<T>,
+}
+
+/// Decoded pixels, viewable both as samples of type `T` and as raw bytes, e.g. to hand them
+/// over FFI without transmuting the buffer.
+///
+/// The samples are interleaved, in native endianness, and aligned for `T`. The bytes are the
+/// samples in memory order, so [`as_bytes`](Self::as_bytes) has `size_of::<T>()` bytes for
+/// each sample of [`as_typed`](Self::as_typed).
+#[derive(Debug, Clone)]
+pub struct PixelBuffer<T> {
+    data: Vec<T>,
+}
+
+impl<T: PixelType> PixelBuffer<T> {
+    pub(crate) fn new(data: Vec<T>) -> Self {
+        Self { data }
+    }
+
+    /// The samples
+    #[must_use]
+    pub fn as_typed(&self) -> &[T] {
+        &self.data
+    }
+
+    /// The samples as bytes
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8] {
+        // Safety: the pixel types are plain numbers, without padding, and any initialized
+        // memory is valid for `u8`
+        unsafe {
+            std::slice::from_raw_parts(
+                self.data.as_ptr().cast(),
+                std::mem::size_of_val(self.data.as_slice()),
+            )
+        }
+    }
+
+    /// Take the samples
+    #[must_use]
+    pub fn into_vec(self) -> Vec<T> {
+        self.data
+    }
+}
+
+/// Decoded pixels along with their layout, see
+/// [`JxlDecoder::decode_image`](super::JxlDecoder::decode_image)
+///
+/// Rows start every [`stride`](Self::stride) samples, which is more than
+/// `width * num_channels` when the rows are padded by [`PixelFormat::align`](super::PixelFormat).
+/// The last row is not padded.
+#[derive(Debug, Clone)]
+pub struct DecodedImage<T> {
+    /// Width of the image
+    pub width: u32,
+    /// Height of the image
+    pub height: u32,
+    /// Number of interleaved channels of a pixel
+    pub num_channels: u32,
+    /// Number of samples from the start of a row to the start of the next one
+    pub stride: usize,
+    /// Samples of the image
+    pub data: Vec<T>,
+}
+
+impl<T> DecodedImage<T> {
+    /// Iterate over the rows of the image, without their padding
+    pub fn as_rows(&self) -> impl Iterator<Item = &[T]> {
+        let row_len = self.width as usize * self.num_channels as usize;
+        (0..self.height as usize).map(move |y| &self.data[y * self.stride..][..row_len])
+    }
+
+    /// The samples of the pixel at `(x, y)`, or `None` when out of bounds
+    #[must_use]
+    pub fn pixel(&self, x: u32, y: u32) -> Option<&[T]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let num_channels = self.num_channels as usize;
+        let start = y as usize * self.stride + x as usize * num_channels;
+        self.data.get(start..start + num_channels)
+    }
+}
+
 /// Reconstruction result
 pub enum Data {
     /// JPEG  
@@ -84,25 +373,70 @@ pub enum Data {
 mod tests {
     use super::*;
 
+    fn metadata(intensity_target: f32) -> Metadata {
+        Metadata {
+            width: 0,
+            height: 0,
+            intensity_target,
+            min_nits: 0.0,
+            orientation: Orientation::Identity,
+            num_color_channels: 0,
+            has_alpha_channel: false,
+            intrinsic_width: 0,
+            intrinsic_height: 0,
+            bits_per_sample: 8,
+            exponent_bits_per_sample: 0,
+            uses_original_profile: false,
+            icc_profile: None,
+            color_encoding: None,
+        }
+    }
+
     #[test]
     #[cfg_attr(coverage_nightly, coverage(off))]
     fn test_derive() {
-        println!(
-            "{:?}",
-            Metadata {
-                width: 0,
-                height: 0,
-                intensity_target: 0.0,
-                min_nits: 0.0,
-                orientation: Orientation::Identity,
-                num_color_channels: 0,
-                has_alpha_channel: false,
-                intrinsic_width: 0,
-                intrinsic_height: 0,
-                icc_profile: None,
-            }
-        );
+        println!("{:?}", metadata(0.0));
 
         println!("{:?}", Pixels::Float(vec![]));
     }
+
+    #[test]
+    fn recommended_pixel_type() {
+        let with_bits = |bits_per_sample, exponent_bits_per_sample| Metadata {
+            bits_per_sample,
+            exponent_bits_per_sample,
+            ..metadata(255.0)
+        };
+
+        assert_eq!(
+            with_bits(2, 0).recommended_pixel_type(),
+            RecommendedType::U8
+        );
+        assert_eq!(
+            with_bits(8, 0).recommended_pixel_type(),
+            RecommendedType::U8
+        );
+        assert_eq!(
+            with_bits(12, 0).recommended_pixel_type(),
+            RecommendedType::U16
+        );
+        assert_eq!(
+            with_bits(24, 0).recommended_pixel_type(),
+            RecommendedType::F32
+        );
+        assert_eq!(
+            with_bits(16, 5).recommended_pixel_type(),
+            RecommendedType::F32
+        );
+        assert_eq!(
+            with_bits(32, 8).recommended_pixel_type(),
+            RecommendedType::F32
+        );
+    }
+
+    #[test]
+    fn hdr() {
+        assert!(!metadata(255.0).is_hdr());
+        assert!(metadata(10000.0).is_hdr());
+    }
 }
@@ -0,0 +1,789 @@
+/*
+This file is part of jpegxl-rs.
+
+jpegxl-rs is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+jpegxl-rs is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with jpegxl-rs.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Incremental decoding with the input fed in chunks
+
+use std::{ffi::c_void, marker::PhantomData, mem::MaybeUninit, ops::ControlFlow};
+
+#[allow(clippy::wildcard_imports)]
+use jpegxl_sys::{
+    codestream_header::JxlFrameHeader,
+    decode::*,
+    types::{JxlBool, JxlPixelFormat},
+};
+
+use super::{BasicInfo, ExtraChannel, Frame, JxlDecoder};
+use crate::{
+    common::PixelType,
+    encode::BlendMode,
+    errors::{check_dec_status, from_status, DecodeError},
+};
+
+/// Events emitted by [`StreamingDecoder`]
+pub(crate) enum Event {
+    /// The basic info of the image is available, see [`StreamingDecoder::basic_info`]
+    BasicInfo,
+    /// A frame is fully decoded, see [`StreamingDecoder::frame`]
+    Frame,
+    /// A frame is partially decoded, and [`StreamingDecoder::frame`] has a preview of it.
+    /// Only emitted by [`StreamingDecoder::new_progressive`]
+    Progress,
+    /// More input is needed to make progress
+    NeedMoreInput,
+    /// The whole image is decoded
+    Finished,
+}
+
+/// Decoder state kept between chunks of input
+pub(crate) struct StreamingDecoder<'dec, 'pr, 'mm, T: PixelType> {
+    decoder: &'dec mut JxlDecoder<'pr, 'mm>,
+    /// Input not yet consumed by the decoder
+    input: Vec<u8>,
+    input_set: bool,
+    closed: bool,
+    basic_info: Option<BasicInfo>,
+    frame_header: Option<JxlFrameHeader>,
+    frame_name: Option<String>,
+    pixel_format: Option<JxlPixelFormat>,
+    pixels: Vec<u8>,
+    image_out: ImageOut,
+    /// Number of frames left to skip
+    skip_frames: usize,
+    /// Buffers of the extra channels, if requested
+    extra_channels: Option<Vec<Vec<u8>>>,
+    _pixel_type: PhantomData<T>,
+}
+
+/// Where the decoder writes the pixels
+#[derive(Clone, Copy)]
+enum ImageOut {
+    /// The internal buffer, read with [`StreamingDecoder::frame`]
+    Pixels,
+    /// A callback, with its opaque pointer
+    Callback(JxlImageOutCallback, *mut c_void),
+    /// A buffer of the caller, with its length
+    Buffer(*mut u8, usize),
+}
+
+impl<'dec, 'pr, 'mm, T: PixelType> StreamingDecoder<'dec, 'pr, 'mm, T> {
+    pub(crate) fn new(decoder: &'dec mut JxlDecoder<'pr, 'mm>) -> Result<Self, DecodeError> {
+        Self::with_events(decoder, 0)
+    }
+
+    /// Create a decoder also emitting [`Event::Progress`] at the progressive steps set by
+    /// `progressive_detail`
+    pub(crate) fn new_progressive(
+        decoder: &'dec mut JxlDecoder<'pr, 'mm>,
+    ) -> Result<Self, DecodeError> {
+        Self::with_events(decoder, JxlDecoderStatus::FrameProgression as i32)
+    }
+
+    fn with_events(
+        decoder: &'dec mut JxlDecoder<'pr, 'mm>,
+        events: i32,
+    ) -> Result<Self, DecodeError> {
+        use JxlDecoderStatus::{BasicInfo, Frame, FullImage};
+
+        let stream = Self {
+            decoder,
+            input: vec![],
+            input_set: false,
+            closed: false,
+            basic_info: None,
+            frame_header: None,
+            frame_name: None,
+            pixel_format: None,
+            pixels: vec![],
+            image_out: ImageOut::Pixels,
+            skip_frames: 0,
+            extra_channels: None,
+            _pixel_type: PhantomData,
+        };
+        stream
+            .decoder
+            .setup_decoder(events | BasicInfo as i32 | Frame as i32 | FullImage as i32)?;
+
+        Ok(stream)
+    }
+
+    /// Append a chunk of input, keeping the bytes not yet consumed by the decoder
+    pub(crate) fn feed(&mut self, data: &[u8]) -> Result<(), DecodeError> {
+        let dec = self.decoder.dec;
+
+        if self.input_set {
+            let remaining = unsafe { JxlDecoderReleaseInput(dec) };
+            self.input.drain(..self.input.len() - remaining);
+        }
+        self.input.extend_from_slice(data);
+
+        check_dec_status(unsafe {
+            JxlDecoderSetInput(dec, self.input.as_ptr(), self.input.len())
+        })?;
+        self.input_set = true;
+
+        Ok(())
+    }
+
+    /// Deliver the pixels of every frame to `callback` instead of [`StreamingDecoder::frame`],
+    /// in the format returned by [`StreamingDecoder::output_format`]
+    ///
+    /// # Safety
+    /// `opaque` must be valid for `callback` until the decoding ends,
+    /// and may be used from several threads at the same time
+    pub(crate) unsafe fn set_image_out_callback(
+        &mut self,
+        callback: JxlImageOutCallback,
+        opaque: *mut c_void,
+    ) {
+        self.image_out = ImageOut::Callback(callback, opaque);
+    }
+
+    /// Write the pixels of every frame into `buffer` instead of [`StreamingDecoder::frame`],
+    /// in the format returned by [`StreamingDecoder::output_format`].
+    /// A buffer smaller than the output fails with [`DecodeError::BufferTooSmall`]
+    ///
+    /// # Safety
+    /// `buffer` must be valid and not accessed until the decoding ends
+    pub(crate) unsafe fn set_image_out_buffer(&mut self, buffer: *mut u8, len: usize) {
+        self.image_out = ImageOut::Buffer(buffer, len);
+    }
+
+    /// Skip the next `count` frames without decoding their pixels, though `libjxl` still
+    /// decodes the parts of them later frames depend on
+    pub(crate) fn skip_frames(&mut self, count: usize) {
+        self.skip_frames = count;
+    }
+
+    /// Number of frames left to skip, e.g. when the image has fewer frames
+    pub(crate) fn frames_to_skip(&self) -> usize {
+        self.skip_frames
+    }
+
+    /// Also decode the extra channels of every frame, see [`StreamingDecoder::extra_channel`]
+    pub(crate) fn request_extra_channels(&mut self) {
+        self.extra_channels = Some(vec![]);
+    }
+
+    /// Go back to the start of the image, keeping the settings of the decoder. The input must
+    /// be fed again from its beginning, and the events are emitted again from the basic info
+    pub(crate) fn rewind(&mut self) {
+        unsafe { JxlDecoderRewind(self.decoder.dec) };
+        self.input.clear();
+        self.input_set = false;
+        self.closed = false;
+        self.basic_info = None;
+        self.frame_header = None;
+        self.frame_name = None;
+        self.skip_frames = 0;
+    }
+
+    /// Signal that there is no more input
+    pub(crate) fn close(&mut self) {
+        unsafe { JxlDecoderCloseInput(self.decoder.dec) };
+        self.closed = true;
+    }
+
+    /// Process the input until the next event
+    #[allow(clippy::too_many_lines)]
+    pub(crate) fn next_event(&mut self) -> Result<Event, DecodeError> {
+        let dec = self.decoder.dec;
+
+        loop {
+            use JxlDecoderStatus as s;
+
+            match unsafe { JxlDecoderProcessInput(dec) } {
+                s::NeedMoreInput if self.closed => return Err(DecodeError::GenericError),
+                s::NeedMoreInput => return Ok(Event::NeedMoreInput),
+
+                s::BasicInfo => {
+                    let mut info = MaybeUninit::uninit();
+                    check_dec_status(unsafe { JxlDecoderGetBasicInfo(dec, info.as_mut_ptr()) })?;
+                    let info = unsafe { info.assume_init() };
+                    self.decoder.check_basic_info(&info)?;
+                    self.decoder.tone_map(
+                        &info,
+                        Some(T::pixel_type()),
+                        self.decoder.pixel_format,
+                    )?;
+
+                    if let Some(pr) = self.decoder.parallel_runner {
+                        pr.callback_basic_info(&info);
+                    }
+
+                    self.basic_info = Some(info);
+                    return Ok(Event::BasicInfo);
+                }
+
+                s::Frame if self.skip_frames > 0 => {
+                    check_dec_status(unsafe { JxlDecoderSkipCurrentFrame(dec) })?;
+                    self.skip_frames -= 1;
+                }
+
+                s::Frame => {
+                    let mut header = MaybeUninit::uninit();
+                    check_dec_status(unsafe {
+                        JxlDecoderGetFrameHeader(dec, header.as_mut_ptr())
+                    })?;
+                    let header = unsafe { header.assume_init() };
+
+                    self.frame_name = if header.name_length > 0 {
+                        // Including the terminating null character
+                        let mut name = vec![0u8; header.name_length as usize + 1];
+                        check_dec_status(unsafe {
+                            JxlDecoderGetFrameName(dec, name.as_mut_ptr().cast(), name.len())
+                        })?;
+                        name.pop();
+                        Some(String::from_utf8_lossy(&name).into_owned())
+                    } else {
+                        None
+                    };
+                    self.frame_header = Some(header);
+                }
+
+                s::NeedImageOutBuffer => {
+                    // Safety: the basic info always comes before any frame
+                    let info = unsafe { self.basic_info.as_ref().unwrap_unchecked() };
+                    let format = match self.image_out {
+                        ImageOut::Pixels => {
+                            let mut format = MaybeUninit::uninit();
+                            self.decoder.output(
+                                info,
+                                Some(T::pixel_type()),
+                                self.decoder.pixel_format,
+                                format.as_mut_ptr(),
+                                &mut self.pixels,
+                            )?;
+                            unsafe { format.assume_init() }
+                        }
+                        ImageOut::Callback(callback, opaque) => {
+                            let format = self.decoder.output_format(info, Some(T::pixel_type()))?;
+                            check_dec_status(unsafe {
+                                JxlDecoderSetImageOutCallback(
+                                    dec,
+                                    std::ptr::from_ref(&format),
+                                    callback,
+                                    opaque,
+                                )
+                            })?;
+                            format
+                        }
+                        ImageOut::Buffer(buffer, len) => {
+                            let format = self.decoder.output_format(info, Some(T::pixel_type()))?;
+                            let mut size = 0;
+                            check_dec_status(unsafe {
+                                JxlDecoderImageOutBufferSize(
+                                    dec,
+                                    std::ptr::from_ref(&format),
+                                    std::ptr::from_mut(&mut size),
+                                )
+                            })?;
+                            if len < size {
+                                return Err(DecodeError::BufferTooSmall {
+                                    required: size,
+                                    actual: len,
+                                });
+                            }
+                            check_dec_status(unsafe {
+                                JxlDecoderSetImageOutBuffer(
+                                    dec,
+                                    std::ptr::from_ref(&format),
+                                    buffer.cast(),
+                                    size,
+                                )
+                            })?;
+                            format
+                        }
+                    };
+                    if let Some(buffers) = self.extra_channels.as_mut() {
+                        let format = extra_channel_format(&format);
+                        buffers.resize_with(info.num_extra_channels as usize, Vec::new);
+                        for (i, buffer) in (0..).zip(buffers.iter_mut()) {
+                            let mut size = 0;
+                            check_dec_status(unsafe {
+                                JxlDecoderExtraChannelBufferSize(
+                                    dec,
+                                    std::ptr::from_ref(&format),
+                                    std::ptr::from_mut(&mut size),
+                                    i,
+                                )
+                            })?;
+                            buffer.resize(self.decoder.check_buffer_size(size as u64)?, 0);
+                            check_dec_status(unsafe {
+                                JxlDecoderSetExtraChannelBuffer(
+                                    dec,
+                                    std::ptr::from_ref(&format),
+                                    buffer.as_mut_ptr().cast(),
+                                    buffer.len(),
+                                    i,
+                                )
+                            })?;
+                        }
+                    }
+                    self.pixel_format = Some(format);
+                }
+
+                // Render what is decoded so far, which fails if there is not enough yet
+                s::FrameProgression => {
+                    if unsafe { JxlDecoderFlushImage(dec) } == s::Success {
+                        return Ok(Event::Progress);
+                    }
+                }
+
+                s::FullImage => return Ok(Event::Frame),
+
+                s::Box => self.decoder.check_box()?,
+
+                s::Success => return Ok(Event::Finished),
+                status => return Err(from_status(status)),
+            }
+        }
+    }
+}
+
+impl<T: PixelType> StreamingDecoder<'_, '_, '_, T> {
+    /// Decode the whole of `data`, calling `f` with the decoder at every [`Event::BasicInfo`]
+    /// and [`Event::Frame`] until the image is decoded, or until `f` breaks with a value, which
+    /// is then returned.
+    ///
+    /// An input cut short fails with the errors of [`JxlDecoder::truncation`], as when
+    /// decoding it in one go.
+    pub(crate) fn decode_all<B>(
+        &mut self,
+        data: &[u8],
+        mut f: impl FnMut(&mut Self, Event) -> Result<ControlFlow<B>, DecodeError>,
+    ) -> Result<Option<B>, DecodeError> {
+        self.feed(data)?;
+        self.close();
+
+        loop {
+            let event = match self.next_event() {
+                Ok(event) => event,
+                Err(DecodeError::GenericError) => {
+                    return Err(self
+                        .decoder
+                        .truncation(data)
+                        .unwrap_or(DecodeError::GenericError))
+                }
+                Err(e) => return Err(e),
+            };
+            match event {
+                Event::BasicInfo | Event::Frame => {
+                    if let ControlFlow::Break(value) = f(self, event)? {
+                        return Ok(Some(value));
+                    }
+                }
+                Event::Finished => return Ok(None),
+                // Not emitted once the input is closed, nor without progressive decoding
+                Event::NeedMoreInput | Event::Progress => return Err(DecodeError::GenericError),
+            }
+        }
+    }
+
+    /// The basic info of the image, once [`Event::BasicInfo`] is emitted
+    pub(crate) fn basic_info(&self) -> Option<&BasicInfo> {
+        self.basic_info.as_ref()
+    }
+
+    /// Pixel format of the output, once [`Event::BasicInfo`] is emitted
+    pub(crate) fn output_format(&self) -> Result<JxlPixelFormat, DecodeError> {
+        let info = self.basic_info.as_ref().ok_or(DecodeError::GenericError)?;
+        self.decoder.output_format(info, Some(T::pixel_type()))
+    }
+
+    /// Info and name of the extra channels, once [`Event::BasicInfo`] is emitted
+    pub(crate) fn extra_channels(&self) -> Result<Vec<ExtraChannel>, DecodeError> {
+        let info = self.basic_info.as_ref().ok_or(DecodeError::GenericError)?;
+        (0..info.num_extra_channels)
+            .map(|i| self.decoder.extra_channel(i))
+            .collect()
+    }
+
+    /// The extra channel at `index` of the last decoded frame, if they are requested with
+    /// [`StreamingDecoder::request_extra_channels`]
+    pub(crate) fn extra_channel(&self, index: usize) -> Vec<T> {
+        match (&self.extra_channels, &self.pixel_format) {
+            (Some(buffers), Some(format)) => buffers
+                .get(index)
+                .map(|buffer| T::convert(buffer, &extra_channel_format(format)))
+                .unwrap_or_default(),
+            _ => vec![],
+        }
+    }
+
+    /// The last decoded frame
+    pub(crate) fn frame(&self) -> Frame<T> {
+        let mut frame = Frame {
+            width: 0,
+            height: 0,
+            x: 0,
+            y: 0,
+            blend_mode: BlendMode::Replace,
+            duration: 0,
+            is_last: false,
+            name: None,
+            data: vec![],
+        };
+        self.frame_into(&mut frame);
+        frame
+    }
+
+    /// Write the last decoded frame into `frame`, reusing the allocation of its pixels
+    pub(crate) fn frame_into(&self, frame: &mut Frame<T>) {
+        // Safety: the frame header and the output buffer are always set before
+        // a frame is decoded
+        let (header, format) = unsafe {
+            (
+                self.frame_header.as_ref().unwrap_unchecked(),
+                self.pixel_format.as_ref().unwrap_unchecked(),
+            )
+        };
+
+        frame.width = header.layer_info.xsize;
+        frame.height = header.layer_info.ysize;
+        frame.x = header.layer_info.crop_x0;
+        frame.y = header.layer_info.crop_y0;
+        frame.blend_mode = header.layer_info.blend_info.blendmode;
+        frame.duration = header.duration;
+        frame.is_last = header.is_last == JxlBool::True;
+        frame.name.clone_from(&self.frame_name);
+        T::convert_into(&self.pixels, format, &mut frame.data);
+    }
+}
+
+/// Pixel format of the extra channels, one sample per pixel of the type and endianness of
+/// the pixels
+fn extra_channel_format(format: &JxlPixelFormat) -> JxlPixelFormat {
+    JxlPixelFormat {
+        num_channels: 1,
+        align: 0,
+        ..*format
+    }
+}
+
+impl<T: PixelType> Drop for StreamingDecoder<'_, '_, '_, T> {
+    fn drop(&mut self) {
+        // Leave the decoder reusable, even if the decoding is abandoned halfway
+        unsafe { JxlDecoderReset(self.decoder.dec) };
+    }
+}
+
+/// Events returned by [`ChunkedDecoder::next_event`]
+#[derive(Debug)]
+pub enum DecoderEvent<T> {
+    /// The basic info of the image is decoded
+    BasicInfo(BasicInfo),
+    /// A frame is fully decoded
+    Frame(Frame<T>),
+    /// A frame is partially decoded, with a progressively refined preview of its pixels.
+    /// Only returned by [`JxlDecoder::decode_progressive`]
+    Progress(Frame<T>),
+    /// More input is needed to make progress, see [`ChunkedDecoder::feed`]
+    NeedMoreInput,
+    /// The whole image is decoded
+    Finished,
+}
+
+/// Decoder fed with chunks of input as they arrive, returned by [`JxlDecoder::decode_chunked`]
+pub struct ChunkedDecoder<'dec, 'pr, 'mm, T: PixelType> {
+    decoder: StreamingDecoder<'dec, 'pr, 'mm, T>,
+}
+
+impl<T: PixelType> ChunkedDecoder<'_, '_, '_, T> {
+    /// Append a chunk of input. Only the bytes not yet consumed by the decoder are kept.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<(), DecodeError> {
+        self.decoder.feed(chunk)
+    }
+
+    /// Signal that there is no more input, after which a truncated image fails with
+    /// [`DecodeError::GenericError`] instead of returning [`DecoderEvent::NeedMoreInput`]
+    pub fn close(&mut self) {
+        self.decoder.close();
+    }
+
+    /// Go back to the start of the image, e.g. to seek backwards in an animation with
+    /// [`ChunkedDecoder::skip_frames`]. The input must be fed again from its beginning,
+    /// and the events are returned again from [`DecoderEvent::BasicInfo`]
+    pub fn rewind(&mut self) {
+        self.decoder.rewind();
+    }
+
+    /// Skip the next `count` frames without returning them, e.g. to seek forward in an
+    /// animation. `libjxl` still decodes the parts of them later frames depend on
+    pub fn skip_frames(&mut self, count: usize) {
+        self.decoder.skip_frames(count);
+    }
+
+    /// Decode the input fed so far until the next event
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn next_event(&mut self) -> Result<DecoderEvent<T>, DecodeError> {
+        Ok(match self.decoder.next_event()? {
+            Event::BasicInfo => DecoderEvent::BasicInfo(
+                self.decoder
+                    .basic_info()
+                    .cloned()
+                    .ok_or(DecodeError::GenericError)?,
+            ),
+            Event::Frame => DecoderEvent::Frame(self.decoder.frame()),
+            Event::Progress => DecoderEvent::Progress(self.decoder.frame()),
+            Event::NeedMoreInput => DecoderEvent::NeedMoreInput,
+            Event::Finished => DecoderEvent::Finished,
+        })
+    }
+}
+
+impl<'pr, 'mm> JxlDecoder<'pr, 'mm> {
+    /// Decode a JPEG XL image fed in chunks, e.g. as it arrives over the network, without
+    /// buffering the whole input. The decoder state is kept between calls to
+    /// [`ChunkedDecoder::feed`], and [`ChunkedDecoder::next_event`] returns the basic info and
+    /// each frame as soon as they are decoded.
+    ///
+    /// The decoder is reset when the returned [`ChunkedDecoder`] is dropped, so it stays
+    /// usable even if the decoding is abandoned halfway.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] if it fails to set up the decoder
+    pub fn decode_chunked<T: PixelType>(
+        &mut self,
+    ) -> Result<ChunkedDecoder<'_, 'pr, 'mm, T>, DecodeError> {
+        Ok(ChunkedDecoder {
+            decoder: StreamingDecoder::new(self)?,
+        })
+    }
+
+    /// Decode a JPEG XL image fed in chunks as [`decode_chunked`](Self::decode_chunked), also
+    /// returning [`DecoderEvent::Progress`] with a preview of the frame being decoded, e.g. to
+    /// render it progressively in a viewer. Previews are made at the steps set by
+    /// [`progressive_detail`](JxlDecoder::progressive_detail): the DC first, a 1:8 version of
+    /// the image, then each pass. They are only made when enough of the frame is decoded, so
+    /// images without progressive steps, e.g. lossless ones, may have none.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] if it fails to set up the decoder
+    pub fn decode_progressive<T: PixelType>(
+        &mut self,
+    ) -> Result<ChunkedDecoder<'_, 'pr, 'mm, T>, DecodeError> {
+        Ok(ChunkedDecoder {
+            decoder: StreamingDecoder::new_progressive(self)?,
+        })
+    }
+}
+
+#[cfg(feature = "futures")]
+pub use self::futures::{FrameStream, ReaderFrameStream};
+
+#[cfg(feature = "futures")]
+mod futures {
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use futures_core::Stream;
+    use futures_io::AsyncRead;
+
+    use super::{Event, StreamingDecoder};
+    use crate::{
+        common::PixelType,
+        decode::{Frame, JxlDecoder},
+        DecodeError,
+    };
+
+    /// Size of the chunks read by [`ReaderFrameStream`]
+    const READ_SIZE: usize = 64 * 1024;
+
+    /// Asynchronous stream of decoded frames, returned by [`JxlDecoder::decode_stream`]
+    #[cfg_attr(docsrs, doc(cfg(feature = "futures")))]
+    pub struct FrameStream<'dec, 'pr, 'mm, T: PixelType, S> {
+        decoder: StreamingDecoder<'dec, 'pr, 'mm, T>,
+        input: S,
+        finished: bool,
+    }
+
+    /// Asynchronous stream of decoded frames read from an [`AsyncRead`],
+    /// returned by [`JxlDecoder::decode_async_read`]
+    #[cfg_attr(docsrs, doc(cfg(feature = "futures")))]
+    pub struct ReaderFrameStream<'dec, 'pr, 'mm, T: PixelType, R> {
+        decoder: StreamingDecoder<'dec, 'pr, 'mm, T>,
+        reader: R,
+        buffer: Vec<u8>,
+        finished: bool,
+    }
+
+    /// Decode until the next frame, calling `poll_input` to feed the decoder when it needs
+    /// more input. `poll_input` returns `false` once the input ends
+    fn poll_frame<T: PixelType>(
+        decoder: &mut StreamingDecoder<'_, '_, '_, T>,
+        finished: &mut bool,
+        cx: &mut Context<'_>,
+        mut poll_input: impl FnMut(
+            &mut StreamingDecoder<'_, '_, '_, T>,
+            &mut Context<'_>,
+        ) -> Poll<Result<bool, DecodeError>>,
+    ) -> Poll<Option<Result<Frame<T>, DecodeError>>> {
+        while !*finished {
+            let res = match decoder.next_event() {
+                Ok(Event::Frame) => return Poll::Ready(Some(Ok(decoder.frame()))),
+                Ok(Event::BasicInfo | Event::Progress) => Ok(()),
+                Ok(Event::Finished) => {
+                    *finished = true;
+                    Ok(())
+                }
+                Ok(Event::NeedMoreInput) => match poll_input(decoder, cx) {
+                    Poll::Ready(Ok(true)) => Ok(()),
+                    Poll::Ready(Ok(false)) => {
+                        decoder.close();
+                        Ok(())
+                    }
+                    Poll::Ready(Err(e)) => Err(e),
+                    Poll::Pending => return Poll::Pending,
+                },
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = res {
+                *finished = true;
+                return Poll::Ready(Some(Err(e)));
+            }
+        }
+
+        Poll::Ready(None)
+    }
+
+    impl<T, S> Stream for FrameStream<'_, '_, '_, T, S>
+    where
+        T: PixelType + Unpin,
+        S: Stream + Unpin,
+        S::Item: AsRef<[u8]>,
+    {
+        type Item = Result<Frame<T>, DecodeError>;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            let this = self.get_mut();
+            let input = &mut this.input;
+
+            poll_frame(&mut this.decoder, &mut this.finished, cx, |decoder, cx| {
+                Pin::new(&mut *input)
+                    .poll_next(cx)
+                    .map(|chunk| match chunk {
+                        Some(chunk) => decoder.feed(chunk.as_ref()).map(|()| true),
+                        None => Ok(false),
+                    })
+            })
+        }
+    }
+
+    impl<T, R> Stream for ReaderFrameStream<'_, '_, '_, T, R>
+    where
+        T: PixelType + Unpin,
+        R: AsyncRead + Unpin,
+    {
+        type Item = Result<Frame<T>, DecodeError>;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            let this = self.get_mut();
+            let (reader, buffer) = (&mut this.reader, &mut this.buffer);
+
+            poll_frame(&mut this.decoder, &mut this.finished, cx, |decoder, cx| {
+                Pin::new(&mut *reader)
+                    .poll_read(cx, buffer)
+                    .map(|read| match read? {
+                        0 => Ok(false),
+                        len => decoder.feed(&buffer[..len]).map(|()| true),
+                    })
+            })
+        }
+    }
+
+    impl<'pr, 'mm> JxlDecoder<'pr, 'mm> {
+        /// Decode a JPEG XL image from an asynchronous stream of byte chunks,
+        /// yielding the frames as soon as they are decoded.
+        ///
+        /// # Backpressure
+        /// The input stream is only polled when `libjxl` needs more data to make progress,
+        /// so at most one chunk plus the bytes not yet consumed by the decoder are buffered.
+        /// The decoding itself is CPU-bound and runs inside [`Stream::poll_next`], on the
+        /// thread polling the stream, see [`decode_async_read`](Self::decode_async_read) to
+        /// keep it off the threads of an async runtime.
+        ///
+        /// # Errors
+        /// Return a [`DecodeError`] if it fails to set up the decoder.
+        /// Decoding errors are yielded by the stream, which then ends.
+        #[cfg_attr(docsrs, doc(cfg(feature = "futures")))]
+        pub fn decode_stream<T: PixelType, S>(
+            &mut self,
+            input: S,
+        ) -> Result<FrameStream<'_, 'pr, 'mm, T, S>, DecodeError> {
+            Ok(FrameStream {
+                decoder: StreamingDecoder::new(self)?,
+                input,
+                finished: false,
+            })
+        }
+
+        /// Decode a JPEG XL image read from an [`AsyncRead`], e.g. an upload, yielding the
+        /// frames as soon as they are decoded, as [`decode_stream`](Self::decode_stream).
+        /// The reader is read in chunks of 64 KiB, only when `libjxl` needs more data.
+        ///
+        /// `tokio` readers can be adapted with `tokio_util::compat`. The decoding is not
+        /// offloaded: it runs inside [`Stream::poll_next`], on the thread polling the stream.
+        /// To keep it off the threads of an async runtime, drive the stream on a thread that
+        /// may block. As the decoder is neither `Send` nor `'static`, it must be created on that
+        /// thread, which only needs the reader to be moved to it.
+        ///
+        /// # Example
+        /// ```
+        /// # || -> Result<(), Box<dyn std::error::Error>> {
+        /// use futures::{executor::block_on_stream, io::Cursor};
+        /// use jpegxl_rs::{decoder_builder, DecodeError};
+        ///
+        /// # let upload = vec![];
+        /// let reader = Cursor::new(upload);
+        /// // Or `tokio::task::spawn_blocking` in a `tokio` runtime
+        /// let decoding = std::thread::spawn(move || -> Result<usize, DecodeError> {
+        ///     let mut decoder = decoder_builder().build()?;
+        ///     let frames = decoder.decode_async_read::<u8, _>(reader)?;
+        ///     block_on_stream(frames)
+        ///         .map(|frame| frame.map(|frame| frame.data.len()))
+        ///         .sum()
+        /// });
+        /// let bytes = decoding.join().expect("the decoding panicked")?;
+        /// # Ok(())
+        /// # };
+        /// ```
+        ///
+        /// # Errors
+        /// Return a [`DecodeError`] if it fails to set up the decoder.
+        /// Decoding and reading errors are yielded by the stream, which then ends.
+        #[cfg_attr(docsrs, doc(cfg(feature = "futures")))]
+        pub fn decode_async_read<T: PixelType, R>(
+            &mut self,
+            reader: R,
+        ) -> Result<ReaderFrameStream<'_, 'pr, 'mm, T, R>, DecodeError> {
+            Ok(ReaderFrameStream {
+                decoder: StreamingDecoder::new(self)?,
+                reader,
+                buffer: vec![0; READ_SIZE],
+                finished: false,
+            })
+        }
+    }
+}
@@ -17,19 +17,32 @@ along with jpegxl-rs.  If not, see <https://www.gnu.org/licenses/>.
 
 //! Encoder of JPEG XL format
 
-use std::{marker::PhantomData, mem::MaybeUninit, ops::Deref, ptr::null};
+use std::{
+    ffi::CString,
+    io::Write,
+    marker::PhantomData,
+    mem::MaybeUninit,
+    ops::Deref,
+    ptr::null,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 #[allow(clippy::wildcard_imports)]
 use jpegxl_sys::{
-    color_encoding::JxlColorEncoding,
+    codestream_header::{JxlBlendMode, JxlLayerInfo},
     encode::*,
-    types::{JxlEndianness, JxlPixelFormat},
+    types::{JxlBool, JxlEndianness, JxlPixelFormat},
 };
 
 use crate::{
     common::PixelType, errors::EncodeError, memory::MemoryManager, parallel::JxlParallelRunner,
 };
 
+pub use crate::common::ColorEncoding;
+
+/// How a layer is blended with the layers below it, see [`MultiFrames::add_layer`]
+pub type BlendMode = JxlBlendMode;
+
 /// Encoding speed
 #[derive(Debug, Clone, Copy)]
 pub enum EncoderSpeed {
@@ -59,35 +72,37 @@ impl std::default::Default for EncoderSpeed {
     }
 }
 
-/// Encoding color profile
-#[derive(Debug, Clone, Copy)]
-pub enum ColorEncoding {
-    /// SRGB, default for uint pixel types
-    Srgb,
-    /// Linear SRGB, default for float pixel types
-    LinearSrgb,
-    /// SRGB, images with only luma channel
-    SrgbLuma,
-    /// Linear SRGB with only luma channel
-    LinearSrgbLuma,
+/// Color transform applied to the pixels before compression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorTransform {
+    /// XYB, the perceptual color space of JPEG XL, used for lossy compression
+    Xyb = 0,
+    /// No transform, the samples are compressed as is
+    None = 1,
+    /// YCbCr, as used by JPEG
+    Ycbcr = 2,
 }
 
-impl From<ColorEncoding> for JxlColorEncoding {
-    fn from(val: ColorEncoding) -> Self {
-        use ColorEncoding::{LinearSrgb, LinearSrgbLuma, Srgb, SrgbLuma};
-
-        let mut color_encoding = MaybeUninit::uninit();
+/// Animation settings of the encoder
+#[derive(Debug, Clone, Copy)]
+pub struct Animation {
+    /// Numerator of the ticks per second of the frame durations
+    pub tps_numerator: u32,
+    /// Denominator of the ticks per second of the frame durations
+    pub tps_denominator: u32,
+    /// Number of times the animation is played, 0 means forever
+    pub num_loops: u32,
+}
 
-        unsafe {
-            match val {
-                Srgb => JxlColorEncodingSetToSRGB(color_encoding.as_mut_ptr(), false),
-                LinearSrgb => JxlColorEncodingSetToLinearSRGB(color_encoding.as_mut_ptr(), false),
-                SrgbLuma => JxlColorEncodingSetToSRGB(color_encoding.as_mut_ptr(), true),
-                LinearSrgbLuma => {
-                    JxlColorEncodingSetToLinearSRGB(color_encoding.as_mut_ptr(), true);
-                }
-            }
-            color_encoding.assume_init()
+impl Animation {
+    /// Animation with frame durations in milliseconds, played `num_loops` times,
+    /// 0 meaning forever
+    #[must_use]
+    pub fn milliseconds(num_loops: u32) -> Self {
+        Self {
+            tps_numerator: 1000,
+            tps_denominator: 1,
+            num_loops,
         }
     }
 }
@@ -98,6 +113,8 @@ pub struct EncoderFrame<'data, T: PixelType> {
     num_channels: Option<u32>,
     endianness: Option<JxlEndianness>,
     align: Option<usize>,
+    duration: Option<u32>,
+    name: Option<&'data str>,
 }
 
 impl<'data, T: PixelType> EncoderFrame<'data, T> {
@@ -110,6 +127,8 @@ impl<'data, T: PixelType> EncoderFrame<'data, T> {
             num_channels: None,
             endianness: None,
             align: None,
+            duration: None,
+            name: None,
         }
     }
 
@@ -137,6 +156,22 @@ impl<'data, T: PixelType> EncoderFrame<'data, T> {
         self
     }
 
+    /// Set the duration of the frame in ticks of the animation,
+    /// see [`JxlEncoderBuilder::animation`]
+    #[must_use]
+    pub fn duration(mut self, value: u32) -> Self {
+        self.duration = Some(value);
+        self
+    }
+
+    /// Set the name of the frame, e.g. the name of a layer.
+    /// It must be at most 1071 bytes long, and contain no null character
+    #[must_use]
+    pub fn name(mut self, value: &'data str) -> Self {
+        self.name = Some(value);
+        self
+    }
+
     fn pixel_format(&self) -> JxlPixelFormat {
         JxlPixelFormat {
             num_channels: self.num_channels.unwrap_or(3),
@@ -179,6 +214,13 @@ pub struct JxlEncoder<'prl, 'mm> {
     ///
     /// Default: false
     pub has_alpha: bool,
+    /// Set whether the colors of the input are premultiplied by the alpha channel, i.e. it has
+    /// associated alpha, which is stored as is. Unused without `has_alpha`.
+    /// The decoder can unpremultiply it with
+    /// [`unpremul_alpha`](crate::decode::JxlDecoder::unpremul_alpha)
+    ///
+    /// Default: false, and the alpha is straight
+    pub premultiplied_alpha: bool,
     /// Set lossless
     ///
     /// Default: false
@@ -194,13 +236,30 @@ pub struct JxlEncoder<'prl, 'mm> {
     ///    1.0 = visually lossless. <br />
     ///    Recommended range: 0.5 .. 3.0. <br />
     ///    Default value: 1.0. <br />
-    ///    If `lossless` is set to `true`, this value is unused and implied to be 0.
+    ///    If `lossless` is set to `true`, this value is unused and implied to be 0. <br />
+    ///    Encoding fails with [`EncodeError::InvalidQuality`] outside of the range.
     pub quality: f32,
+    /// Set a target size in bytes for lossy compression, instead of picking `quality` manually
+    ///
+    /// The encoder binary searches the quality over at most 8 encoding attempts, stopping once
+    /// the output is at most 5% smaller than the target, and returns the largest output that
+    /// fits, or the smallest one if none does. This is several times slower than a single
+    /// encoding. Only used by [`JxlEncoder::encode`] and [`JxlEncoder::encode_frame`],
+    /// and unused if `lossless` is set to `true`.
+    ///
+    /// Default: `None`
+    pub target_size: Option<usize>,
     /// Configure the encoder to use the JPEG XL container format
     ///
     /// Using the JPEG XL container format allows to store metadata such as JPEG reconstruction;
     /// but it adds a few bytes to the encoded file for container headers
     /// even if there is no extra metadata.
+    ///
+    /// [`JxlEncoder::encode_jpeg`] always uses the container, whatever this is set to, as the
+    /// JPEG reconstruction data is stored in a box. So do animations, see
+    /// [`JxlEncoder::animation`].
+    ///
+    /// Default: `false`, and the output is a bare codestream
     pub use_container: bool,
     /// Configure the encoder to use the original color profile
     ///
@@ -214,6 +273,7 @@ pub struct JxlEncoder<'prl, 'mm> {
     /// Set the decoding speed tier
     ///
     /// Minimum is 0 (highest quality), and maximum is 4 (lowest quality). Default is 0.
+    /// Encoding fails with [`EncodeError::InvalidDecodingSpeed`] outside of the range.
     pub decoding_speed: i64,
     /// Set initial output buffer size in bytes.
     /// Anything less than 32 bytes will be rounded up to 32 bytes.
@@ -225,13 +285,50 @@ pub struct JxlEncoder<'prl, 'mm> {
     ///
     /// Default: SRGB
     pub color_encoding: ColorEncoding,
+    /// Set the color transform
+    ///
+    /// [`ColorTransform::Xyb`] compresses best for lossy encoding, but converting to XYB
+    /// and back is not exact. [`ColorTransform::None`] keeps the samples as is, which lossless
+    /// encoding needs to round trip bit-exactly, and requires `uses_original_profile` to be
+    /// set to `true`. [`ColorTransform::Ycbcr`] is mostly useful for JPEG-like content.
+    ///
+    /// Default: unset, letting the encoder choose, i.e. XYB for lossy and no transform for lossless
+    pub color_transform: Option<ColorTransform>,
+    /// Set the number of iterations of the edge-preserving filter applied when decoding,
+    /// which smooths blocking and ringing artifacts of lossy images at the cost of detail
+    ///
+    /// Range: 0 (disabled) .. 3 (strongest). Default: unset, letting the encoder choose
+    /// from the quality
+    pub epf_iters: Option<i64>,
+
+    /// Enable chroma-from-luma when recompressing JPEG images with [`JxlEncoder::encode_jpeg`],
+    /// which predicts the chroma DCT coefficients from the luma ones. This only changes the
+    /// size of the output: the reconstructed JPEG stays byte-for-byte identical either way.
+    ///
+    /// Default: unset, letting the encoder choose, i.e. enabled
+    pub jpeg_recon_cfl: Option<bool>,
+    /// Compress the Exif and XMP metadata of JPEG images with Brotli, in `brob` boxes, when
+    /// recompressing them with [`JxlEncoder::encode_jpeg`]. The metadata is decompressed
+    /// when reconstructing the JPEG, which stays byte-for-byte identical either way.
+    ///
+    /// Default: unset, letting the encoder choose, i.e. disabled
+    pub jpeg_compress_boxes: Option<bool>,
+
+    /// Encode an animation, where each frame added with [`JxlEncoder::multiple`] is shown for
+    /// its [`EncoderFrame::duration`]. Animations always use the container format,
+    /// whatever [`use_container`](Self::use_container) is set to.
+    ///
+    /// Default: `None`, and the frames are layers of a still image
+    pub animation: Option<Animation>,
 
     /// Set parallel runner
     ///
     /// Default: `None`, indicating single thread execution
     pub parallel_runner: Option<&'prl dyn JxlParallelRunner>,
 
-    /// Set memory manager
+    /// Set memory manager, used when creating the encoder
+    ///
+    /// Default: `None`, indicating the default allocator of `libjxl`
     #[allow(dead_code)]
     memory_manager: Option<&'mm dyn MemoryManager>,
 }
@@ -264,14 +361,21 @@ impl<'prl, 'mm> JxlEncoderBuilder<'prl, 'mm> {
             enc,
             options_ptr,
             has_alpha: self.has_alpha.unwrap_or_default(),
+            premultiplied_alpha: self.premultiplied_alpha.unwrap_or_default(),
             lossless: self.lossless.unwrap_or_default(),
             speed: self.speed.unwrap_or_default(),
             quality: self.quality.unwrap_or(1.0),
+            target_size: self.target_size.flatten(),
             use_container: self.use_container.unwrap_or_default(),
             uses_original_profile: self.uses_original_profile.unwrap_or_default(),
             decoding_speed: self.decoding_speed.unwrap_or_default(),
             init_buffer_size,
             color_encoding: self.color_encoding.unwrap_or(ColorEncoding::Srgb),
+            color_transform: self.color_transform.flatten(),
+            epf_iters: self.epf_iters.flatten(),
+            jpeg_recon_cfl: self.jpeg_recon_cfl.flatten(),
+            jpeg_compress_boxes: self.jpeg_compress_boxes.flatten(),
+            animation: self.animation.flatten(),
             parallel_runner: self.parallel_runner.flatten(),
             memory_manager: mm,
         })
@@ -298,9 +402,22 @@ impl JxlEncoder<'_, '_> {
         }
     }
 
+    // Use the container if asked to, or for animations, as for JPEG reconstruction
+    fn uses_container(&self) -> bool {
+        self.use_container || self.animation.is_some()
+    }
+
     // Set options
     fn set_options(&self) -> Result<(), EncodeError> {
-        self.check_enc_status(unsafe { JxlEncoderUseContainer(self.enc, self.use_container) })?;
+        // Checked here rather than by libjxl, which only reports an API usage error
+        if !(0.0..=15.0).contains(&self.quality) {
+            return Err(EncodeError::InvalidQuality(self.quality));
+        }
+        if !(0..=4).contains(&self.decoding_speed) {
+            return Err(EncodeError::InvalidDecodingSpeed(self.decoding_speed));
+        }
+
+        self.check_enc_status(unsafe { JxlEncoderUseContainer(self.enc, self.uses_container()) })?;
         self.check_enc_status(unsafe {
             JxlEncoderSetFrameLossless(self.options_ptr, self.lossless)
         })?;
@@ -321,6 +438,30 @@ impl JxlEncoder<'_, '_> {
                 self.decoding_speed,
             )
         })?;
+        if let Some(transform) = self.color_transform {
+            self.check_enc_status(unsafe {
+                JxlEncoderFrameSettingsSetOption(
+                    self.options_ptr,
+                    FrameSetting::ColorTransform,
+                    transform as _,
+                )
+            })?;
+        }
+        if let Some(iters) = self.epf_iters {
+            self.check_enc_status(unsafe {
+                JxlEncoderFrameSettingsSetOption(self.options_ptr, FrameSetting::Epf, iters)
+            })?;
+        }
+        for (setting, value) in [
+            (FrameSetting::JpegReconCfl, self.jpeg_recon_cfl),
+            (FrameSetting::JpegCompressBoxes, self.jpeg_compress_boxes),
+        ] {
+            if let Some(value) = value {
+                self.check_enc_status(unsafe {
+                    JxlEncoderFrameSettingsSetOption(self.options_ptr, setting, value.into())
+                })?;
+            }
+        }
 
         Ok(())
     }
@@ -353,7 +494,7 @@ impl JxlEncoder<'_, '_> {
 
         basic_info.xsize = width;
         basic_info.ysize = height;
-        basic_info.have_container = self.use_container.into();
+        basic_info.have_container = self.uses_container().into();
         basic_info.uses_original_profile = self.uses_original_profile.into();
 
         basic_info.bits_per_sample = bits;
@@ -363,12 +504,23 @@ impl JxlEncoder<'_, '_> {
             basic_info.num_extra_channels = 1;
             basic_info.alpha_bits = bits;
             basic_info.alpha_exponent_bits = exp;
+            basic_info.alpha_premultiplied = self.premultiplied_alpha.into();
         } else {
             basic_info.num_extra_channels = 0;
             basic_info.alpha_bits = 0;
             basic_info.alpha_exponent_bits = 0;
         }
 
+        if let Some(animation) = self.animation {
+            basic_info.have_animation = true.into();
+            basic_info.animation.tps_numerator = animation.tps_numerator;
+            basic_info.animation.tps_denominator = animation.tps_denominator;
+            basic_info.animation.num_loops = animation.num_loops;
+        }
+
+        // libjxl has no API to add a preview frame, so `have_preview` is left unset: signaling
+        // a preview without encoding one would produce an invalid codestream
+
         match self.color_encoding {
             ColorEncoding::SrgbLuma | ColorEncoding::LinearSrgbLuma => {
                 basic_info.num_color_channels = 1;
@@ -387,8 +539,45 @@ impl JxlEncoder<'_, '_> {
         })
     }
 
-    // Add a frame
-    fn add_frame<T: PixelType>(&self, frame: &EncoderFrame<T>) -> Result<(), EncodeError> {
+    // Add a frame, positioned and blended as `layer` if it is a layer of a still image
+    fn add_frame<T: PixelType>(
+        &self,
+        frame: &EncoderFrame<T>,
+        layer: Option<JxlLayerInfo>,
+    ) -> Result<(), EncodeError> {
+        // The header is kept by the frame settings, so reset it for every frame
+        let mut header = unsafe {
+            let mut header = MaybeUninit::uninit();
+            JxlEncoderInitFrameHeader(header.as_mut_ptr());
+            header.assume_init()
+        };
+        // Layers are shown at once, without duration
+        if let Some(layer) = layer {
+            header.layer_info = layer;
+        } else {
+            header.duration = frame.duration.unwrap_or_default();
+        }
+
+        self.check_enc_status(unsafe {
+            JxlEncoderSetFrameHeader(self.options_ptr, std::ptr::from_ref(&header))
+        })?;
+        if self.has_alpha {
+            self.check_enc_status(unsafe {
+                JxlEncoderSetExtraChannelBlendInfo(
+                    self.options_ptr,
+                    0,
+                    std::ptr::from_ref(&header.layer_info.blend_info),
+                )
+            })?;
+        }
+
+        // The name is kept by the frame settings, so reset it for unnamed frames
+        let name =
+            CString::new(frame.name.unwrap_or_default()).map_err(|_| EncodeError::BadInput)?;
+        self.check_enc_status(unsafe {
+            JxlEncoderSetFrameName(self.options_ptr, name.as_ptr().cast())
+        })?;
+
         self.check_enc_status(unsafe {
             JxlEncoderAddImageFrame(
                 self.options_ptr,
@@ -408,42 +597,124 @@ impl JxlEncoder<'_, '_> {
                 std::mem::size_of_val(data),
             )
         })
+        .map_err(|e| match e {
+            EncodeError::BadInput | EncodeError::NotSupported => EncodeError::JpegNotSupported,
+            e => e,
+        })
     }
 
-    fn _internal(&mut self) -> Result<Vec<u8>, EncodeError> {
-        unsafe { JxlEncoderCloseInput(self.enc) };
-
-        let mut buffer = vec![0; self.init_buffer_size];
-        let mut next_out = buffer.as_mut_ptr().cast();
-        let mut avail_out = buffer.len();
+    // Write the output available so far after the `used` bytes of `buffer`, growing it as needed
+    fn process_output(&self, buffer: &mut Vec<u8>, used: &mut usize) -> Result<(), EncodeError> {
+        if *used == buffer.len() {
+            buffer.resize(buffer.len() * 2, 0);
+        }
 
-        let mut status;
         loop {
-            status = unsafe { JxlEncoderProcessOutput(self.enc, &mut next_out, &mut avail_out) };
+            let mut next_out = unsafe { buffer.as_mut_ptr().add(*used) };
+            let mut avail_out = buffer.len() - *used;
+            let status =
+                unsafe { JxlEncoderProcessOutput(self.enc, &mut next_out, &mut avail_out) };
+            *used = buffer.len() - avail_out;
 
             if status != JxlEncoderStatus::NeedMoreOutput {
-                break;
-            }
-
-            unsafe {
-                let offset = next_out.offset_from(buffer.as_ptr());
-                debug_assert!(offset >= 0);
-
-                buffer.resize(buffer.len() * 2, 0);
-                next_out = buffer.as_mut_ptr().offset(offset);
-                avail_out = buffer.len().wrapping_add_signed(-offset);
+                return self.check_enc_status(status);
             }
+            buffer.resize(buffer.len() * 2, 0);
         }
-        buffer.truncate(next_out as usize - buffer.as_ptr() as usize);
-        self.check_enc_status(status)?;
+    }
 
+    // Reset the encoder for the next image, recreating the frame settings it owned
+    fn reset(&mut self) {
         unsafe { JxlEncoderReset(self.enc) };
         self.options_ptr = unsafe { JxlEncoderFrameSettingsCreate(self.enc, null()) };
+    }
+
+    // Close the input, write the rest of the output and reset the encoder
+    fn finish(&mut self, mut buffer: Vec<u8>, mut used: usize) -> Result<Vec<u8>, EncodeError> {
+        unsafe { JxlEncoderCloseInput(self.enc) };
+
+        let res = self.process_output(&mut buffer, &mut used);
+        buffer.truncate(used);
+        self.reset();
+        res?;
 
         buffer.shrink_to_fit();
         Ok(buffer)
     }
 
+    fn _internal(&mut self) -> Result<Vec<u8>, EncodeError> {
+        let buffer = vec![0; self.init_buffer_size];
+        self.finish(buffer, 0)
+    }
+
+    // Encode a single frame with the current options
+    fn encode_once<T: PixelType, U: PixelType>(
+        &mut self,
+        frame: &EncoderFrame<T>,
+        width: u32,
+        height: u32,
+    ) -> Result<EncoderResult<U>, EncodeError> {
+        let res = self
+            .setup_encoder(width, height, U::bits_per_sample(), self.has_alpha)
+            .and_then(|()| self.add_frame(frame, None));
+        if let Err(e) = res {
+            self.reset();
+            return Err(e);
+        }
+        self.start_encoding::<U>()
+    }
+
+    // Search the quality to get the output size closest to the target
+    #[allow(clippy::manual_midpoint)] // `f32::midpoint` needs Rust 1.85
+    fn encode_to_size<T: PixelType, U: PixelType>(
+        &mut self,
+        frame: &EncoderFrame<T>,
+        width: u32,
+        height: u32,
+        target: usize,
+    ) -> Result<EncoderResult<U>, EncodeError> {
+        const ATTEMPTS: usize = 8;
+        const TOLERANCE: usize = 20; // 1/20 = 5%
+
+        let quality = self.quality;
+        let (mut low, mut high) = (0.0, 15.0);
+        let mut best: Option<(bool, EncoderResult<U>)> = None;
+
+        let mut search = || {
+            for _ in 0..ATTEMPTS {
+                self.quality = (low + high) / 2.0;
+                let res = self.encode_once::<T, U>(frame, width, height)?;
+
+                let fits = res.len() <= target;
+                if fits {
+                    high = self.quality;
+                } else {
+                    low = self.quality;
+                }
+
+                let done = fits && res.len() >= target - target / TOLERANCE;
+                let better = match &best {
+                    None => true,
+                    Some((true, b)) => fits && res.len() > b.len(),
+                    Some((false, b)) => fits || res.len() < b.len(),
+                };
+                if better {
+                    best = Some((fits, res));
+                }
+                if done {
+                    break;
+                }
+            }
+            Ok::<_, EncodeError>(())
+        };
+        let res = search();
+        self.quality = quality;
+        res?;
+
+        // Safety: there is at least one attempt
+        Ok(unsafe { best.unwrap_unchecked() }.1)
+    }
+
     // Start encoding
     fn start_encoding<U: PixelType>(&mut self) -> Result<EncoderResult<U>, EncodeError> {
         Ok(EncoderResult {
@@ -484,10 +755,15 @@ impl<'prl, 'mm> JxlEncoder<'prl, 'mm> {
 
     /// Encode a JPEG XL image from existing raw JPEG data
     ///
+    /// The JPEG reconstruction metadata is stored, so the original JPEG can be reconstructed
+    /// byte-for-byte with [`JxlDecoder::reconstruct`](crate::decode::JxlDecoder::reconstruct)
+    /// or [`JxlDecoder::decode_to_jpeg`](crate::decode::JxlDecoder::decode_to_jpeg).
+    ///
     /// Note: Only support output pixel type of `u8`. Ignore alpha channel settings
     ///
     /// # Errors
-    /// Return [`EncodeError`] if the internal encoder fails to encode
+    /// Return [`EncodeError::JpegNotSupported`] if the JPEG cannot be losslessly transcoded,
+    /// or [`EncodeError`] if the internal encoder fails to encode
     pub fn encode_jpeg(&mut self, data: &[u8]) -> Result<EncoderResult<u8>, EncodeError> {
         if let Some(runner) = self.parallel_runner {
             unsafe {
@@ -501,7 +777,8 @@ impl<'prl, 'mm> JxlEncoder<'prl, 'mm> {
 
         self.set_options()?;
 
-        // If using container format, store JPEG reconstruction metadata
+        // Store JPEG reconstruction metadata, which needs the container format
+        self.check_enc_status(unsafe { JxlEncoderUseContainer(self.enc, true) })?;
         self.check_enc_status(unsafe { JxlEncoderStoreJPEGMetadata(self.enc, true) })?;
 
         self.add_jpeg_frame(data)?;
@@ -520,9 +797,42 @@ impl<'prl, 'mm> JxlEncoder<'prl, 'mm> {
         width: u32,
         height: u32,
     ) -> Result<EncoderResult<U>, EncodeError> {
+        self.encode_frame(&EncoderFrame::new(data), width, height)
+    }
+
+    /// Encode an animation from an iterator of `(pixels, duration)` frames, see
+    /// [`JxlEncoderBuilder::animation`]. The frames are pulled lazily and the output is written
+    /// after each of them, so only one frame is held in memory at a time.
+    ///
+    /// The end of the iterator marks the last frame, and the pixels are handled as in
+    /// [`JxlEncoder::encode`].
+    ///
+    /// # Errors
+    /// Return [`EncodeError`] if the internal encoder fails to encode
+    pub fn encode_frames_iter<T, U, I>(
+        &mut self,
+        frames: I,
+        width: u32,
+        height: u32,
+    ) -> Result<EncoderResult<U>, EncodeError>
+    where
+        T: PixelType,
+        U: PixelType,
+        I: IntoIterator<Item = (Vec<T>, u32)>,
+    {
         self.setup_encoder(width, height, U::bits_per_sample(), self.has_alpha)?;
-        self.add_frame(&EncoderFrame::new(data))?;
-        self.start_encoding::<U>()
+
+        let mut buffer = vec![0; self.init_buffer_size];
+        let mut used = 0;
+        for (data, duration) in frames {
+            self.add_frame(&EncoderFrame::new(&data).duration(duration), None)?;
+            self.process_output(&mut buffer, &mut used)?;
+        }
+
+        Ok(EncoderResult {
+            data: self.finish(buffer, used)?,
+            _pixel_type: PhantomData,
+        })
     }
 
     /// Encode a JPEG XL image from a frame. See [`EncoderFrame`] for custom options of the original pixels.
@@ -535,9 +845,61 @@ impl<'prl, 'mm> JxlEncoder<'prl, 'mm> {
         width: u32,
         height: u32,
     ) -> Result<EncoderResult<U>, EncodeError> {
-        self.setup_encoder(width, height, U::bits_per_sample(), self.has_alpha)?;
-        self.add_frame(frame)?;
-        self.start_encoding::<U>()
+        match self.target_size {
+            Some(target) if !self.lossless => self.encode_to_size(frame, width, height, target),
+            _ => self.encode_once(frame, width, height),
+        }
+    }
+
+    /// Encode a JPEG XL image from a frame as [`JxlEncoder::encode_frame`], writing the output
+    /// to `writer` as it is produced instead of collecting it, e.g. to a file or a socket.
+    /// At most 64 KiB of output are buffered. `target_size` is unused, as it needs several
+    /// encodings. Decoding from a reader is done with
+    /// [`JxlDecoder::decode_reader`](crate::decode::JxlDecoder::decode_reader).
+    ///
+    /// # Errors
+    /// Return [`EncodeError::Io`] if it fails to write the output,
+    /// or [`EncodeError`] if the internal encoder fails to encode
+    pub fn encode_to_writer<T: PixelType, U: PixelType, W: Write>(
+        &mut self,
+        frame: &EncoderFrame<T>,
+        width: u32,
+        height: u32,
+        mut writer: W,
+    ) -> Result<(), EncodeError> {
+        const CHUNK_SIZE: usize = 64 * 1024;
+
+        let mut write = || {
+            self.setup_encoder(width, height, U::bits_per_sample(), self.has_alpha)?;
+            self.add_frame(frame, None)?;
+            unsafe { JxlEncoderCloseInput(self.enc) };
+
+            let mut buffer = vec![0u8; CHUNK_SIZE];
+            loop {
+                let mut next_out = buffer.as_mut_ptr();
+                let mut avail_out = buffer.len();
+                let status = unsafe {
+                    JxlEncoderProcessOutput(
+                        self.enc,
+                        std::ptr::from_mut(&mut next_out),
+                        std::ptr::from_mut(&mut avail_out),
+                    )
+                };
+                writer.write_all(&buffer[..buffer.len() - avail_out])?;
+
+                if status != JxlEncoderStatus::NeedMoreOutput {
+                    self.check_enc_status(status)?;
+                    break;
+                }
+            }
+            writer.flush()?;
+            Ok(())
+        };
+        let res = write();
+
+        // Reset even on errors, or the next encoding would continue this one
+        self.reset();
+        res
     }
 }
 
@@ -558,7 +920,45 @@ impl<U: PixelType> MultiFrames<'_, '_, '_, U> {
     /// # Errors
     /// Return [`EncodeError`] if the internal encoder fails to add a frame
     pub fn add_frame<T: PixelType>(self, frame: &EncoderFrame<T>) -> Result<Self, EncodeError> {
-        self.0.add_frame(frame)?;
+        self.0.add_frame(frame, None)?;
+        Ok(self)
+    }
+
+    /// Add a layer to a still image, with its top-left corner at `(x, y)` in the image,
+    /// and `(width, height)` pixels blended with the layers below as `blend_mode`.
+    ///
+    /// Unlike frames of an animation, added with [`add_frame`](Self::add_frame), layers have no
+    /// duration and are not shown one after the other: decoders merge them into the single
+    /// displayed image, or return them as they are with coalescing disabled, like the layers
+    /// of an image editor. The layers may be smaller than the image, and lie partly outside.
+    ///
+    /// # Errors
+    /// Return [`EncodeError`] if the internal encoder fails to add the layer,
+    /// e.g. if the pixels are not `width` by `height`
+    pub fn add_layer<T: PixelType>(
+        self,
+        frame: &EncoderFrame<T>,
+        (x, y): (i32, i32),
+        (width, height): (u32, u32),
+        blend_mode: BlendMode,
+    ) -> Result<Self, EncodeError> {
+        let mut blend_info = unsafe {
+            let mut info = MaybeUninit::uninit();
+            JxlEncoderInitBlendInfo(info.as_mut_ptr());
+            info.assume_init()
+        };
+        blend_info.blendmode = blend_mode;
+
+        let layer = JxlLayerInfo {
+            have_crop: JxlBool::True,
+            crop_x0: x,
+            crop_y0: y,
+            xsize: width,
+            ysize: height,
+            blend_info,
+            save_as_reference: 0,
+        };
+        self.0.add_frame(frame, Some(layer))?;
         Ok(self)
     }
 
@@ -584,6 +984,61 @@ pub fn encoder_builder<'prl, 'mm>() -> JxlEncoderBuilder<'prl, 'mm> {
     JxlEncoderBuilder::default()
 }
 
+/// Encode a batch of images, each given as `(pixels, width, height)`, into separate JPEG XL files
+/// concurrently, and return the results in the same order as the inputs.
+///
+/// `settings` is called on the worker threads to create a fresh encoder for every image,
+/// e.g. `|| encoder_builder().speed(EncoderSpeed::Lightning).build()`.
+/// The pixels are encoded as in [`JxlEncoder::encode`], with the same bit depth as the input.
+///
+/// # Concurrency
+/// At most `threads` images are encoded at the same time, on scoped threads.
+/// If `threads` is 0, the available parallelism of the system is used.
+/// Setting a parallel runner in `settings` additionally parallelizes the encoding of each image.
+///
+/// # Panics
+/// Panic if `settings` or the encoding panics on one of the threads
+pub fn encode_batch<'prl, 'mm, T, F>(
+    inputs: &[(Vec<T>, u32, u32)],
+    threads: usize,
+    settings: F,
+) -> Vec<Result<Vec<u8>, EncodeError>>
+where
+    T: PixelType + Sync,
+    F: Fn() -> Result<JxlEncoder<'prl, 'mm>, EncodeError> + Sync,
+{
+    let threads = match threads {
+        0 => std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get),
+        n => n,
+    }
+    .min(inputs.len());
+
+    let next = AtomicUsize::new(0);
+    let encode = || {
+        let mut results = vec![];
+        loop {
+            let i = next.fetch_add(1, Ordering::Relaxed);
+            let Some((data, width, height)) = inputs.get(i) else {
+                return results;
+            };
+            let res = settings()
+                .and_then(|mut encoder| encoder.encode::<T, T>(data, *width, *height))
+                .map(|res| res.data);
+            results.push((i, res));
+        }
+    };
+
+    let mut results: Vec<_> = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..threads).map(|_| s.spawn(encode)).collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().expect("encoding thread panicked"))
+            .collect()
+    });
+    results.sort_unstable_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, res)| res).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -21,6 +21,8 @@ use thiserror::Error;
 
 use jpegxl_sys::{decode::JxlDecoderStatus, encode::JxlEncoderError};
 
+use crate::decode::ColorType;
+
 /// Errors derived from [`JxlDecoderStatus`]
 #[derive(Error, Debug)]
 #[non_exhaustive]
@@ -29,6 +31,12 @@ pub enum DecodeError {
     #[error("Cannot create a decoder")]
     CannotCreateDecoder,
     /// Unknown Error
+    ///
+    /// `libjxl` reports every failure while decoding, e.g. corrupt data or a failed
+    /// allocation, with the same status, so they cannot be told apart. A debug build of libjxl
+    /// outputs a specific error message. Failures detected before or around `libjxl`
+    /// have their own variants, e.g. [`DecodeError::InvalidInput`] for a wrong signature,
+    /// or [`DecodeError::CodestreamTruncated`] for a truncated input.
     #[error("Generic Error")]
     GenericError,
     /// Invalid input
@@ -37,6 +45,129 @@ pub enum DecodeError {
     /// Unsupported Pixel bit width
     #[error("Unsupported Pixel bit width: {0}")]
     UnsupportedBitWidth(u32),
+    /// The overridden number of color channels is neither 1 nor 3
+    #[error("Unsupported number of color channels: {0}")]
+    UnsupportedColorChannels(u32),
+    /// The requested number of channels per pixel cannot be output for the color channels of
+    /// the image, e.g. grayscale for a color image
+    #[error("Cannot output {requested} channels per pixel from {color_channels} color channels")]
+    UnsupportedChannelLayout {
+        /// Number of channels per pixel of the output
+        requested: u32,
+        /// Number of color channels of the image
+        color_channels: u32,
+    },
+    /// The requested number of channels per pixel doesn't match the output pixel format
+    #[error("Expected {expected} channels per pixel, but the output has {actual}")]
+    ChannelMismatch {
+        /// Number of channels requested by the caller
+        expected: usize,
+        /// Number of channels of the output pixel format
+        actual: u32,
+    },
+    /// The dimensions of the image differ from the expected ones
+    #[error("Expected an image of {expected:?}, but got {actual:?}")]
+    UnexpectedDimensions {
+        /// Expected `(width, height)`
+        expected: (u32, u32),
+        /// Actual `(width, height)` of the image
+        actual: (u32, u32),
+    },
+    /// The color type of the image differs from the required one
+    #[error("Expected a {expected} image, but got {actual}")]
+    UnexpectedColorType {
+        /// Required color type
+        expected: ColorType,
+        /// Actual color type of the image
+        actual: ColorType,
+    },
+    /// The image has more pixels than allowed by `max_pixels`
+    #[error("The image has {pixels} pixels, more than the maximum of {max}")]
+    TooManyPixels {
+        /// Number of pixels of the image
+        pixels: u64,
+        /// Maximum number of pixels of an image
+        max: u64,
+    },
+    /// The region to decode is empty or not within the image
+    #[error("The region {region:?} is not within the image of {image:?}")]
+    RegionOutOfBounds {
+        /// Requested `(x, y, width, height)`
+        region: (u32, u32, u32, u32),
+        /// `(width, height)` of the image
+        image: (u32, u32),
+    },
+    /// The output buffer given by the caller is too small for the pixels
+    #[error("The output buffer needs {required} bytes, but has {actual}")]
+    BufferTooSmall {
+        /// Number of bytes of the pixels
+        required: usize,
+        /// Length of the output buffer
+        actual: usize,
+    },
+    /// The pixels of the image need a larger buffer than allowed by `max_buffer_bytes`
+    #[error("The image needs a buffer of {requested} bytes, more than the maximum of {max}")]
+    ImageTooLarge {
+        /// Number of bytes of the pixels
+        requested: u64,
+        /// Maximum number of bytes of a buffer
+        max: u64,
+    },
+    /// The image has fewer frames than the index of the requested frame
+    #[error("No frame at index {index}, the image has {count} frames")]
+    FrameIndexOutOfRange {
+        /// Index of the requested frame
+        index: usize,
+        /// Number of frames of the image
+        count: usize,
+    },
+    /// The color model of the image is not supported, e.g. CMYK
+    #[error("Unsupported color model")]
+    UnsupportedColorModel,
+    /// The container has a box unknown to the specification, see `strict_boxes`
+    #[error("Unknown box: {:?}", String::from_utf8_lossy(.0))]
+    UnknownBox([u8; 4]),
+    /// The ICC profile disagrees with the color encoding signaled in the codestream
+    #[error("The ICC profile is inconsistent with the color encoding")]
+    InconsistentColorProfile,
+    /// The ICC profile is larger than allowed by `max_icc_bytes`
+    #[error("The ICC profile has {size} bytes, more than the maximum of {max}")]
+    IccProfileTooLarge {
+        /// Number of bytes of the profile
+        size: usize,
+        /// Maximum number of bytes of a profile
+        max: usize,
+    },
+    /// The image has no JPEG reconstruction data, i.e. it was not transcoded from a JPEG
+    #[error("The image cannot be reconstructed to JPEG")]
+    NoJpegReconstruction,
+    /// The decoding was cancelled
+    #[error("The decoding was cancelled")]
+    Cancelled,
+    /// The decoder needs more input, i.e. the input is truncated
+    #[error("The decoder needs more input")]
+    NeedMoreInput,
+    /// The input is cut in the codestream. For a bare codestream, this is detected by decoding
+    /// it again without marking the end of the input, which makes the decoder ask for more
+    #[error("The codestream is truncated")]
+    CodestreamTruncated,
+    /// The container is cut outside of the codestream, e.g. in a metadata box or between boxes,
+    /// or lacks the boxes holding the rest of the codestream.
+    /// This is detected by walking the boxes of the container
+    #[error("The container is incomplete")]
+    ContainerIncomplete,
+    /// The buffer for the contents of a box is too small
+    #[error("The box output buffer is too small")]
+    BoxNeedMoreOutput,
+    /// The buffer for the reconstructed JPEG is too small
+    #[error("The JPEG output buffer is too small")]
+    JpegNeedMoreOutput,
+    /// The decoder needs a buffer for the preview image
+    #[error("The decoder needs a preview output buffer")]
+    NeedPreviewOutBuffer,
+    /// Failed to read the input
+    #[error("Failed to read the input: {0}")]
+    Io(#[from] std::io::Error),
     /// Unknown status
     #[error("Unknown status: `{0:?}`")]
     UnknownStatus(JxlDecoderStatus),
@@ -64,12 +195,26 @@ pub enum EncodeError {
     /// JPEG bitstream reconstruction data could not be represented (e.g. too much tail data)
     #[error("JPEG bitstream reconstruction data could not be represented")]
     Jbrd,
+    /// The JPEG data cannot be losslessly transcoded, either because it is corrupt or because it
+    /// uses features unsupported by the JPEG reconstruction (e.g. arithmetic coding or unusual
+    /// chroma subsampling)
+    #[error("The JPEG cannot be losslessly transcoded")]
+    JpegNotSupported,
+    /// The quality is not a butteraugli distance between 0 and 15
+    #[error("Invalid quality: {0}, expected a distance between 0 and 15")]
+    InvalidQuality(f32),
+    /// The decoding speed tier is not between 0 and 4
+    #[error("Invalid decoding speed: {0}, expected a tier between 0 and 4")]
+    InvalidDecodingSpeed(i64),
     /// Input is invalid (e.g. corrupt JPEG file or ICC profile)
     #[error("Input is invalid")]
     BadInput,
     /// The encoder API is used in an incorrect way. In this case, a debug build of libjxl should output a specific error message
     #[error("The encoder API is used in an incorrect way")]
     ApiUsage,
+    /// Failed to write the output
+    #[error("Failed to write the output: {0}")]
+    Io(#[from] std::io::Error),
     /// Unknown status
     #[error("Unknown status: `{0:?}`")]
     UnknownStatus(JxlEncoderError),
@@ -79,8 +224,20 @@ pub enum EncodeError {
 pub(crate) fn check_dec_status(status: JxlDecoderStatus) -> Result<(), DecodeError> {
     match status {
         JxlDecoderStatus::Success => Ok(()),
-        JxlDecoderStatus::Error => Err(DecodeError::GenericError),
-        _ => Err(DecodeError::UnknownStatus(status)),
+        _ => Err(from_status(status)),
+    }
+}
+
+/// Map a status the decoder cannot handle to a [`DecodeError`], falling back to
+/// [`DecodeError::UnknownStatus`] for events that were not subscribed to
+pub(crate) fn from_status(status: JxlDecoderStatus) -> DecodeError {
+    match status {
+        JxlDecoderStatus::Error => DecodeError::GenericError,
+        JxlDecoderStatus::NeedMoreInput => DecodeError::NeedMoreInput,
+        JxlDecoderStatus::BoxNeedMoreOutput => DecodeError::BoxNeedMoreOutput,
+        JxlDecoderStatus::JpegNeedMoreOutput => DecodeError::JpegNeedMoreOutput,
+        JxlDecoderStatus::NeedPreviewOutBuffer => DecodeError::NeedPreviewOutBuffer,
+        _ => DecodeError::UnknownStatus(status),
     }
 }
 
@@ -104,7 +261,7 @@ mod tests {
         ));
         assert!(matches!(
             decoder.decode(&crate::tests::SAMPLE_JXL[..100]),
-            Err(DecodeError::GenericError)
+            Err(DecodeError::CodestreamTruncated)
         ));
 
         assert!(matches!(
@@ -120,6 +277,47 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn statuses() {
+        use jpegxl_sys::decode::{
+            JxlDecoderCloseInput, JxlDecoderCreate, JxlDecoderDestroy, JxlDecoderProcessInput,
+            JxlDecoderSetInput, JxlDecoderSubscribeEvents,
+        };
+
+        let data = &crate::tests::SAMPLE_JXL[..100];
+        let process = |close: bool| unsafe {
+            let dec = JxlDecoderCreate(std::ptr::null());
+            JxlDecoderSubscribeEvents(dec, JxlDecoderStatus::FullImage as i32);
+            JxlDecoderSetInput(dec, data.as_ptr(), data.len());
+            if close {
+                JxlDecoderCloseInput(dec);
+            }
+            let status = JxlDecoderProcessInput(dec);
+            JxlDecoderDestroy(dec);
+            status
+        };
+
+        // Truncated input, which may still be completed
+        assert!(matches!(
+            from_status(process(false)),
+            DecodeError::NeedMoreInput
+        ));
+        // Truncated input, once closed
+        assert!(matches!(
+            from_status(process(true)),
+            DecodeError::GenericError
+        ));
+
+        assert!(matches!(
+            from_status(JxlDecoderStatus::BoxNeedMoreOutput),
+            DecodeError::BoxNeedMoreOutput
+        ));
+        assert!(matches!(
+            from_status(JxlDecoderStatus::PreviewImage),
+            DecodeError::UnknownStatus(JxlDecoderStatus::PreviewImage)
+        ));
+    }
+
     #[test]
     #[cfg_attr(coverage_nightly, coverage(off))]
     fn encode_invalid_data() -> TestResult {
@@ -21,13 +21,18 @@ along with jpegxl-rs.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::mem::MaybeUninit;
 
-use image::{DynamicImage, ImageBuffer};
+use image::{
+    error::{DecodingError, ImageFormatHint},
+    imageops, ColorType, DynamicImage, ImageBuffer, ImageDecoder, ImageError, ImageResult,
+    RgbaImage,
+};
 use jpegxl_sys::types::{JxlDataType, JxlPixelFormat};
 
 use crate::{
     common::PixelType,
     decode::{JxlDecoder, Metadata},
-    DecodeError,
+    encode::{ColorEncoding, EncoderFrame, EncoderResult, JxlEncoder},
+    DecodeError, EncodeError,
 };
 
 /// Extension trait for [`JxlDecoder`]
@@ -48,6 +53,28 @@ pub trait ToDynamic {
         &self,
         data: &[u8],
     ) -> Result<Option<DynamicImage>, DecodeError>;
+
+    /// Decode JPEG XL images and composite their thumbnails into a grid, e.g. a contact sheet
+    ///
+    /// The images fill the grid row by row, `columns` per row. Each thumbnail is downscaled to
+    /// fit a square cell of `cell_size` pixels, preserving its aspect ratio, and is centered in
+    /// it. Cells are separated by `spacing` transparent pixels, which also surround the grid, so
+    /// the sheet is `columns * cell_size + (columns + 1) * spacing` pixels wide, and likewise
+    /// high for its rows. A decoded image not representable as a [`DynamicImage`] leaves its
+    /// cell empty.
+    ///
+    /// # Panics
+    /// Panic if `columns` is 0
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoding of any image fails
+    fn decode_to_grid(
+        &self,
+        images: &[&[u8]],
+        columns: u32,
+        cell_size: u32,
+        spacing: u32,
+    ) -> Result<RgbaImage, DecodeError>;
 }
 
 impl<'pr, 'mm> ToDynamic for JxlDecoder<'pr, 'mm> {
@@ -61,6 +88,7 @@ impl<'pr, 'mm> ToDynamic for JxlDecoder<'pr, 'mm> {
             None,
             pixel_format.as_mut_ptr(),
             &mut buffer,
+            None,
         )?;
 
         let pixel_format = unsafe { pixel_format.assume_init() };
@@ -80,11 +108,220 @@ impl<'pr, 'mm> ToDynamic for JxlDecoder<'pr, 'mm> {
             None,
             pixel_format.as_mut_ptr(),
             &mut buffer,
+            None,
         )?;
 
         let pixel_format = unsafe { pixel_format.assume_init() };
         Ok(to_image(metadata, &pixel_format, buffer))
     }
+
+    fn decode_to_grid(
+        &self,
+        images: &[&[u8]],
+        columns: u32,
+        cell_size: u32,
+        spacing: u32,
+    ) -> Result<RgbaImage, DecodeError> {
+        assert!(columns > 0, "a grid needs at least one column");
+
+        let rows = u32::try_from(images.len())
+            .map_err(|_| DecodeError::GenericError)?
+            .div_ceil(columns);
+        let mut sheet = RgbaImage::new(
+            columns * cell_size + (columns + 1) * spacing,
+            rows * cell_size + (rows + 1) * spacing,
+        );
+
+        for ((column, row), data) in (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .zip(images)
+        {
+            let Some(image) = self.decode_to_image_with::<u16>(data)? else {
+                continue;
+            };
+            let thumbnail = image.thumbnail(cell_size, cell_size).to_rgba8();
+
+            let x = spacing + column * (cell_size + spacing) + (cell_size - thumbnail.width()) / 2;
+            let y = spacing + row * (cell_size + spacing) + (cell_size - thumbnail.height()) / 2;
+            imageops::replace(&mut sheet, &thumbnail, x.into(), y.into());
+        }
+
+        Ok(sheet)
+    }
+}
+
+/// [`ImageDecoder`] of a JPEG XL image, for the APIs of the `image` crate taking a decoder, e.g.
+/// [`DynamicImage::from_decoder`]
+///
+/// The pixel layout is picked from the basic info and the options of the decoder: 8-bit and
+/// 16-bit samples for integer images, and 32-bit floats for the others. Grayscale floating point
+/// images, which `image` cannot represent, are decoded to 16-bit samples.
+pub struct JxlImageDecoder<'a, 'pr, 'mm> {
+    decoder: &'a JxlDecoder<'pr, 'mm>,
+    data: &'a [u8],
+    dimensions: (u32, u32),
+    data_type: JxlDataType,
+    color_type: ColorType,
+}
+
+impl<'a, 'pr, 'mm> JxlImageDecoder<'a, 'pr, 'mm> {
+    /// Read the basic info of `data` to create a decoder of it, without decoding the pixels
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails, or
+    /// [`DecodeError::UnsupportedColorChannels`] if the output has a number of channels
+    /// `image` cannot represent
+    pub fn new(decoder: &'a JxlDecoder<'pr, 'mm>, data: &'a [u8]) -> Result<Self, DecodeError> {
+        let (dimensions, format) = decoder.with_basic_info(data, |info| {
+            let data_type = match (info.bits_per_sample, info.exponent_bits_per_sample) {
+                (0..=8, 0) => JxlDataType::Uint8,
+                (_, 0) => JxlDataType::Uint16,
+                _ => JxlDataType::Float,
+            };
+            let mut format = decoder.output_format(info, Some(data_type))?;
+            if format.data_type == JxlDataType::Float && format.num_channels < 3 {
+                format = decoder.output_format(info, Some(JxlDataType::Uint16))?;
+            }
+            Ok(((info.xsize, info.ysize), format))
+        })?;
+
+        let color_type = match (format.data_type, format.num_channels) {
+            (JxlDataType::Uint8, 1) => ColorType::L8,
+            (JxlDataType::Uint8, 2) => ColorType::La8,
+            (JxlDataType::Uint8, 3) => ColorType::Rgb8,
+            (JxlDataType::Uint8, 4) => ColorType::Rgba8,
+            (JxlDataType::Uint16, 1) => ColorType::L16,
+            (JxlDataType::Uint16, 2) => ColorType::La16,
+            (JxlDataType::Uint16, 3) => ColorType::Rgb16,
+            (JxlDataType::Uint16, 4) => ColorType::Rgba16,
+            (JxlDataType::Float, 3) => ColorType::Rgb32F,
+            (JxlDataType::Float, 4) => ColorType::Rgba32F,
+            (_, n) => return Err(DecodeError::UnsupportedColorChannels(n)),
+        };
+
+        Ok(Self {
+            decoder,
+            data,
+            dimensions,
+            data_type: format.data_type,
+            color_type,
+        })
+    }
+}
+
+impl ImageDecoder for JxlImageDecoder<'_, '_, '_> {
+    fn dimensions(&self) -> (u32, u32) {
+        self.dimensions
+    }
+
+    fn color_type(&self) -> ColorType {
+        self.color_type
+    }
+
+    fn read_image(self, buf: &mut [u8]) -> ImageResult<()> {
+        assert_eq!(u64::try_from(buf.len()), Ok(self.total_bytes()));
+
+        let decoded = match self.data_type {
+            JxlDataType::Uint8 => self
+                .decoder
+                .decode_with::<u8>(self.data)
+                .map(|(_, data)| data),
+            JxlDataType::Uint16 => self
+                .decoder
+                .decode_with::<u16>(self.data)
+                .map(|(_, data)| data.iter().flat_map(|v| v.to_ne_bytes()).collect()),
+            _ => self
+                .decoder
+                .decode_with::<f32>(self.data)
+                .map(|(_, data)| data.iter().flat_map(|v| v.to_ne_bytes()).collect()),
+        };
+        let decoded = decoded.map_err(decoding_error)?;
+
+        // Rows padded by `align` are not representable
+        if decoded.len() != buf.len() {
+            return Err(decoding_error(DecodeError::BufferTooSmall {
+                required: decoded.len(),
+                actual: buf.len(),
+            }));
+        }
+        buf.copy_from_slice(&decoded);
+
+        Ok(())
+    }
+
+    fn read_image_boxed(self: Box<Self>, buf: &mut [u8]) -> ImageResult<()> {
+        (*self).read_image(buf)
+    }
+}
+
+/// Extension trait for [`JxlEncoder`]
+pub trait FromDynamic {
+    /// Encode a [`DynamicImage`] to JPEG XL, picking the number of channels, the alpha channel
+    /// and the bit depth of the output from its color type. Grayscale images are encoded with
+    /// a luma color encoding, which is linear if the one of the encoder is.
+    ///
+    /// The `has_alpha` and `color_encoding` options of the encoder are only changed for this
+    /// encoding, the other ones are used as they are.
+    ///
+    /// # Errors
+    /// Return [`EncodeError::NotSupported`] for color types unknown to this crate,
+    /// or [`EncodeError`] if the internal encoder fails to encode
+    fn encode_image(&mut self, image: &DynamicImage) -> Result<Vec<u8>, EncodeError>;
+}
+
+impl FromDynamic for JxlEncoder<'_, '_> {
+    fn encode_image(&mut self, image: &DynamicImage) -> Result<Vec<u8>, EncodeError> {
+        let size = (image.width(), image.height());
+        match image {
+            DynamicImage::ImageLuma8(i) => encode_pixels(self, i.as_raw(), 1, size),
+            DynamicImage::ImageLumaA8(i) => encode_pixels(self, i.as_raw(), 2, size),
+            DynamicImage::ImageRgb8(i) => encode_pixels(self, i.as_raw(), 3, size),
+            DynamicImage::ImageRgba8(i) => encode_pixels(self, i.as_raw(), 4, size),
+            DynamicImage::ImageLuma16(i) => encode_pixels(self, i.as_raw(), 1, size),
+            DynamicImage::ImageLumaA16(i) => encode_pixels(self, i.as_raw(), 2, size),
+            DynamicImage::ImageRgb16(i) => encode_pixels(self, i.as_raw(), 3, size),
+            DynamicImage::ImageRgba16(i) => encode_pixels(self, i.as_raw(), 4, size),
+            DynamicImage::ImageRgb32F(i) => encode_pixels(self, i.as_raw(), 3, size),
+            DynamicImage::ImageRgba32F(i) => encode_pixels(self, i.as_raw(), 4, size),
+            _ => Err(EncodeError::NotSupported),
+        }
+    }
+}
+
+/// Encode `data` of `num_channels` interleaved channels with an output of the same bit depth,
+/// restoring the options of `encoder` changed for it
+fn encode_pixels<T: PixelType>(
+    encoder: &mut JxlEncoder,
+    data: &[T],
+    num_channels: u32,
+    (width, height): (u32, u32),
+) -> Result<Vec<u8>, EncodeError> {
+    let (has_alpha, color_encoding) = (encoder.has_alpha, encoder.color_encoding);
+
+    let linear = matches!(
+        color_encoding,
+        ColorEncoding::LinearSrgb | ColorEncoding::LinearSrgbLuma
+    );
+    encoder.has_alpha = matches!(num_channels, 2 | 4);
+    encoder.color_encoding = match (num_channels < 3, linear) {
+        (true, false) => ColorEncoding::SrgbLuma,
+        (true, true) => ColorEncoding::LinearSrgbLuma,
+        (false, false) => ColorEncoding::Srgb,
+        (false, true) => ColorEncoding::LinearSrgb,
+    };
+
+    let frame = EncoderFrame::new(data).num_channels(num_channels);
+    let res: Result<EncoderResult<T>, _> = encoder.encode_frame(&frame, width, height);
+    (encoder.has_alpha, encoder.color_encoding) = (has_alpha, color_encoding);
+
+    res.map(|res| res.data)
+}
+
+fn decoding_error(e: DecodeError) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Name("JPEG XL".to_owned()),
+        e,
+    ))
 }
 
 fn to_image(
@@ -92,6 +329,16 @@ fn to_image(
     pixel_format: &JxlPixelFormat,
     buffer: Vec<u8>,
 ) -> Option<DynamicImage> {
+    // Rows padded by `align` are not representable
+    let sample_size = match pixel_format.data_type {
+        JxlDataType::Uint8 => 1,
+        JxlDataType::Uint16 | JxlDataType::Float16 => 2,
+        JxlDataType::Float => 4,
+    };
+    if buffer.len() != (width * height * pixel_format.num_channels) as usize * sample_size {
+        return None;
+    }
+
     match (pixel_format.data_type, pixel_format.num_channels) {
         (JxlDataType::Float, 3) => {
             ImageBuffer::from_raw(width, height, f32::convert(&buffer, pixel_format))
@@ -228,4 +475,81 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn encode_image() -> TestResult {
+        use crate::encoder_builder;
+
+        let sample = image::load_from_memory_with_format(SAMPLE_PNG, image::ImageFormat::Png)?;
+        let decoder = decoder_builder().build()?;
+        let mut encoder = encoder_builder()
+            .lossless(true)
+            .uses_original_profile(true)
+            .build()?;
+
+        for image in [
+            DynamicImage::ImageRgba8(sample.to_rgba8()),
+            DynamicImage::ImageRgb16(sample.to_rgb16()),
+            DynamicImage::ImageLuma8(sample.to_luma8()),
+            DynamicImage::ImageLumaA16(sample.to_luma_alpha16()),
+        ] {
+            let data = encoder.encode_image(&image)?;
+            let roundtrip = decoder
+                .decode_to_image(&data)?
+                .expect("Failed to create DynamicImage");
+            assert_eq!(roundtrip.color(), image.color());
+            assert_eq!(roundtrip, image);
+        }
+
+        // The options are restored
+        assert!(!encoder.has_alpha);
+        assert!(matches!(encoder.color_encoding, ColorEncoding::Srgb));
+
+        Ok(())
+    }
+
+    #[test]
+    fn image_decoder() -> TestResult {
+        let decoder = decoder_builder().build()?;
+        let expected = decoder
+            .decode_to_image(SAMPLE_JXL)?
+            .expect("Failed to create DynamicImage");
+
+        let image_decoder = JxlImageDecoder::new(&decoder, SAMPLE_JXL)?;
+        assert_eq!(image_decoder.dimensions(), (40, 50));
+        assert_eq!(image_decoder.color_type(), expected.color());
+        let img = DynamicImage::from_decoder(image_decoder)?;
+        assert_eq!(img, expected);
+
+        let mut decoder = decoder_builder().build()?;
+        decoder.pixel_format = Some(PixelFormat {
+            num_channels: 1,
+            ..PixelFormat::default()
+        });
+        let image_decoder = JxlImageDecoder::new(&decoder, SAMPLE_JXL_GRAY)?;
+        assert!(matches!(
+            image_decoder.color_type(),
+            ColorType::L8 | ColorType::L16
+        ));
+        DynamicImage::from_decoder(image_decoder)?;
+
+        assert!(JxlImageDecoder::new(&decoder, &[]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn grid() -> TestResult {
+        let decoder = decoder_builder().build()?;
+
+        let sheet = decoder.decode_to_grid(&[SAMPLE_JXL, SAMPLE_JXL_GRAY], 2, 32, 4)?;
+        assert_eq!(sheet.dimensions(), (2 * 32 + 3 * 4, 32 + 2 * 4));
+        // The spacing is left transparent
+        assert_eq!(sheet.get_pixel(0, 0).0, [0; 4]);
+
+        let sheet = decoder.decode_to_grid(&[SAMPLE_JXL, SAMPLE_JXL_GRAY], 1, 32, 0)?;
+        assert_eq!(sheet.dimensions(), (32, 64));
+
+        Ok(())
+    }
 }
@@ -27,15 +27,31 @@ along with jpegxl-rs.  If not, see <https://www.gnu.org/licenses/>.
 //!
 //! # Building
 //!
+//! By default, the system `libjxl` is linked, which must be at least the version in the build
+//! metadata of the crate (e.g. `0.10.2` for `0.10.2+libjxl-0.10.2`), and is found with `pkg-config`.
 //! If you wish to specify a custom library path, set the `DEP_JXL_LIB` environment variable.
 //!
 //! Building `libjxl` and statically linking can be enabled by using the `vendored` feature.
+//! It takes precedence over the system library, so enabling it anywhere in the dependency
+//! graph is enough.
 //!
 //! If you don't want to depend on C++ standard library, disable the feature `threads`.
+//! The `rayon` feature adds a parallel runner on top of a [`rayon`](https://crates.io/crates/rayon)
+//! thread pool, e.g. to share the pool of an application already using it.
+//!
+//! The crate requires `std`: `libjxl` is a C++ library needing a C runtime, and the errors,
+//! readers and writers of the API are built on `std::error` and `std::io`. On targets where
+//! `std` is available, such as `wasm32-wasi` or `wasm32-unknown-emscripten`, the allocations of
+//! `libjxl` can still be routed through a custom allocator with a [`memory::MemoryManager`].
+//!
+//! For WebAssembly, disable the default features to drop the `threads` runner. Decoders and
+//! encoders are single-threaded unless a parallel runner is set, and
+//! [`JxlDecoder::decode_rgba8`](decode::JxlDecoder::decode_rgba8) outputs the bytes expected by
+//! a canvas `ImageData`.
 //!
 //! # Usage
 //!
-//! Currently, `u8`, `u16`, `f16` and `f32` are supported as pixel types.
+//! Currently, `u8`, `u16`, [`f16`] and `f32` are supported as pixel types.
 //!
 //! ## Decoding
 //!
@@ -109,6 +125,13 @@ along with jpegxl-rs.  If not, see <https://www.gnu.org/licenses/>.
 //! // You can change the settings after initialization
 //! encoder.lossless = false;
 //! encoder.quality = 3.0;
+//!
+//! // Multi-threading, as with the decoder
+//! use jpegxl_rs::ThreadsRunner;
+//! let runner = ThreadsRunner::default();
+//! let mut encoder = encoder_builder()
+//!                     .parallel_runner(&runner)
+//!                     .build()?;
 //! # Ok(()) };
 //! ```
 //!
@@ -148,11 +171,16 @@ pub mod image;
 #[cfg(test)]
 mod tests;
 
-pub use common::Endianness;
+pub use common::{Endianness, PixelType};
 pub use decode::decoder_builder;
 pub use encode::encoder_builder;
 pub use errors::{DecodeError, EncodeError};
+/// Half precision float type of the `f16` pixels, re-exported so the `half` version matches
+pub use half::f16;
 
+pub use parallel::pool_runner::{ParallelPool, PoolRunner};
+#[cfg(feature = "rayon")]
+pub use parallel::rayon_runner::RayonRunner;
 #[cfg(feature = "threads")]
 pub use parallel::resizable_runner::ResizableRunner;
 #[cfg(feature = "threads")]
@@ -27,9 +27,26 @@ along with jpegxl-rs.  If not, see <https://www.gnu.org/licenses/>.
 //! # };
 //! ```
 //!
+//! # Sharing a runner
+//! Decoders and encoders borrow their runner, so a single thread pool can serve several of
+//! them, e.g. to decode then re-encode an image without spawning threads twice. A runner
+//! shared by several owners can also be held behind an [`Rc`](std::rc::Rc) or an
+//! [`Arc`], which implement [`JxlParallelRunner`] too.
+//!
+//! A runner is not re-entrant, so it must not be used by two decoders or encoders at the same
+//! time. The runners of this crate wrapping `libjxl` are not [`Sync`], which prevents this.
+//! [`RayonRunner`](rayon_runner::RayonRunner) keeps no state between calls, so it may be.
+//!
+//! # Custom thread pools
+//! Implementing [`JxlParallelRunner`] means writing the FFI callbacks of `libjxl`. Instead,
+//! a thread pool implementing [`ParallelPool`](pool_runner::ParallelPool) in safe Rust can be
+//! plugged in with [`PoolRunner`](pool_runner::PoolRunner).
+//!
 
-use std::ffi::c_void;
+use std::{ffi::c_void, rc::Rc, sync::Arc};
 
+pub mod pool_runner;
+pub mod rayon_runner;
 pub mod resizable_runner;
 pub mod threads_runner;
 
@@ -73,6 +90,20 @@ pub type RunnerFn = unsafe extern "C" fn(
     end_range: u32,
 ) -> JxlParallelRetCode;
 
+/// Pointer to the state of `libjxl`, which it shares between the threads of a run
+#[derive(Clone, Copy)]
+pub(crate) struct Opaque(pub(crate) *mut c_void);
+
+// Safety: `libjxl` expects `run_func` to be called with it from several threads
+unsafe impl Send for Opaque {}
+unsafe impl Sync for Opaque {}
+
+impl Opaque {
+    pub(crate) fn get(self) -> *mut c_void {
+        self.0
+    }
+}
+
 /// JPEG XL Parallel Runner
 pub trait JxlParallelRunner {
     /// Get a [`RunnerFn`] for the parallel runner.
@@ -85,3 +116,31 @@ pub trait JxlParallelRunner {
     #[allow(unused_variables)]
     fn callback_basic_info(&self, basic_info: &BasicInfo) {}
 }
+
+impl<R: JxlParallelRunner + ?Sized> JxlParallelRunner for Rc<R> {
+    fn runner(&self) -> RunnerFn {
+        (**self).runner()
+    }
+
+    fn as_opaque_ptr(&self) -> *mut c_void {
+        (**self).as_opaque_ptr()
+    }
+
+    fn callback_basic_info(&self, basic_info: &BasicInfo) {
+        (**self).callback_basic_info(basic_info);
+    }
+}
+
+impl<R: JxlParallelRunner + ?Sized> JxlParallelRunner for Arc<R> {
+    fn runner(&self) -> RunnerFn {
+        (**self).runner()
+    }
+
+    fn as_opaque_ptr(&self) -> *mut c_void {
+        (**self).as_opaque_ptr()
+    }
+
+    fn callback_basic_info(&self, basic_info: &BasicInfo) {
+        (**self).callback_basic_info(basic_info);
+    }
+}
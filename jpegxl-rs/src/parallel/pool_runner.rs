@@ -0,0 +1,277 @@
+/*
+This file is part of jpegxl-rs.
+
+jpegxl-rs is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+jpegxl-rs is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with jpegxl-rs.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Parallel runner on top of any thread pool, implemented in safe Rust
+//!
+//! # Example
+//! ```
+//! # || -> Result<(), Box<dyn std::error::Error>> {
+//! use std::{
+//!     ops::Range,
+//!     sync::atomic::{AtomicU32, Ordering},
+//! };
+//!
+//! use jpegxl_rs::{decoder_builder, parallel::pool_runner::{ParallelPool, PoolRunner}};
+//!
+//! /// Spawn scoped threads for each run, taking the values in turn
+//! struct ScopedPool(usize);
+//!
+//! impl ParallelPool for ScopedPool {
+//!     fn num_threads(&self) -> usize {
+//!         self.0
+//!     }
+//!
+//!     fn run(&self, range: Range<u32>, f: &(dyn Fn(u32, usize) + Sync)) {
+//!         let (next, end) = (AtomicU32::new(range.start), range.end);
+//!         std::thread::scope(|s| {
+//!             for thread_id in 0..self.0 {
+//!                 let next = &next;
+//!                 s.spawn(move || loop {
+//!                     let value = next.fetch_add(1, Ordering::Relaxed);
+//!                     if value >= end {
+//!                         break;
+//!                     }
+//!                     f(value, thread_id);
+//!                 });
+//!             }
+//!         });
+//!     }
+//! }
+//!
+//! let runner = PoolRunner::new(ScopedPool(4));
+//! let decoder = decoder_builder().parallel_runner(&runner).build()?;
+//! # Ok(())
+//! # };
+//! ```
+
+use std::{
+    ffi::c_void,
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::{InitFn, JxlParallelRetCode, JxlParallelRunner, Opaque, RunFn, RunnerFn};
+use crate::decode::CancelHandle;
+
+/// Return code of `JXL_PARALLEL_RET_RUNNER_ERROR`
+const RUNNER_ERROR: JxlParallelRetCode = -1;
+
+/// A thread pool running the jobs of [`PoolRunner`]
+pub trait ParallelPool {
+    /// Number of threads of the pool, at least 1
+    fn num_threads(&self) -> usize;
+
+    /// Call `f` once for every value of `range`, possibly from several threads in parallel,
+    /// and return once all the calls returned. The second argument of `f` is the index of
+    /// the calling thread, below [`num_threads`](Self::num_threads), and must not be used
+    /// by two threads at the same time.
+    ///
+    /// Breaking these rules is safe, but slows down or fails the decoding or encoding.
+    fn run(&self, range: Range<u32>, f: &(dyn Fn(u32, usize) + Sync));
+}
+
+/// Parallel runner adapting a [`ParallelPool`], handling the FFI of `libjxl`
+pub struct PoolRunner<P> {
+    pool: P,
+    cancel: Option<CancelHandle>,
+}
+
+impl<P: ParallelPool> PoolRunner<P> {
+    /// Run the jobs of `libjxl` on `pool`
+    #[must_use]
+    pub fn new(pool: P) -> Self {
+        Self { pool, cancel: None }
+    }
+
+    /// Skip the remaining jobs once `cancel` is cancelled, failing the decoding or encoding.
+    /// Decodings given the same handle then return
+    /// [`DecodeError::Cancelled`](crate::DecodeError::Cancelled)
+    #[must_use]
+    pub fn with_cancel(self, cancel: CancelHandle) -> Self {
+        Self {
+            cancel: Some(cancel),
+            ..self
+        }
+    }
+
+    /// The pool the jobs are run on
+    #[must_use]
+    pub fn pool(&self) -> &P {
+        &self.pool
+    }
+}
+
+unsafe extern "C" fn pool_runner<P: ParallelPool>(
+    runner_opaque: *mut c_void,
+    jpegxl_opaque: *mut c_void,
+    init_func: InitFn,
+    run_func: RunFn,
+    start_range: u32,
+    end_range: u32,
+) -> JxlParallelRetCode {
+    // Safety: the opaque pointer is the runner, borrowed by the decoder or encoder
+    let runner = unsafe { &*runner_opaque.cast::<PoolRunner<P>>() };
+    let num_threads = runner.pool.num_threads().max(1);
+
+    let ret = unsafe { init_func(jpegxl_opaque, num_threads) };
+    if ret != 0 {
+        return ret;
+    }
+
+    // `libjxl` relies on each value running once, and on the threads having their own index,
+    // which are enforced here so that a faulty pool cannot cause data races
+    let done: Vec<AtomicBool> = (start_range..end_range)
+        .map(|_| AtomicBool::new(false))
+        .collect();
+    let busy: Vec<AtomicBool> = (0..num_threads).map(|_| AtomicBool::new(false)).collect();
+    let opaque = Opaque(jpegxl_opaque);
+    let cancel = runner.cancel.as_ref();
+    let f = |value: u32, thread_id: usize| {
+        let (Some(done), Some(busy)) = (
+            value
+                .checked_sub(start_range)
+                .and_then(|i| done.get(i as usize)),
+            busy.get(thread_id),
+        ) else {
+            return;
+        };
+        if cancel.is_some_and(CancelHandle::is_cancelled) || done.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        while busy.swap(true, Ordering::Acquire) {
+            std::thread::yield_now();
+        }
+        unsafe { run_func(opaque.get(), value, thread_id) };
+        busy.store(false, Ordering::Release);
+    };
+    runner.pool.run(start_range..end_range, &f);
+
+    if done.iter().all(|done| done.load(Ordering::Relaxed)) {
+        0
+    } else {
+        RUNNER_ERROR
+    }
+}
+
+impl<P: ParallelPool> JxlParallelRunner for PoolRunner<P> {
+    fn runner(&self) -> RunnerFn {
+        pool_runner::<P>
+    }
+
+    fn as_opaque_ptr(&self) -> *mut c_void {
+        std::ptr::from_ref(self).cast_mut().cast()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+
+    use pretty_assertions::assert_eq;
+    use testresult::TestResult;
+
+    use super::*;
+    use crate::{decoder_builder, encode::EncoderResult, encoder_builder};
+
+    /// Spawn scoped threads for each run, taking the values in turn
+    struct ScopedPool(usize);
+
+    impl ParallelPool for ScopedPool {
+        fn num_threads(&self) -> usize {
+            self.0
+        }
+
+        fn run(&self, range: Range<u32>, f: &(dyn Fn(u32, usize) + Sync)) {
+            let (next, end) = (AtomicU32::new(range.start), range.end);
+            std::thread::scope(|s| {
+                for thread_id in 0..self.0 {
+                    let next = &next;
+                    s.spawn(move || loop {
+                        let value = next.fetch_add(1, Ordering::Relaxed);
+                        if value >= end {
+                            break;
+                        }
+                        f(value, thread_id);
+                    });
+                }
+            });
+        }
+    }
+
+    /// Pool forgetting the last value of each run
+    struct LossyPool;
+
+    impl ParallelPool for LossyPool {
+        fn num_threads(&self) -> usize {
+            1
+        }
+
+        fn run(&self, range: Range<u32>, f: &(dyn Fn(u32, usize) + Sync)) {
+            for value in range.start..range.end.saturating_sub(1) {
+                f(value, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn decode_encode() -> TestResult {
+        let decoder = decoder_builder().build()?;
+        let (_, expected) = decoder.decode_with::<u8>(crate::tests::SAMPLE_JXL)?;
+
+        let runner = PoolRunner::new(ScopedPool(3));
+        let decoder = decoder_builder().parallel_runner(&runner).build()?;
+        let (metadata, data) = decoder.decode_with::<u8>(crate::tests::SAMPLE_JXL)?;
+        assert_eq!(data, expected);
+
+        let mut encoder = encoder_builder()
+            .has_alpha(true)
+            .parallel_runner(&runner)
+            .build()?;
+        let _res: EncoderResult<u8> = encoder.encode(&data, metadata.width, metadata.height)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn faulty_pool() -> TestResult {
+        let runner = PoolRunner::new(LossyPool);
+        let decoder = decoder_builder().parallel_runner(&runner).build()?;
+        assert!(decoder.decode_with::<u8>(crate::tests::SAMPLE_JXL).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cancel() -> TestResult {
+        let cancel = CancelHandle::new();
+        let runner = PoolRunner::new(ScopedPool(2)).with_cancel(cancel.clone());
+        let decoder = decoder_builder().parallel_runner(&runner).build()?;
+
+        cancel.clone().cancel();
+        assert!(cancel.is_cancelled());
+        assert!(decoder.decode_with::<u8>(crate::tests::SAMPLE_JXL).is_err());
+        assert!(matches!(
+            decoder.decode_cancellable(crate::tests::SAMPLE_JXL, cancel.as_ref()),
+            Err(crate::DecodeError::Cancelled)
+        ));
+
+        cancel.reset();
+        decoder.decode_cancellable(crate::tests::SAMPLE_JXL, cancel.as_ref())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+/*
+This file is part of jpegxl-rs.
+
+jpegxl-rs is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+jpegxl-rs is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with jpegxl-rs.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Parallel runner on top of a [`rayon`] thread pool
+
+#![cfg(feature = "rayon")]
+#![cfg_attr(docsrs, doc(cfg(feature = "rayon")))]
+
+use std::ffi::c_void;
+
+use rayon::{prelude::*, ThreadPool};
+
+use super::{InitFn, JxlParallelRetCode, JxlParallelRunner, Opaque, RunFn, RunnerFn};
+
+/// Parallel runner using a [`rayon`] thread pool, so applications already using rayon don't
+/// spawn a second pool
+#[derive(Default)]
+pub struct RayonRunner<'pool> {
+    pool: Option<&'pool ThreadPool>,
+}
+
+impl<'pool> RayonRunner<'pool> {
+    /// Run on `pool` instead of the global thread pool of rayon
+    #[must_use]
+    pub fn with_pool(pool: &'pool ThreadPool) -> Self {
+        Self { pool: Some(pool) }
+    }
+
+    fn num_threads(&self) -> usize {
+        self.pool
+            .map_or_else(rayon::current_num_threads, ThreadPool::current_num_threads)
+    }
+}
+
+unsafe extern "C" fn rayon_runner(
+    runner_opaque: *mut c_void,
+    jpegxl_opaque: *mut c_void,
+    init_func: InitFn,
+    run_func: RunFn,
+    start_range: u32,
+    end_range: u32,
+) -> JxlParallelRetCode {
+    // Safety: the opaque pointer is the runner, borrowed by the decoder or encoder
+    let runner = unsafe { &*runner_opaque.cast::<RayonRunner>() };
+
+    let ret = unsafe { init_func(jpegxl_opaque, runner.num_threads()) };
+    if ret != 0 {
+        return ret;
+    }
+
+    let opaque = Opaque(jpegxl_opaque);
+    let run = || {
+        (start_range..end_range).into_par_iter().for_each(|value| {
+            // The work only runs on the threads of the pool, whose indices are below
+            // the number of threads given to `init_func`
+            let thread_id = rayon::current_thread_index().unwrap_or(0);
+            unsafe { run_func(opaque.get(), value, thread_id) };
+        });
+    };
+    match runner.pool {
+        Some(pool) => pool.install(run),
+        None => run(),
+    }
+
+    0
+}
+
+impl JxlParallelRunner for RayonRunner<'_> {
+    fn runner(&self) -> RunnerFn {
+        rayon_runner
+    }
+
+    fn as_opaque_ptr(&self) -> *mut c_void {
+        std::ptr::from_ref(self).cast_mut().cast()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use testresult::TestResult;
+
+    use super::*;
+    use crate::{decoder_builder, encode::EncoderResult, encoder_builder};
+
+    #[test]
+    fn decode_encode() -> TestResult {
+        let decoder = decoder_builder().build()?;
+        let (_, expected) = decoder.decode_with::<u8>(crate::tests::SAMPLE_JXL)?;
+
+        let runner = RayonRunner::default();
+        let decoder = decoder_builder().parallel_runner(&runner).build()?;
+        let (metadata, data) = decoder.decode_with::<u8>(crate::tests::SAMPLE_JXL)?;
+        assert_eq!(data, expected);
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(3).build()?;
+        let runner = RayonRunner::with_pool(&pool);
+        let decoder = decoder_builder().parallel_runner(&runner).build()?;
+        let (_, data) = decoder.decode_with::<u8>(crate::tests::SAMPLE_JXL)?;
+        assert_eq!(data, expected);
+
+        let mut encoder = encoder_builder()
+            .has_alpha(true)
+            .parallel_runner(&runner)
+            .build()?;
+        let _res: EncoderResult<u8> = encoder.encode(&data, metadata.width, metadata.height)?;
+
+        Ok(())
+    }
+}
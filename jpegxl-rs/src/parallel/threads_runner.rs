@@ -32,6 +32,7 @@ use crate::memory::MemoryManager;
 /// Wrapper for default thread pool implementation with C++ standard library
 pub struct ThreadsRunner<'mm> {
     runner_ptr: *mut c_void,
+    num_threads: usize,
     _memory_manager: Option<&'mm dyn MemoryManager>,
 }
 
@@ -43,11 +44,10 @@ impl<'mm> ThreadsRunner<'mm> {
         num_workers: Option<usize>,
     ) -> Option<Self> {
         let mm = memory_manager.map(MemoryManager::manager);
+        let num_threads = num_workers
+            .unwrap_or_else(|| unsafe { JxlThreadParallelRunnerDefaultNumWorkerThreads() });
         let runner_ptr = unsafe {
-            JxlThreadParallelRunnerCreate(
-                mm.as_ref().map_or(null_mut(), |mm| mm),
-                num_workers.unwrap_or_else(|| JxlThreadParallelRunnerDefaultNumWorkerThreads()),
-            )
+            JxlThreadParallelRunnerCreate(mm.as_ref().map_or(null_mut(), |mm| mm), num_threads)
         };
 
         if runner_ptr.is_null() {
@@ -55,21 +55,53 @@ impl<'mm> ThreadsRunner<'mm> {
         } else {
             Some(Self {
                 runner_ptr,
+                num_threads,
                 _memory_manager: memory_manager,
             })
         }
     }
+
+    /// Construct with a fixed number of worker threads, e.g. to cap the CPU usage of each
+    /// decoding on a server. With 0 threads, the jobs run on the calling thread.
+    /// Return `None` if it fails to create the thread pool
+    #[must_use]
+    pub fn with_threads(num_threads: usize) -> Option<Self> {
+        Self::new(None, Some(num_threads))
+    }
+
+    /// Number of worker threads of the pool
+    #[must_use]
+    pub fn num_threads(&self) -> usize {
+        self.num_threads
+    }
+
+    /// Pre-warm the thread pool, by running an empty job on it, so that the first decoding
+    /// or encoding does not pay for waking up the threads, e.g. in latency-sensitive apps.
+    /// Return `false` if the runner failed to run the job.
+    ///
+    /// The threads are created with the runner, and stay idle in between jobs, blocked
+    /// without using the CPU. Warming only helps if the runner is created well before it is
+    /// first used, while the threads then hold their memory for longer.
+    #[must_use]
+    pub fn warm(&self) -> bool {
+        unsafe extern "C" fn init(_: *mut c_void, _: usize) -> i32 {
+            0
+        }
+        unsafe extern "C" fn run(_: *mut c_void, _: u32, _: usize) {}
+
+        // Enough tasks to be picked up by every thread
+        let tasks = u32::try_from(self.num_threads.max(1) * 4).unwrap_or(u32::MAX);
+        unsafe { JxlThreadParallelRunner(self.runner_ptr, null_mut(), init, run, 0, tasks) == 0 }
+    }
 }
 
 impl Default for ThreadsRunner<'_> {
+    /// Construct with one worker thread per core
     fn default() -> Self {
+        let num_threads = unsafe { JxlThreadParallelRunnerDefaultNumWorkerThreads() };
         Self {
-            runner_ptr: unsafe {
-                JxlThreadParallelRunnerCreate(
-                    std::ptr::null(),
-                    JxlThreadParallelRunnerDefaultNumWorkerThreads(),
-                )
-            },
+            runner_ptr: unsafe { JxlThreadParallelRunnerCreate(std::ptr::null(), num_threads) },
+            num_threads,
             _memory_manager: None,
         }
     }
@@ -107,4 +139,28 @@ mod tests {
         let parallel_runner = ThreadsRunner::new(Some(&memory_manager), Some(10));
         assert!(parallel_runner.is_some());
     }
+
+    #[test]
+    fn warm() -> testresult::TestResult {
+        let runner = ThreadsRunner::default();
+        assert!(runner.warm());
+        assert!(runner.warm());
+
+        let decoder = crate::decoder_builder().parallel_runner(&runner).build()?;
+        decoder.decode(crate::tests::SAMPLE_JXL)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn with_threads() -> testresult::TestResult {
+        let runner = ThreadsRunner::with_threads(2).ok_or("Failed to create the runner")?;
+        assert_eq!(runner.num_threads(), 2);
+        assert!(runner.warm());
+
+        let decoder = crate::decoder_builder().parallel_runner(&runner).build()?;
+        decoder.decode(crate::tests::SAMPLE_JXL)?;
+
+        Ok(())
+    }
 }
@@ -2,8 +2,56 @@ mod decode;
 mod encode;
 
 pub const SAMPLE_PNG: &[u8] = include_bytes!("../../samples/sample.png");
-const SAMPLE_JPEG: &[u8] = include_bytes!("../../samples/sample.jpg");
+pub const SAMPLE_JPEG: &[u8] = include_bytes!("../../samples/sample.jpg");
 pub const SAMPLE_JXL: &[u8] = include_bytes!("../../samples/sample.jxl");
 const SAMPLE_JXL_JPEG: &[u8] = include_bytes!("../../samples/sample_jpg.jxl");
 pub const SAMPLE_JXL_GRAY: &[u8] = include_bytes!("../../samples/sample_grey.jxl");
 const SAMPLE_JXL_2BIT: &[u8] = include_bytes!("../../samples/2bit.jxl");
+/// Only the headers of an 8x8 CMYK image, which declare a black extra channel
+const SAMPLE_JXL_CMYK: &[u8] = include_bytes!("../../samples/cmyk.jxl");
+
+/// Insert a box after the signature and `ftyp` boxes of the `SAMPLE_JXL_JPEG` container
+fn with_box(box_type: [u8; 4], payload: &[u8]) -> Vec<u8> {
+    let size = u32::try_from(8 + payload.len()).expect("box too large");
+
+    let mut data = SAMPLE_JXL_JPEG[..32].to_vec();
+    data.extend_from_slice(&size.to_be_bytes());
+    data.extend_from_slice(&box_type);
+    data.extend_from_slice(payload);
+    data.extend_from_slice(&SAMPLE_JXL_JPEG[32..]);
+    data
+}
+
+/// Encode an animation of `frames` frames of the pixels of `SAMPLE_JXL`, the samples of the
+/// frame `i` being shifted by `i * 60` so that the frames differ
+fn animated_sample(frames: u8) -> Vec<u8> {
+    use crate::{
+        decoder_builder,
+        encode::{Animation, EncoderFrame, EncoderResult},
+        encoder_builder,
+    };
+
+    let decoder = decoder_builder().build().expect("failed to create decoder");
+    let (_, data) = decoder
+        .decode_with::<u8>(SAMPLE_JXL)
+        .expect("failed to decode sample");
+    let mut encoder = encoder_builder()
+        .has_alpha(true)
+        .animation(Animation {
+            tps_numerator: 10,
+            tps_denominator: 1,
+            num_loops: 0,
+        })
+        .build()
+        .expect("failed to create encoder");
+
+    let mut encoder = encoder.multiple(40, 50).expect("failed to start animation");
+    for i in 0..frames {
+        let data: Vec<_> = data.iter().map(|v| v.wrapping_add(i * 60)).collect();
+        encoder = encoder
+            .add_frame(&EncoderFrame::new(&data).num_channels(4).duration(1))
+            .expect("failed to add frame");
+    }
+    let result: EncoderResult<u8> = encoder.encode().expect("failed to encode animation");
+    result.data
+}
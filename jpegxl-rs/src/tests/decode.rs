@@ -6,7 +6,7 @@ use pretty_assertions::assert_eq;
 use testresult::TestResult;
 
 use crate::{
-    decode::{Data, Metadata, PixelFormat, Pixels},
+    decode::{ColorType, Data, Metadata, PixelFormat, Pixels},
     decoder_builder, DecodeError,
 };
 #[cfg(feature = "threads")]
@@ -24,27 +24,1465 @@ fn invalid() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn single_threaded_by_default() -> TestResult {
+    let decoder = decoder_builder().build()?;
+    assert!(decoder.parallel_runner.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn max_buffer_bytes() -> TestResult {
+    // 40x50 RGBA pixels of 8 bits
+    let decoder = decoder_builder().max_buffer_bytes(7999).build()?;
+    assert!(matches!(
+        decoder.decode_with::<u8>(super::SAMPLE_JXL),
+        Err(DecodeError::ImageTooLarge {
+            requested: 8000,
+            max: 7999
+        })
+    ));
+    let decoder = decoder_builder().max_buffer_bytes(8000).build()?;
+    decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+
+    // Codestream signature, then a size header of 65536x65536 and default image metadata,
+    // i.e. 12 GiB of RGB pixels
+    let mut header = vec![0xFF, 0x0A, 0xFC, 0xFF, 0x1F, 0xFE, 0xFF, 0x1F];
+    header.resize(32, 0);
+    let decoder = decoder_builder().max_buffer_bytes(4 << 30).build()?;
+    assert!(matches!(
+        decoder.required_buffer_size::<u8>(&header),
+        Err(DecodeError::ImageTooLarge {
+            requested: 12_884_901_888,
+            max: 4_294_967_296
+        })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn truncated() -> TestResult {
+    let mut decoder = decoder_builder().build()?;
+
+    let codestream = &super::SAMPLE_JXL[..super::SAMPLE_JXL.len() / 2];
+    assert!(matches!(
+        decoder.decode(codestream),
+        Err(DecodeError::CodestreamTruncated)
+    ));
+
+    // Cut in the `jxlc` box
+    assert!(matches!(
+        decoder.decode(&super::SAMPLE_JXL_JPEG[..600]),
+        Err(DecodeError::CodestreamTruncated)
+    ));
+    // Cut in the `jbrd` box, and right before the `jxlc` box
+    for len in [100, 202] {
+        assert!(matches!(
+            decoder.decode(&super::SAMPLE_JXL_JPEG[..len]),
+            Err(DecodeError::ContainerIncomplete)
+        ));
+    }
+
+    // Frame by frame decoding tells the same errors
+    assert!(matches!(
+        decoder.decode_frames::<u8>(codestream),
+        Err(DecodeError::CodestreamTruncated)
+    ));
+    assert!(matches!(
+        decoder.for_each_frame::<u8, _>(codestream, |_| {}),
+        Err(DecodeError::CodestreamTruncated)
+    ));
+    let mut buffer = vec![0; decoder.required_buffer_size::<u8>(super::SAMPLE_JXL)?];
+    assert!(matches!(
+        decoder.decode_into::<u8>(codestream, &mut buffer),
+        Err(DecodeError::CodestreamTruncated)
+    ));
+    assert!(matches!(
+        decoder.decode_frames::<u8>(&super::SAMPLE_JXL_JPEG[..100]),
+        Err(DecodeError::ContainerIncomplete)
+    ));
+
+    // The decoder is still usable
+    decoder.decode(super::SAMPLE_JXL)?;
+
+    Ok(())
+}
+
 #[test]
 fn simple() -> TestResult {
     let decoder = decoder_builder().icc_profile(true).build()?;
 
-    let (
-        Metadata {
-            width,
-            height,
-            icc_profile,
-            ..
-        },
-        data,
-    ) = decoder.decode(super::SAMPLE_JXL)?;
+    let (
+        Metadata {
+            width,
+            height,
+            icc_profile,
+            ..
+        },
+        data,
+    ) = decoder.decode(super::SAMPLE_JXL)?;
+
+    let Pixels::Uint16(data) = data else {
+        return Err("Failed to decode".into());
+    };
+
+    assert_eq!(data.len(), (width * height * 4) as usize);
+    // Check if icc profile is valid
+    lcms2::Profile::new_icc(&icc_profile.expect("ICC profile not retrieved"))?;
+
+    Ok(())
+}
+
+#[test]
+fn color_encoding() -> TestResult {
+    use jpegxl_sys::color_encoding::JxlColorSpace;
+
+    let decoder = decoder_builder().icc_profile(true).build()?;
+    let (
+        Metadata {
+            icc_profile,
+            color_encoding,
+            ..
+        },
+        _,
+    ) = decoder.decode(super::SAMPLE_JXL)?;
+    assert!(icc_profile.is_some());
+    assert!(matches!(
+        color_encoding.map(|e| e.color_space),
+        Some(JxlColorSpace::Rgb)
+    ));
+
+    let decoder = decoder_builder().build()?;
+    let (Metadata { color_encoding, .. }, _) = decoder.decode(super::SAMPLE_JXL)?;
+    assert!(color_encoding.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn max_icc_bytes() -> TestResult {
+    let decoder = decoder_builder().icc_profile(true).build()?;
+    let (Metadata { icc_profile, .. }, _) = decoder.decode(super::SAMPLE_JXL)?;
+    let len = icc_profile.ok_or("ICC profile not retrieved")?.len();
+
+    let decoder = decoder_builder()
+        .icc_profile(true)
+        .max_icc_bytes(len - 1)
+        .build()?;
+    assert!(matches!(
+        decoder.decode(super::SAMPLE_JXL),
+        Err(DecodeError::IccProfileTooLarge { size, max }) if size == len && max == len - 1
+    ));
+    // Also checked by the color profile consistency check
+    let decoder = decoder_builder()
+        .strict_color_profile(true)
+        .max_icc_bytes(1)
+        .build()?;
+    assert!(matches!(
+        decoder.decode(super::SAMPLE_JXL),
+        Err(DecodeError::IccProfileTooLarge { .. })
+    ));
+
+    let decoder = decoder_builder()
+        .icc_profile(true)
+        .max_icc_bytes(len)
+        .build()?;
+    decoder.decode(super::SAMPLE_JXL)?;
+
+    Ok(())
+}
+
+#[test]
+fn strict_color_profile() -> TestResult {
+    let decoder = decoder_builder().strict_color_profile(true).build()?;
+
+    decoder.decode(super::SAMPLE_JXL)?;
+    decoder.decode(super::SAMPLE_JXL_GRAY)?;
+
+    Ok(())
+}
+
+#[test]
+#[allow(clippy::float_cmp)]
+fn frames() -> TestResult {
+    let mut decoder = decoder_builder().build()?;
+    let (_, expected) = decoder.decode_with::<u16>(super::SAMPLE_JXL)?;
+
+    let (mut info, mut frames) = decoder.decode_frames::<u16>(super::SAMPLE_JXL)?;
+    assert_eq!(frames.len(), 1);
+    assert_eq!(info.animation.num_loops, 0);
+    assert_eq!(frames[0].data, expected);
+
+    // Still image
+    assert_eq!(frames[0].duration_ms(&info), 0.0);
+
+    info.have_animation = jpegxl_sys::types::JxlBool::True;
+    info.animation.tps_numerator = 100;
+    info.animation.tps_denominator = 1;
+    frames[0].duration = 5;
+    assert_eq!(frames[0].duration_ms(&info), 50.0);
+
+    assert!(matches!(
+        decoder.decode_frames::<u8>(&[0x00, 0x00]),
+        Err(DecodeError::InvalidInput)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn for_each_frame() -> TestResult {
+    use crate::{
+        encode::{EncoderFrame, EncoderResult},
+        encoder_builder,
+    };
+
+    let sample = image::load_from_memory(super::SAMPLE_PNG)?.to_rgb8();
+    let mut encoder = encoder_builder().build()?;
+    let frame = EncoderFrame::new(sample.as_raw());
+    let result: EncoderResult<u8> = encoder
+        .multiple(sample.width(), sample.height())?
+        .add_frame(&frame)?
+        .add_frame(&frame)?
+        .add_frame(&frame)?
+        .encode()?;
+
+    // Get every layer as a frame of the same size
+    let mut decoder = decoder_builder().coalescing(false).build()?;
+    let mut buffers = vec![];
+    let info = decoder.for_each_frame::<u8, _>(&result, |frame| {
+        assert_eq!(
+            (frame.width, frame.height),
+            (sample.width(), sample.height())
+        );
+        buffers.push(frame.data.as_ptr());
+    })?;
+    assert_eq!((info.xsize, info.ysize), (sample.width(), sample.height()));
+    assert_eq!(buffers.len(), 3);
+    assert!(buffers.iter().all(|&b| b == buffers[0]));
+
+    Ok(())
+}
+
+#[test]
+fn callback() -> TestResult {
+    let mut decoder = decoder_builder().build()?;
+    let (Metadata { width, .. }, expected) = decoder.decode_with::<u16>(super::SAMPLE_JXL)?;
+
+    let mut data = vec![0; expected.len()];
+    let info = decoder.decode_with_callback::<u16, _>(super::SAMPLE_JXL, |x, y, n, pixels| {
+        assert_eq!(pixels.len(), n * 4);
+        let start = (y * width as usize + x) * 4;
+        data[start..start + pixels.len()].copy_from_slice(pixels);
+    })?;
+    assert_eq!(info.xsize, width);
+    assert_eq!(data, expected);
+
+    Ok(())
+}
+
+#[test]
+#[allow(clippy::float_cmp)]
+fn range() -> TestResult {
+    let decoder = decoder_builder().build()?;
+
+    let (_, data, ranges) = decoder.decode_with_range::<u8>(super::SAMPLE_JXL)?;
+    assert_eq!(ranges.len(), 4);
+    for (c, &(min, max)) in ranges.iter().enumerate() {
+        let channel = data.iter().skip(c).step_by(4);
+        assert_eq!(min, *channel.clone().min().unwrap());
+        assert_eq!(max, *channel.max().unwrap());
+    }
+
+    let (_, data, ranges) = decoder.decode_with_range::<f32>(super::SAMPLE_JXL)?;
+    for (c, &(min, max)) in ranges.iter().enumerate() {
+        assert!(min.is_finite() && max.is_finite() && min <= max);
+        let channel = data.iter().skip(c).step_by(4).copied();
+        assert_eq!(max, channel.fold(f32::MIN, f32::max));
+    }
+
+    // The padding of the rows is not part of the ranges
+    let (_, _, expected) = decoder.decode_with_range::<u8>(super::SAMPLE_JXL)?;
+    let decoder = decoder_builder()
+        .pixel_format(PixelFormat {
+            num_channels: 4,
+            align: 64,
+            ..PixelFormat::default()
+        })
+        .build()?;
+    let (Metadata { width, height, .. }, data, ranges) =
+        decoder.decode_with_range::<u8>(super::SAMPLE_JXL)?;
+    assert_eq!(data.len(), 192 * (height as usize - 1) + width as usize * 4);
+    assert_eq!(ranges, expected);
+
+    Ok(())
+}
+
+#[test]
+fn placeholder() -> TestResult {
+    let decoder = decoder_builder().build()?;
+
+    let placeholder = decoder.decode_placeholder(super::SAMPLE_JXL, 4, 4)?;
+    assert_eq!(placeholder.len(), 4 * 4 * 4);
+
+    let placeholder = decoder.decode_placeholder(super::SAMPLE_JXL_GRAY, 4, 4)?;
+    assert_eq!(placeholder.len(), 4 * 4 * 4);
+
+    // The decoder is still usable
+    decoder.decode(super::SAMPLE_JXL)?;
+
+    let decoder = decoder_builder().max_pixels(40 * 50 - 1).build()?;
+    assert!(matches!(
+        decoder.decode_placeholder(super::SAMPLE_JXL, 4, 4),
+        Err(DecodeError::TooManyPixels { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn recommended_pixel_type() -> TestResult {
+    use crate::{
+        decode::RecommendedType,
+        encode::{EncoderFrame, EncoderResult},
+        encoder_builder,
+    };
+
+    let decoder = decoder_builder().build()?;
+    let (metadata, data) = decoder.decode_with::<f32>(super::SAMPLE_JXL)?;
+    assert_eq!(metadata.bits_per_sample, 8);
+    assert_eq!(metadata.recommended_pixel_type(), RecommendedType::U8);
+
+    let mut encoder = encoder_builder().has_alpha(true).build()?;
+    let frame = EncoderFrame::new(&data).num_channels(4);
+
+    let result: EncoderResult<u16> = encoder.encode_frame(&frame, 40, 50)?;
+    let (metadata, _) = decoder.decode(&result)?;
+    assert_eq!(metadata.recommended_pixel_type(), RecommendedType::U16);
+
+    let result: EncoderResult<f32> = encoder.encode_frame(&frame, 40, 50)?;
+    let (metadata, _) = decoder.decode(&result)?;
+    assert_eq!(metadata.exponent_bits_per_sample, 8);
+    assert_eq!(metadata.recommended_pixel_type(), RecommendedType::F32);
+
+    Ok(())
+}
+
+#[test]
+fn decode_frame_at() -> TestResult {
+    let result = super::animated_sample(4);
+
+    let mut decoder = decoder_builder().build()?;
+    let (_, expected) = decoder.decode_frames::<u8>(&result)?;
+    let (info, frame) = decoder.decode_frame_at::<u8>(&result, 2)?;
+    assert_eq!((info.xsize, info.ysize), (40, 50));
+    assert_eq!(frame, expected[2].data);
+    assert_ne!(frame, expected[1].data);
+    assert_eq!(
+        decoder.decode_frame_at::<u8>(&result, 0)?.1,
+        expected[0].data
+    );
+
+    assert!(matches!(
+        decoder.decode_frame_at::<u8>(&result, 4),
+        Err(DecodeError::FrameIndexOutOfRange { index: 4, count: 4 })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn max_frames() -> TestResult {
+    let result = super::animated_sample(5);
+
+    let mut decoder = decoder_builder().max_frames(2).build()?;
+    let (info, frames) = decoder.decode_frames::<u8>(&result)?;
+    assert_eq!((info.xsize, info.ysize), (40, 50));
+    assert_eq!(frames.len(), 2);
+    assert!(!frames[1].is_last);
+
+    decoder.max_frames = Some(10);
+    assert_eq!(decoder.decode_frames::<u8>(&result)?.1.len(), 5);
+
+    decoder.max_frames = Some(0);
+    let (info, frames) = decoder.decode_frames::<u8>(&result)?;
+    assert_eq!((info.xsize, info.ysize), (40, 50));
+    assert!(frames.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn resolution() -> TestResult {
+    let decoder = decoder_builder().build()?;
+
+    // The JFIF segment of the original JPEG gives 118 dots per centimeter
+    let (x, y) = decoder
+        .resolution(super::SAMPLE_JXL_JPEG)?
+        .ok_or("no resolution")?;
+    assert!((x - 299.72).abs() < 1e-6 && (y - 299.72).abs() < 1e-6);
+
+    assert_eq!(decoder.resolution(super::SAMPLE_JXL)?, None);
+
+    Ok(())
+}
+
+#[test]
+fn decode_preview() -> TestResult {
+    let decoder = decoder_builder().build()?;
+
+    assert!(decoder.decode_preview::<u8>(super::SAMPLE_JXL)?.is_none());
+    assert!(matches!(
+        decoder.decode_preview::<u8>(&[0; 64]),
+        Err(DecodeError::InvalidInput)
+    ));
+
+    let decoder = decoder_builder().max_pixels(40 * 50 - 1).build()?;
+    assert!(matches!(
+        decoder.decode_preview::<u8>(super::SAMPLE_JXL),
+        Err(DecodeError::TooManyPixels { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn hdr_info() -> TestResult {
+    use crate::decode::TransferFunction;
+
+    let decoder = decoder_builder().build()?;
+
+    let hdr = decoder.hdr_info(super::SAMPLE_JXL)?;
+    let info = decoder.info(super::SAMPLE_JXL)?;
+    assert_eq!(
+        (hdr.intensity_target.to_bits(), hdr.min_nits.to_bits()),
+        (info.intensity_target.to_bits(), info.min_nits.to_bits())
+    );
+
+    let sdr = crate::decode::HdrInfo {
+        intensity_target: 255.0,
+        min_nits: 0.0,
+        linear_below: 0.0,
+        relative_to_max_display: false,
+        transfer_function: Some(TransferFunction::SRgb),
+    };
+    assert!(!sdr.is_hdr());
+    assert!(crate::decode::HdrInfo {
+        transfer_function: Some(TransferFunction::Pq),
+        ..sdr
+    }
+    .is_hdr());
+    assert!(crate::decode::HdrInfo {
+        intensity_target: 1000.0,
+        transfer_function: None,
+        ..sdr
+    }
+    .is_hdr());
+
+    assert!(matches!(
+        decoder.hdr_info(&[0; 64]),
+        Err(DecodeError::InvalidInput)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn metadata_boxes() -> TestResult {
+    let decoder = decoder_builder().build()?;
+
+    let xmp = b"<x:xmpmeta xmlns:x='adobe:ns:meta/'/>";
+    let data = super::with_box(*b"xml ", xmp);
+    let data = [
+        &data[..32 + 8 + xmp.len()],
+        &super::with_box(*b"jumb", b"one")[32..],
+    ]
+    .concat();
+    let metadata = decoder.metadata_boxes(&data)?;
+    assert_eq!(metadata.xmp.as_deref(), Some(&xmp[..]));
+    assert_eq!(metadata.jumbf, vec![b"one".to_vec()]);
+
+    let metadata = decoder.metadata_boxes(super::SAMPLE_JXL)?;
+    assert!(metadata.exif.is_none() && metadata.xmp.is_none() && metadata.jumbf.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn count_frames() -> TestResult {
+    let mut decoder = decoder_builder().build()?;
+    assert_eq!(decoder.count_frames(super::SAMPLE_JXL)?, 1);
+
+    let result = super::animated_sample(3);
+
+    assert_eq!(
+        decoder.count_frames(&result)?,
+        decoder.decode_frames::<u8>(&result)?.1.len()
+    );
+    assert_eq!(decoder.count_frames(&result)?, 3);
+
+    Ok(())
+}
+
+#[test]
+fn info() -> TestResult {
+    use jpegxl_sys::types::JxlBool;
+
+    let decoder = decoder_builder().build()?;
+    let info = decoder.info(super::SAMPLE_JXL)?;
+    assert_eq!((info.xsize, info.ysize), (40, 50));
+    assert_eq!(info.have_animation, JxlBool::False);
+    assert!(info.alpha_bits > 0);
+
+    // The pixels are not needed
+    let info = decoder.info(&super::SAMPLE_JXL[..100])?;
+    assert_eq!((info.xsize, info.ysize), (40, 50));
+
+    assert!(matches!(
+        decoder.info(&[0x00, 0x00]),
+        Err(DecodeError::InvalidInput)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn info_summary() -> TestResult {
+    let decoder = decoder_builder().build()?;
+
+    let summary = decoder.info_summary(super::SAMPLE_JXL)?;
+    assert!(summary.contains("40x50"));
+    assert!(summary.contains("RGBA"));
+    assert!(!summary.contains("animated"));
+
+    let summary = decoder.info_summary(super::SAMPLE_JXL_GRAY)?;
+    assert!(summary.contains("Gray"));
+
+    Ok(())
+}
+
+#[test]
+fn extra_channels() -> TestResult {
+    use crate::decode::ExtraChannelType;
+
+    let mut decoder = decoder_builder().build()?;
+    let channels = decoder.extra_channels(super::SAMPLE_JXL)?;
+    assert_eq!(channels.len(), 1);
+    assert_eq!(channels[0].info.type_, ExtraChannelType::Alpha);
+
+    let (info, pixels, planes) = decoder.decode_extra_channels::<u8>(super::SAMPLE_JXL)?;
+    assert_eq!(planes.len(), 1);
+    let (channel, alpha) = &planes[0];
+    assert_eq!(channel.info.type_, ExtraChannelType::Alpha);
+    assert_eq!(alpha.len(), (info.xsize * info.ysize) as usize);
+    // The same samples as the alpha of the RGBA pixels
+    assert!(pixels
+        .chunks_exact(4)
+        .map(|p| p[3])
+        .eq(alpha.iter().copied()));
+
+    Ok(())
+}
+
+#[test]
+fn decode_into() -> TestResult {
+    let mut decoder = decoder_builder().build()?;
+    let (_, expected) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+
+    let size = decoder.required_buffer_size::<u8>(super::SAMPLE_JXL)?;
+    assert_eq!(size, expected.len());
+
+    let mut buffer = vec![0; size];
+    decoder.decode_into::<u8>(super::SAMPLE_JXL, &mut buffer)?;
+    assert_eq!(buffer, expected);
+
+    assert!(matches!(
+        decoder.decode_into::<u8>(super::SAMPLE_JXL, &mut buffer[1..]),
+        Err(DecodeError::BufferTooSmall { required, actual }) if required == size && actual == size - 1
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn decode_into_slice() -> TestResult {
+    let mut decoder = decoder_builder().build()?;
+    let (_, expected) = decoder.decode_with::<u16>(super::SAMPLE_JXL)?;
+
+    let mut buffer = vec![0u16; expected.len()];
+    let info = decoder.decode_into_slice(super::SAMPLE_JXL, &mut buffer)?;
+    assert_eq!((info.xsize, info.ysize), (40, 50));
+    assert_eq!(buffer, expected);
+
+    assert!(matches!(
+        decoder.decode_into_slice(super::SAMPLE_JXL, &mut buffer[1..]),
+        Err(DecodeError::BufferTooSmall { required, actual }) if actual == required - 2
+    ));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "memmap2")]
+fn decode_to_file() -> TestResult {
+    let mut decoder = decoder_builder().build()?;
+    let (_, expected) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+
+    let path = std::env::temp_dir().join(format!("jpegxl-rs-{}.raw", std::process::id()));
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)?;
+
+    let (_, map) = unsafe { decoder.decode_to_file::<u8>(super::SAMPLE_JXL, &file)? };
+    assert_eq!(&map[..], &expected[..]);
+
+    drop(map);
+    std::fs::remove_file(path)?;
+
+    Ok(())
+}
+
+#[test]
+fn reader() -> TestResult {
+    use std::io::BufReader;
+
+    let mut decoder = decoder_builder().build()?;
+    let (Metadata { width, height, .. }, expected) =
+        decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+
+    let (info, data) = decoder.decode_reader::<u8, _>(BufReader::new(super::SAMPLE_JXL))?;
+    assert_eq!((info.xsize, info.ysize), (width, height));
+    assert_eq!(data, expected);
+
+    // Truncated input
+    assert!(matches!(
+        decoder.decode_reader::<u8, _>(&super::SAMPLE_JXL[..1024]),
+        Err(DecodeError::GenericError)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn chunked() -> TestResult {
+    use crate::decode::DecoderEvent;
+
+    let mut decoder = decoder_builder().build()?;
+    let (_, expected) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+
+    let mut chunks = super::SAMPLE_JXL.chunks(256);
+    let mut chunked = decoder.decode_chunked::<u8>()?;
+    let (mut info, mut frames) = (None, vec![]);
+    loop {
+        match chunked.next_event()? {
+            DecoderEvent::BasicInfo(basic_info) => info = Some(basic_info),
+            DecoderEvent::Frame(frame) => frames.push(frame),
+            DecoderEvent::Progress(_) => unreachable!("progress without decode_progressive"),
+            DecoderEvent::NeedMoreInput => match chunks.next() {
+                Some(chunk) => chunked.feed(chunk)?,
+                None => chunked.close(),
+            },
+            DecoderEvent::Finished => break,
+        }
+    }
+    drop(chunked);
+
+    let info = info.ok_or("no basic info")?;
+    assert_eq!((info.xsize, info.ysize), (40, 50));
+    assert_eq!(frames.len(), 1);
+    assert!(frames[0].is_last);
+    assert_eq!(frames[0].data, expected);
+
+    // Truncated input
+    let mut chunked = decoder.decode_chunked::<u8>()?;
+    chunked.feed(&super::SAMPLE_JXL[..1024])?;
+    assert!(matches!(chunked.next_event()?, DecoderEvent::BasicInfo(_)));
+    assert!(matches!(chunked.next_event()?, DecoderEvent::NeedMoreInput));
+    chunked.close();
+    assert!(matches!(
+        chunked.next_event(),
+        Err(DecodeError::GenericError)
+    ));
+    drop(chunked);
+
+    // The decoder is still usable
+    decoder.decode(super::SAMPLE_JXL)?;
+
+    Ok(())
+}
+
+#[test]
+fn chunked_rewind() -> TestResult {
+    use crate::decode::DecoderEvent;
+
+    let mut decoder = decoder_builder().build()?;
+    let (_, expected) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+
+    let mut chunked = decoder.decode_chunked::<u8>()?;
+    chunked.feed(&super::SAMPLE_JXL[..1024])?;
+    assert!(matches!(chunked.next_event()?, DecoderEvent::BasicInfo(_)));
+    assert!(matches!(chunked.next_event()?, DecoderEvent::NeedMoreInput));
+
+    // Decode again from the start, with the whole input
+    chunked.rewind();
+    chunked.feed(super::SAMPLE_JXL)?;
+    chunked.close();
+    assert!(matches!(chunked.next_event()?, DecoderEvent::BasicInfo(_)));
+    match chunked.next_event()? {
+        DecoderEvent::Frame(frame) => assert_eq!(frame.data, expected),
+        _ => unreachable!("the whole input is fed"),
+    }
+    assert!(matches!(chunked.next_event()?, DecoderEvent::Finished));
+
+    // Skipping the only frame
+    chunked.rewind();
+    chunked.feed(super::SAMPLE_JXL)?;
+    chunked.close();
+    chunked.skip_frames(1);
+    assert!(matches!(chunked.next_event()?, DecoderEvent::BasicInfo(_)));
+    assert!(matches!(chunked.next_event()?, DecoderEvent::Finished));
+
+    Ok(())
+}
+
+#[test]
+fn reuse_after_error() -> TestResult {
+    let mut decoder = decoder_builder().build()?;
+    let (_, expected) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+
+    assert!(decoder
+        .decode_with::<u8>(&super::SAMPLE_JXL[..1024])
+        .is_err());
+    let (_, data) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+    assert_eq!(data, expected);
+
+    decoder.reset();
+    let (_, data) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+    assert_eq!(data, expected);
+
+    Ok(())
+}
+
+#[test]
+fn decode_progressive() -> TestResult {
+    use crate::{decode::DecoderEvent, encode::EncoderResult, encoder_builder};
+
+    let sample = image::load_from_memory(super::SAMPLE_PNG)?.to_rgb8();
+    let mut encoder = encoder_builder().build()?;
+    let result: EncoderResult<u8> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+
+    let mut decoder = decoder_builder().build()?;
+    let (_, expected) = decoder.decode_with::<u8>(&result)?;
+
+    let mut chunks = result.chunks(64);
+    let mut progressive = decoder.decode_progressive::<u8>()?;
+    let (mut previews, mut frames) = (vec![], vec![]);
+    loop {
+        match progressive.next_event()? {
+            DecoderEvent::BasicInfo(_) => {}
+            DecoderEvent::Progress(frame) => previews.push(frame),
+            DecoderEvent::Frame(frame) => frames.push(frame),
+            DecoderEvent::NeedMoreInput => match chunks.next() {
+                Some(chunk) => progressive.feed(chunk)?,
+                None => progressive.close(),
+            },
+            DecoderEvent::Finished => break,
+        }
+    }
+    drop(progressive);
+
+    // The DC is rendered before the full image
+    assert!(!previews.is_empty());
+    for preview in &previews {
+        assert_eq!((preview.width, preview.height), (40, 50));
+        assert_eq!(preview.data.len(), expected.len());
+    }
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].data, expected);
+
+    Ok(())
+}
+
+#[test]
+fn output_color() -> TestResult {
+    use crate::decode::ColorEncoding;
+
+    let decoder = decoder_builder().build()?;
+    let (_, expected) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+
+    // The sample is sRGB, so converting to sRGB must not change it
+    let decoder = decoder_builder()
+        .output_color(ColorEncoding::Srgb)
+        .build()?;
+    let (_, data) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+    assert_eq!(data, expected);
+
+    let decoder = decoder_builder()
+        .output_color(ColorEncoding::LinearSrgb)
+        .build()?;
+    let (_, data) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+    assert_eq!(data.len(), expected.len());
+    assert_ne!(data, expected);
+
+    Ok(())
+}
+
+#[test]
+fn alpha_channel() -> TestResult {
+    let decoder = decoder_builder().build()?;
+
+    let (
+        Metadata {
+            has_alpha_channel, ..
+        },
+        data,
+    ) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+    assert!(has_alpha_channel);
+    assert_eq!(data.len(), 40 * 50 * 4);
+
+    let (
+        Metadata {
+            width,
+            height,
+            has_alpha_channel,
+            ..
+        },
+        data,
+    ) = decoder.decode_with::<u8>(super::SAMPLE_JXL_GRAY)?;
+    assert_eq!(
+        data.len(),
+        (width * height * (1 + u32::from(has_alpha_channel))) as usize
+    );
+    assert_eq!(
+        data.len(),
+        decoder.required_buffer_size::<u8>(super::SAMPLE_JXL_GRAY)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn rendering_intent() -> TestResult {
+    use crate::decode::{ColorEncoding, RenderingIntent};
+
+    let decoder = decoder_builder()
+        .output_color(ColorEncoding::LinearSrgb)
+        .build()?;
+    let (_, expected) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+
+    for intent in [
+        RenderingIntent::Perceptual,
+        RenderingIntent::Saturation,
+        RenderingIntent::Absolute,
+    ] {
+        let decoder = decoder_builder()
+            .output_color(ColorEncoding::LinearSrgb)
+            .rendering_intent(intent)
+            .build()?;
+        let (_, data) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+        assert_eq!(data.len(), expected.len());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn srgb_and_original() -> TestResult {
+    use crate::decode::ColorEncoding;
+
+    let decoder = decoder_builder()
+        .output_color(ColorEncoding::LinearSrgb)
+        .build()?;
+
+    let (Metadata { width, height, .. }, display, original) =
+        decoder.decode_srgb_and_original(super::SAMPLE_JXL)?;
+    assert_eq!(display.len(), (width * height * 4) as usize);
+    let Pixels::Uint16(original) = original else {
+        return Err("Failed to decode the original pixels".into());
+    };
+    assert_eq!(original.len(), display.len());
+
+    // The output color set by the user is kept
+    assert!(matches!(
+        decoder.output_color,
+        Some(ColorEncoding::LinearSrgb)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn num_color_channels() -> TestResult {
+    let decoder = decoder_builder().num_color_channels(3).build()?;
+
+    let (
+        Metadata {
+            width,
+            height,
+            num_color_channels,
+            has_alpha_channel,
+            ..
+        },
+        data,
+    ) = decoder.decode_with::<u8>(super::SAMPLE_JXL_GRAY)?;
+    assert_eq!(num_color_channels, 1);
+    assert_eq!(
+        data.len(),
+        (width * height * (3 + u32::from(has_alpha_channel))) as usize
+    );
+
+    let decoder = decoder_builder().num_color_channels(2).build()?;
+    assert!(matches!(
+        decoder.decode(super::SAMPLE_JXL_GRAY),
+        Err(DecodeError::UnsupportedColorChannels(2))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn channel_layout() -> TestResult {
+    let decoder = decoder_builder().grayscale().build()?;
+    let (Metadata { width, height, .. }, data) =
+        decoder.decode_with::<u8>(super::SAMPLE_JXL_GRAY)?;
+    assert_eq!(data.len() % (width * height) as usize, 0);
+    assert!(data.len() <= (width * height * 2) as usize);
+
+    assert!(matches!(
+        decoder.decode(super::SAMPLE_JXL),
+        Err(DecodeError::UnsupportedChannelLayout {
+            requested: 2,
+            color_channels: 3
+        })
+    ));
+
+    for num_channels in [1, 5] {
+        let decoder = decoder_builder()
+            .pixel_format(PixelFormat {
+                num_channels,
+                ..PixelFormat::default()
+            })
+            .build()?;
+        assert!(matches!(
+            decoder.decode(super::SAMPLE_JXL),
+            Err(DecodeError::UnsupportedChannelLayout { requested, .. }) if requested == num_channels
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn expect_dimensions() -> TestResult {
+    let decoder = decoder_builder().expect_dimensions((40, 50)).build()?;
+    decoder.decode(super::SAMPLE_JXL)?;
+
+    let mut decoder = decoder_builder().expect_dimensions((50, 40)).build()?;
+    assert!(matches!(
+        decoder.decode(super::SAMPLE_JXL),
+        Err(DecodeError::UnexpectedDimensions {
+            expected: (50, 40),
+            actual: (40, 50)
+        })
+    ));
+    assert!(matches!(
+        decoder.decode_frames::<u8>(super::SAMPLE_JXL),
+        Err(DecodeError::UnexpectedDimensions { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn require_color_type() -> TestResult {
+    let decoder = decoder_builder()
+        .require_color_type(ColorType::Rgba)
+        .build()?;
+    decoder.decode(super::SAMPLE_JXL)?;
+
+    // Rejected at the basic info, before the pixels are decoded
+    let mut decoder = decoder_builder()
+        .require_color_type(ColorType::Rgb)
+        .build()?;
+    assert!(matches!(
+        decoder.decode(super::SAMPLE_JXL),
+        Err(DecodeError::UnexpectedColorType {
+            expected: ColorType::Rgb,
+            actual: ColorType::Rgba
+        })
+    ));
+    assert!(matches!(
+        decoder.decode_frames::<u8>(super::SAMPLE_JXL),
+        Err(DecodeError::UnexpectedColorType { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn max_pixels() -> TestResult {
+    let decoder = decoder_builder().max_pixels(40 * 50).build()?;
+    decoder.decode(super::SAMPLE_JXL)?;
+
+    let mut decoder = decoder_builder().max_pixels(40 * 50 - 1).build()?;
+    assert!(matches!(
+        decoder.decode(super::SAMPLE_JXL),
+        Err(DecodeError::TooManyPixels {
+            pixels: 2000,
+            max: 1999
+        })
+    ));
+    assert!(matches!(
+        decoder.decode_frames::<u8>(super::SAMPLE_JXL),
+        Err(DecodeError::TooManyPixels { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn strict_boxes() -> TestResult {
+    let data = super::with_box(*b"abcd", b"unknown");
+
+    let decoder = decoder_builder().build()?;
+    decoder.decode(&data)?;
+
+    let mut decoder = decoder_builder().strict_boxes(true).build()?;
+    decoder.decode(super::SAMPLE_JXL_JPEG)?;
+    decoder.decode_frames::<u8>(super::SAMPLE_JXL_JPEG)?;
+    assert!(matches!(
+        decoder.decode(&data),
+        Err(DecodeError::UnknownBox(t)) if &t == b"abcd"
+    ));
+    assert!(matches!(
+        decoder.decode_frames::<u8>(&data),
+        Err(DecodeError::UnknownBox(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+#[allow(clippy::float_cmp)]
+fn intensity_target() -> TestResult {
+    let decoder = decoder_builder().build()?;
+    let (metadata, _) = decoder.decode(super::SAMPLE_JXL)?;
+
+    // SDR sample
+    assert_eq!(metadata.intensity_target, 255.0);
+    assert!(!metadata.is_hdr());
+
+    Ok(())
+}
+
+#[test]
+fn color_transform_fn() -> TestResult {
+    use std::cell::Cell;
 
-    let Pixels::Uint16(data) = data else {
-        return Err("Failed to decode".into());
+    use jpegxl_sys::{
+        color_encoding::{JxlColorEncoding, JxlColorSpace},
+        types::{JxlDataType, JxlPixelFormat},
     };
 
-    assert_eq!(data.len(), (width * height * 4) as usize);
-    // Check if icc profile is valid
-    lcms2::Profile::new_icc(&icc_profile.expect("ICC profile not retrieved"))?;
+    let decoder = decoder_builder().build()?;
+    let (_, expected) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+
+    let ran = Cell::new(false);
+    let invert_red =
+        |pixels: &mut [u8], format: &JxlPixelFormat, encoding: Option<&JxlColorEncoding>| {
+            assert_eq!(format.num_channels, 4);
+            assert_eq!(format.data_type, JxlDataType::Uint8);
+            assert!(matches!(
+                encoding.map(|e| e.color_space),
+                Some(JxlColorSpace::Rgb)
+            ));
+
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel[0] = 255 - pixel[0];
+            }
+            ran.set(true);
+        };
+    let decoder = decoder_builder().color_transform_fn(&invert_red).build()?;
+    let (_, data) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+
+    assert!(ran.get());
+    assert_eq!(data.len(), expected.len());
+    for (pixel, expected) in data.chunks_exact(4).zip(expected.chunks_exact(4)) {
+        assert_eq!(pixel[0], 255 - expected[0]);
+        assert_eq!(pixel[1..], expected[1..]);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[allow(clippy::manual_slice_size_calculation)]
+fn decode_buffer() -> TestResult {
+    let decoder = decoder_builder().build()?;
+
+    let (_, expected) = decoder.decode_with::<u16>(super::SAMPLE_JXL)?;
+    let (Metadata { width, height, .. }, buffer) =
+        decoder.decode_buffer::<u16>(super::SAMPLE_JXL)?;
+    assert_eq!(buffer.as_typed(), expected);
+    assert_eq!(buffer.as_typed().len(), (width * height * 4) as usize);
+    assert_eq!(
+        buffer.as_bytes().len(),
+        buffer.as_typed().len() * std::mem::size_of::<u16>()
+    );
+    assert_eq!(buffer.as_bytes()[..2], expected[0].to_ne_bytes());
+    assert_eq!(buffer.into_vec(), expected);
+
+    let (_, buffer) = decoder.decode_buffer::<f32>(super::SAMPLE_JXL)?;
+    assert_eq!(
+        buffer.as_bytes().len(),
+        buffer.as_typed().len() * std::mem::size_of::<f32>()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn sanitize_floats() -> TestResult {
+    use jpegxl_sys::{color_encoding::JxlColorEncoding, types::JxlPixelFormat};
+
+    let decoder = decoder_builder().build()?;
+    let (_, expected) = decoder.decode_with::<f32>(super::SAMPLE_JXL)?;
+
+    // Inject non-finite samples in the decoded pixels
+    let inject = |pixels: &mut [u8], _: &JxlPixelFormat, _: Option<&JxlColorEncoding>| {
+        pixels[..4].copy_from_slice(&f32::NAN.to_ne_bytes());
+        pixels[4..8].copy_from_slice(&f32::INFINITY.to_ne_bytes());
+    };
+    let decoder = decoder_builder()
+        .color_transform_fn(&inject)
+        .sanitize_floats(0.0)
+        .build()?;
+    let (_, data) = decoder.decode_with::<f32>(super::SAMPLE_JXL)?;
+    assert_eq!(data[..2], [0.0, 0.0]);
+    assert_eq!(data[2..], expected[2..]);
+
+    // Integer samples are left as is
+    let (_, expected) = decoder_builder()
+        .build()?
+        .decode_with::<u8>(super::SAMPLE_JXL)?;
+    let decoder = decoder_builder().sanitize_floats(0.0).build()?;
+    assert_eq!(decoder.decode_with::<u8>(super::SAMPLE_JXL)?.1, expected);
+
+    Ok(())
+}
+
+#[test]
+fn decode_to_luma() -> TestResult {
+    use crate::{encode::EncoderResult, encoder_builder};
+
+    let decoder = decoder_builder().build()?;
+    let (width, height, luma) = decoder.decode_to_luma::<u8>(super::SAMPLE_JXL)?;
+    assert_eq!((width, height), (40, 50));
+    assert_eq!(luma.len(), 40 * 50);
+
+    // Pure red
+    let red = [255u8, 0, 0].repeat(8 * 8);
+    let mut encoder = encoder_builder()
+        .lossless(true)
+        .uses_original_profile(true)
+        .build()?;
+    let result: EncoderResult<u8> = encoder.encode(&red, 8, 8)?;
+    let (_, _, luma) = decoder.decode_to_luma::<u8>(&result)?;
+    assert_eq!(luma, [54; 8 * 8]);
+
+    // Grayscale images are returned as is
+    let (_, expected) = decoder.decode_with::<u8>(super::SAMPLE_JXL_GRAY)?;
+    let (_, _, luma) = decoder.decode_to_luma::<u8>(super::SAMPLE_JXL_GRAY)?;
+    assert_eq!(luma, expected);
+
+    // The padding of the rows is skipped
+    let (_, _, expected) = decoder.decode_to_luma::<u8>(super::SAMPLE_JXL)?;
+    let decoder = decoder_builder()
+        .pixel_format(PixelFormat {
+            num_channels: 3,
+            align: 64,
+            ..PixelFormat::default()
+        })
+        .build()?;
+    let (_, _, luma) = decoder.decode_to_luma::<u8>(super::SAMPLE_JXL)?;
+    assert_eq!(luma.len(), 40 * 50);
+    assert_eq!(luma, expected);
+
+    Ok(())
+}
+
+#[test]
+fn decode_to_writer() -> TestResult {
+    let decoder = decoder_builder().build()?;
+    let (_, expected) = decoder.decode_with::<u16>(super::SAMPLE_JXL)?;
+
+    let mut decoder = decoder_builder().build()?;
+    let mut bytes = vec![];
+    let info = decoder.decode_to_writer::<u16, _>(super::SAMPLE_JXL, &mut bytes)?;
+    assert_eq!((info.xsize, info.ysize), (40, 50));
+    assert_eq!(
+        bytes,
+        expected
+            .iter()
+            .flat_map(|v| v.to_ne_bytes())
+            .collect::<Vec<_>>()
+    );
+
+    // Write errors are returned
+    let mut small = [0u8; 16];
+    assert!(matches!(
+        decoder.decode_to_writer::<u8, _>(super::SAMPLE_JXL, &mut small[..]),
+        Err(DecodeError::Io(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn decode_tiles() -> TestResult {
+    use crate::{
+        decode::TileEdge,
+        encode::{EncoderFrame, EncoderResult},
+        encoder_builder,
+    };
+
+    let (width, height) = (150, 100);
+    let pixels: Vec<u8> = (0..width * height)
+        .flat_map(|i: u32| [i.to_le_bytes()[0], i.to_le_bytes()[1], 0])
+        .collect();
+    let mut encoder = encoder_builder().build()?;
+    let result: EncoderResult<u8> = encoder
+        .multiple(width, height)?
+        .add_frame(&EncoderFrame::new(&pixels))?
+        .encode()?;
+
+    let decoder = decoder_builder().build()?;
+    let (_, full) = decoder.decode_with::<u8>(&result)?;
+    let region = |x: usize, y: usize, w: usize, h: usize| -> Vec<u8> {
+        (y..y + h)
+            .flat_map(|row| {
+                &full[(row * width as usize + x) * 3..(row * width as usize + x + w) * 3]
+            })
+            .copied()
+            .collect()
+    };
+
+    let (_, tiles) = decoder.decode_tiles::<u8>(&result, 64, 64, TileEdge::Clip)?;
+    assert_eq!(
+        tiles
+            .iter()
+            .map(|t| (t.column, t.row, t.width, t.height))
+            .collect::<Vec<_>>(),
+        [
+            (0, 0, 64, 64),
+            (1, 0, 64, 64),
+            (2, 0, 22, 64),
+            (0, 1, 64, 36),
+            (1, 1, 64, 36),
+            (2, 1, 22, 36)
+        ]
+    );
+    assert_eq!(tiles[1].data, region(64, 0, 64, 64));
+    assert_eq!(tiles[5].data, region(128, 64, 22, 36));
+
+    let (_, tiles) = decoder.decode_tiles::<u8>(&result, 64, 64, TileEdge::Pad)?;
+    assert!(tiles.iter().all(|t| (t.width, t.height) == (64, 64)));
+    let edge = &tiles[5].data;
+    assert_eq!(edge.len(), 64 * 64 * 3);
+    assert_eq!(edge[..22 * 3], region(128, 64, 22, 1));
+    assert!(edge[22 * 3..64 * 3].iter().all(|&v| v == 0));
+    assert!(edge[36 * 64 * 3..].iter().all(|&v| v == 0));
+
+    // Rows padded by the alignment
+    let decoder = decoder_builder()
+        .pixel_format(PixelFormat {
+            num_channels: 3,
+            align: 64,
+            ..PixelFormat::default()
+        })
+        .build()?;
+    let (_, padded) = decoder.decode_tiles::<u8>(&result, 64, 64, TileEdge::Clip)?;
+    assert_eq!(padded[1].data, region(64, 0, 64, 64));
+    assert_eq!(padded[5].data, region(128, 64, 22, 36));
+
+    Ok(())
+}
+
+#[test]
+fn decode_region() -> TestResult {
+    let decoder = decoder_builder().build()?;
+    let (metadata, pixels) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+    let stride = metadata.width as usize * 4;
+
+    let (info, region) = decoder.decode_region::<u8>(super::SAMPLE_JXL, (10, 20), (25, 30))?;
+    assert_eq!((info.xsize, info.ysize), (40, 50));
+    assert_eq!(region.len(), 25 * 30 * 4);
+    for row in 0..30 {
+        let src = (20 + row) * stride + 10 * 4;
+        assert_eq!(
+            region[row * 25 * 4..(row + 1) * 25 * 4],
+            pixels[src..src + 25 * 4]
+        );
+    }
+
+    // The whole image
+    let (_, region) = decoder.decode_region::<u8>(super::SAMPLE_JXL, (0, 0), (40, 50))?;
+    assert_eq!(region, pixels);
+
+    for (origin, size) in [
+        ((30, 0), (11, 10)),
+        ((0, 0), (0, 10)),
+        ((0, u32::MAX), (1, 1)),
+    ] {
+        assert!(matches!(
+            decoder.decode_region::<u8>(super::SAMPLE_JXL, origin, size),
+            Err(DecodeError::RegionOutOfBounds {
+                image: (40, 50),
+                ..
+            })
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tone_map_to_sdr() -> TestResult {
+    let decoder = decoder_builder().build()?;
+    let (_, expected) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+
+    // SDR images are left as is
+    let decoder = decoder_builder().tone_map_to_sdr(true).build()?;
+    let (_, data) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+    assert_eq!(data, expected);
+
+    Ok(())
+}
+
+#[test]
+fn endianness() -> TestResult {
+    let decode = |endianness| -> Result<_, DecodeError> {
+        let decoder = decoder_builder()
+            .pixel_format(PixelFormat {
+                endianness,
+                ..PixelFormat::default()
+            })
+            .build()?;
+        Ok((
+            decoder.decode_with::<u16>(super::SAMPLE_JXL)?.1,
+            decoder.decode_with::<f32>(super::SAMPLE_JXL)?.1,
+        ))
+    };
+
+    let native = decode(crate::Endianness::Native)?;
+    assert_eq!(decode(crate::Endianness::Little)?, native);
+    assert_eq!(decode(crate::Endianness::Big)?, native);
+
+    Ok(())
+}
+
+#[test]
+fn align() -> TestResult {
+    let format = PixelFormat {
+        num_channels: 3,
+        align: 64,
+        ..PixelFormat::default()
+    };
+    let decoder = decoder_builder().pixel_format(format).build()?;
+
+    let (Metadata { width, height, .. }, data) = decoder.decode_with::<u16>(super::SAMPLE_JXL)?;
+    assert_eq!(format.bytes_per_pixel::<u16>(), 6);
+    assert_eq!(format.row_bytes::<u16>(width), 256);
+    assert_eq!(
+        data.len() * 2,
+        format.row_bytes::<u16>(width) * (height as usize - 1)
+            + format.bytes_per_pixel::<u16>() * width as usize
+    );
+
+    Ok(())
+}
+
+#[test]
+fn decode_image() -> TestResult {
+    let format = PixelFormat {
+        num_channels: 3,
+        align: 64,
+        ..PixelFormat::default()
+    };
+    let decoder = decoder_builder().pixel_format(format).build()?;
+
+    let (_, data) = decoder.decode_with::<u16>(super::SAMPLE_JXL)?;
+    let (Metadata { width, height, .. }, image) = decoder.decode_image::<u16>(super::SAMPLE_JXL)?;
+    assert_eq!((image.width, image.height), (width, height));
+    assert_eq!(image.num_channels, 3);
+    assert_eq!(image.stride, 128);
+    assert_eq!(image.data, data);
+
+    let rows: Vec<_> = image.as_rows().collect();
+    assert_eq!(rows.len(), height as usize);
+    assert!(rows.iter().all(|row| row.len() == width as usize * 3));
+    assert_eq!(rows[1], &data[128..128 + width as usize * 3]);
+
+    assert_eq!(image.pixel(0, 0), Some(&data[..3]));
+    assert_eq!(image.pixel(2, 1), Some(&data[134..137]));
+    assert_eq!(image.pixel(width, 0), None);
+    assert_eq!(image.pixel(0, height), None);
+
+    let decoder = decoder_builder().build()?;
+    let (_, image) = decoder.decode_image::<f32>(super::SAMPLE_JXL)?;
+    assert_eq!(image.stride, width as usize * 4);
+    assert_eq!(image.data.len(), image.stride * height as usize);
+
+    Ok(())
+}
+
+#[test]
+fn decode_rgba8() -> TestResult {
+    let format = PixelFormat {
+        num_channels: 3,
+        align: 64,
+        ..PixelFormat::default()
+    };
+    let decoder = decoder_builder().pixel_format(format).build()?;
+
+    let (width, height, pixels) = decoder.decode_rgba8(super::SAMPLE_JXL)?;
+    assert_eq!((width, height), (40, 50));
+    assert_eq!(pixels.len(), (width * height * 4) as usize);
+    assert_eq!(decoder.pixel_format.map(|f| f.num_channels), Some(3));
+
+    assert!(decoder.decode_rgba8(&[]).is_err());
+    assert_eq!(decoder.pixel_format.map(|f| f.num_channels), Some(3));
+
+    Ok(())
+}
+
+#[test]
+fn on_progress() -> TestResult {
+    let reports = std::sync::Mutex::new(vec![]);
+    let on_progress = |progress| reports.lock().unwrap().push(progress);
+    let decoder = decoder_builder().on_progress(&on_progress).build()?;
+
+    let (_, expected) = decoder_builder()
+        .build()?
+        .decode_with::<u8>(super::SAMPLE_JXL)?;
+    let (_, pixels) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+    assert_eq!(pixels, expected);
+    drop(decoder);
+
+    let reports = reports.into_inner()?;
+    let last = reports.last().ok_or("No progress reported")?;
+    assert_eq!(last.bytes_consumed, super::SAMPLE_JXL.len());
+    assert_eq!(last.total_bytes, super::SAMPLE_JXL.len());
+    assert_eq!(last.frames_completed, 1);
+    assert!(reports
+        .windows(2)
+        .all(|w| w[0].bytes_consumed <= w[1].bytes_consumed));
 
     Ok(())
 }
@@ -62,6 +1500,18 @@ fn sample_2bit() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn cmyk() -> TestResult {
+    let decoder = decoder_builder().build()?;
+
+    assert!(matches!(
+        decoder.decode(super::SAMPLE_JXL_CMYK),
+        Err(DecodeError::UnsupportedColorModel)
+    ));
+
+    Ok(())
+}
+
 #[test]
 fn pixel_types() -> TestResult {
     let mut decoder = decoder_builder().build()?;
@@ -89,6 +1539,39 @@ fn pixel_types() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn pixel_arrays() -> TestResult {
+    let decoder = decoder_builder().build()?;
+
+    let (Metadata { width, height, .. }, flat) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+    let (_, pixels) = decoder.decode_pixels::<u8, 4>(super::SAMPLE_JXL)?;
+    assert_eq!(pixels.len(), (width * height) as usize);
+
+    let idx = (20 * width + 10) as usize;
+    assert_eq!(pixels[idx], flat[idx * 4..idx * 4 + 4]);
+
+    assert!(matches!(
+        decoder.decode_pixels::<u8, 3>(super::SAMPLE_JXL),
+        Err(DecodeError::ChannelMismatch {
+            expected: 3,
+            actual: 4
+        })
+    ));
+
+    // The padding of the rows is skipped
+    let decoder = decoder_builder()
+        .pixel_format(PixelFormat {
+            num_channels: 4,
+            align: 64,
+            ..PixelFormat::default()
+        })
+        .build()?;
+    let (_, padded) = decoder.decode_pixels::<u8, 4>(super::SAMPLE_JXL)?;
+    assert_eq!(padded, pixels);
+
+    Ok(())
+}
+
 #[test]
 fn jpeg() -> TestResult {
     let decoder = decoder_builder().init_jpeg_buffer(512).build()?;
@@ -108,6 +1591,111 @@ fn jpeg() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn decode_to_jpeg() -> TestResult {
+    use crate::encoder_builder;
+
+    let mut encoder = encoder_builder().use_container(true).build()?;
+    let jxl = encoder.encode_jpeg(super::SAMPLE_JPEG)?;
+
+    let decoder = decoder_builder().build()?;
+    assert_eq!(decoder.decode_to_jpeg(&jxl)?, super::SAMPLE_JPEG);
+    assert!(matches!(
+        decoder.decode_to_jpeg(super::SAMPLE_JXL),
+        Err(DecodeError::NoJpegReconstruction)
+    ));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "threads")]
+fn shared_runner() -> TestResult {
+    use std::rc::Rc;
+
+    use crate::encoder_builder;
+
+    let runner = ThreadsRunner::default();
+    let first = decoder_builder().parallel_runner(&runner).build()?;
+    let second = decoder_builder().parallel_runner(&runner).build()?;
+
+    let (_, expected) = first.decode_with::<u8>(super::SAMPLE_JXL)?;
+    let (_, data) = second.decode_with::<u8>(super::SAMPLE_JXL)?;
+    assert_eq!(data, expected);
+
+    // Decode then re-encode with the same thread pool
+    let runner = Rc::new(ThreadsRunner::default());
+    let decoder = decoder_builder().parallel_runner(&runner).build()?;
+    let mut encoder = encoder_builder()
+        .has_alpha(true)
+        .parallel_runner(&runner)
+        .build()?;
+
+    let (Metadata { width, height, .. }, data) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+    let result: crate::encode::EncoderResult<u8> = encoder.encode_frame(
+        &crate::encode::EncoderFrame::new(&data).num_channels(4),
+        width,
+        height,
+    )?;
+    decoder.decode(&result)?;
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "threads")]
+fn cancel() -> TestResult {
+    use std::{
+        ffi::c_void,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use crate::{
+        decode::BasicInfo,
+        parallel::{JxlParallelRunner, RunnerFn},
+    };
+
+    // Cancel the decoding right after the basic info event
+    struct CancelRunner<'a> {
+        runner: ThreadsRunner<'a>,
+        cancel: &'a AtomicBool,
+    }
+
+    impl JxlParallelRunner for CancelRunner<'_> {
+        fn runner(&self) -> RunnerFn {
+            self.runner.runner()
+        }
+
+        fn as_opaque_ptr(&self) -> *mut c_void {
+            self.runner.as_opaque_ptr()
+        }
+
+        fn callback_basic_info(&self, _basic_info: &BasicInfo) {
+            self.cancel.store(true, Ordering::Relaxed);
+        }
+    }
+
+    let cancel = AtomicBool::new(false);
+    let runner = CancelRunner {
+        runner: ThreadsRunner::default(),
+        cancel: &cancel,
+    };
+    let mut decoder = decoder_builder().parallel_runner(&runner).build()?;
+
+    assert!(matches!(
+        decoder.decode_cancellable(super::SAMPLE_JXL, &cancel),
+        Err(DecodeError::Cancelled)
+    ));
+
+    // The decoder is reusable after cancelling
+    decoder.parallel_runner = None;
+    cancel.store(false, Ordering::Relaxed);
+    decoder.decode_cancellable(super::SAMPLE_JXL, &cancel)?;
+    decoder.decode(super::SAMPLE_JXL)?;
+
+    Ok(())
+}
+
 #[test]
 #[cfg(feature = "threads")]
 fn builder() -> TestResult {
@@ -149,3 +1737,109 @@ fn builder() -> TestResult {
 
     Ok(())
 }
+
+#[test]
+#[cfg(feature = "threads")]
+fn auto_single_thread_below() -> TestResult {
+    let runner = ThreadsRunner::default();
+
+    // 40x50 pixels, decoded with the runner by default
+    let decoder = decoder_builder().parallel_runner(&runner).build()?;
+    assert_eq!(decoder.auto_single_thread_below, 0);
+    let (_, threaded) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+
+    let decoder = decoder_builder()
+        .parallel_runner(&runner)
+        .auto_single_thread_below(256 * 256)
+        .build()?;
+    let (_, serial) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+    assert_eq!(serial, threaded);
+
+    let decoder = decoder_builder().build()?;
+    assert_eq!(decoder.decode_with::<u8>(super::SAMPLE_JXL)?.1, serial);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "threads")]
+fn fork_builder() -> TestResult {
+    let runner = ThreadsRunner::default();
+    let mut template = decoder_builder();
+    template
+        .pixel_format(PixelFormat {
+            num_channels: 3,
+            endianness: Endianness::Big,
+            ..PixelFormat::default()
+        })
+        .parallel_runner(&runner);
+
+    let first = template.clone().build()?;
+    let second = template.clone().icc_profile(true).build()?;
+    let base = template.build()?;
+
+    let (_, expected) = base.decode_with::<u16>(super::SAMPLE_JXL)?;
+    assert_eq!(first.decode_with::<u16>(super::SAMPLE_JXL)?.1, expected);
+    assert_eq!(second.decode_with::<u16>(super::SAMPLE_JXL)?.1, expected);
+    assert!(second.icc_profile && !base.icc_profile);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "futures")]
+fn stream() -> TestResult {
+    use futures::{executor::block_on, stream, StreamExt};
+
+    let mut decoder = decoder_builder().build()?;
+    let (_, expected) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+
+    let input = stream::iter(super::SAMPLE_JXL.chunks(256));
+    let frames = block_on(decoder.decode_stream::<u8, _>(input)?.collect::<Vec<_>>());
+    assert_eq!(frames.len(), 1);
+
+    let frame = frames.into_iter().next().unwrap()?;
+    assert!(frame.is_last);
+    assert_eq!((frame.width, frame.height), (40, 50));
+    assert_eq!(frame.data, expected);
+
+    // Truncated input
+    let input = stream::iter([&super::SAMPLE_JXL[..1024]]);
+    let frames = block_on(decoder.decode_stream::<u8, _>(input)?.collect::<Vec<_>>());
+    assert!(matches!(frames[..], [Err(DecodeError::GenericError)]));
+
+    // The decoder is still usable
+    decoder.decode(super::SAMPLE_JXL)?;
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "futures")]
+fn async_read() -> TestResult {
+    use futures::{executor::block_on, io::Cursor, StreamExt};
+
+    let mut decoder = decoder_builder().build()?;
+    let (_, expected) = decoder.decode_with::<u8>(super::SAMPLE_JXL)?;
+
+    let input = Cursor::new(super::SAMPLE_JXL);
+    let frames = block_on(
+        decoder
+            .decode_async_read::<u8, _>(input)?
+            .collect::<Vec<_>>(),
+    );
+    assert_eq!(frames.len(), 1);
+    let frame = frames.into_iter().next().unwrap()?;
+    assert_eq!(frame.data, expected);
+
+    // Truncated input
+    let input = Cursor::new(&super::SAMPLE_JXL[..1024]);
+    let frames = block_on(
+        decoder
+            .decode_async_read::<u8, _>(input)?
+            .collect::<Vec<_>>(),
+    );
+    assert!(matches!(frames[..], [Err(DecodeError::GenericError)]));
+
+    Ok(())
+}
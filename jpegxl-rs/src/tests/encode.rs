@@ -4,9 +4,10 @@ use pretty_assertions::assert_eq;
 use testresult::TestResult;
 
 use crate::{
+    decode::Data,
     decoder_builder,
     encode::{ColorEncoding, EncoderFrame, EncoderResult},
-    encoder_builder, Endianness,
+    encoder_builder, EncodeError, Endianness,
 };
 #[cfg(feature = "threads")]
 use crate::{encode::EncoderSpeed, ResizableRunner, ThreadsRunner};
@@ -47,6 +48,83 @@ fn jpeg() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn jpeg_roundtrip() -> TestResult {
+    let mut encoder = encoder_builder()
+        .use_container(true)
+        .uses_original_profile(true)
+        .build()?;
+    let decoder = decoder_builder().build()?;
+
+    let res = encoder.encode_jpeg(super::SAMPLE_JPEG)?;
+    let (_, data) = decoder.reconstruct(&res)?;
+    let Data::Jpeg(jpeg) = data else {
+        return Err("Failed to reconstruct".into());
+    };
+    assert_eq!(jpeg, super::SAMPLE_JPEG);
+
+    assert!(matches!(
+        encoder.encode_jpeg(&[0xFF, 0xD8, 0xFF, 0x00]),
+        Err(EncodeError::JpegNotSupported)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn container() -> TestResult {
+    const CONTAINER: [u8; 12] = [0, 0, 0, 0xC, b'J', b'X', b'L', b' ', 0xD, 0xA, 0x87, 0xA];
+
+    let sample = get_sample().to_rgb8();
+    let mut encoder = encoder_builder().build()?;
+    let result: EncoderResult<u8> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+    assert!(result.starts_with(&[0xFF, 0x0A]));
+
+    encoder.use_container = true;
+    let result: EncoderResult<u8> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+    assert!(result.starts_with(&CONTAINER));
+
+    // JPEG reconstruction forces the container
+    let mut encoder = encoder_builder().uses_original_profile(true).build()?;
+    assert!(encoder
+        .encode_jpeg(super::SAMPLE_JPEG)?
+        .starts_with(&CONTAINER));
+
+    // So do animations
+    let mut encoder = encoder_builder()
+        .animation(crate::encode::Animation::milliseconds(0))
+        .build()?;
+    let result: EncoderResult<u8> = encoder
+        .multiple(sample.width(), sample.height())?
+        .add_frame(&EncoderFrame::new(sample.as_raw()).duration(10))?
+        .encode()?;
+    assert!(result.starts_with(&CONTAINER));
+
+    Ok(())
+}
+
+#[test]
+fn jpeg_recon_settings() -> TestResult {
+    let decoder = decoder_builder().build()?;
+
+    for (cfl, compress_boxes) in [(false, false), (true, true), (false, true)] {
+        let mut encoder = encoder_builder()
+            .use_container(true)
+            .uses_original_profile(true)
+            .jpeg_recon_cfl(cfl)
+            .jpeg_compress_boxes(compress_boxes)
+            .build()?;
+
+        let res = encoder.encode_jpeg(super::SAMPLE_JPEG)?;
+        let (_, data) = decoder.reconstruct(&res)?;
+        assert!(matches!(data, Data::Jpeg(jpeg) if jpeg == super::SAMPLE_JPEG));
+    }
+
+    Ok(())
+}
+
 #[test]
 #[cfg(feature = "threads")]
 fn builder() -> TestResult {
@@ -102,6 +180,30 @@ fn resizable() -> TestResult {
     Ok(())
 }
 
+#[test]
+#[cfg(feature = "threads")]
+fn parallel_runners_output() -> TestResult {
+    let sample = get_sample().to_rgb8();
+    let threads_runner = ThreadsRunner::default();
+    let resizable_runner = ResizableRunner::default();
+    let mut encoder = encoder_builder().speed(EncoderSpeed::Tortoise).build()?;
+
+    let reference: EncoderResult<u8> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+
+    encoder.parallel_runner = Some(&threads_runner);
+    let res: EncoderResult<u8> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+    assert_eq!(res.data, reference.data);
+
+    encoder.parallel_runner = Some(&resizable_runner);
+    let res: EncoderResult<u8> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+    assert_eq!(res.data, reference.data);
+
+    Ok(())
+}
+
 #[test]
 fn pixel_type() -> TestResult {
     let mut encoder = encoder_builder().has_alpha(true).build()?;
@@ -156,6 +258,274 @@ fn multi_frames() -> TestResult {
     Ok(())
 }
 
+#[test]
+#[allow(clippy::float_cmp)]
+fn animation() -> TestResult {
+    use crate::encode::Animation;
+
+    let sample = get_sample().to_rgb8();
+    let mut encoder = encoder_builder()
+        .animation(Animation {
+            tps_numerator: 100,
+            tps_denominator: 1,
+            num_loops: 3,
+        })
+        .build()?;
+
+    let result: EncoderResult<u8> = encoder
+        .multiple(sample.width(), sample.height())?
+        .add_frame(&EncoderFrame::new(sample.as_raw()).duration(10))?
+        .add_frame(&EncoderFrame::new(sample.as_raw()).duration(20))?
+        .add_frame(&EncoderFrame::new(sample.as_raw()).duration(30))?
+        .encode()?;
+
+    let mut decoder = decoder_builder().build()?;
+    let (info, frames) = decoder.decode_frames::<u8>(&result)?;
+    assert_eq!(info.animation.num_loops, 3);
+    assert_eq!(
+        frames.iter().map(|f| f.duration).collect::<Vec<_>>(),
+        [10, 20, 30]
+    );
+    assert_eq!(
+        frames.iter().map(|f| f.duration_ms(&info)).sum::<f64>(),
+        600.0
+    );
+    assert!(frames[2].is_last);
+
+    Ok(())
+}
+
+#[test]
+fn frame_names() -> TestResult {
+    let sample = get_sample().to_rgb8();
+    let mut encoder = encoder_builder().build()?;
+
+    let result: EncoderResult<u8> = encoder
+        .multiple(sample.width(), sample.height())?
+        .add_frame(&EncoderFrame::new(sample.as_raw()).name("Background"))?
+        .add_frame(&EncoderFrame::new(sample.as_raw()))?
+        .add_frame(&EncoderFrame::new(sample.as_raw()).name("Calque supérieur ✓"))?
+        .encode()?;
+
+    let mut decoder = decoder_builder().coalescing(false).build()?;
+    let (_, frames) = decoder.decode_frames::<u8>(&result)?;
+    assert_eq!(
+        frames.iter().map(|f| f.name.as_deref()).collect::<Vec<_>>(),
+        [Some("Background"), None, Some("Calque supérieur ✓")]
+    );
+
+    assert!(matches!(
+        encoder
+            .multiple::<u8>(sample.width(), sample.height())?
+            .add_frame(&EncoderFrame::new(sample.as_raw()).name("a\0b")),
+        Err(EncodeError::BadInput)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn layers() -> TestResult {
+    use jpegxl_sys::types::JxlBool;
+
+    use crate::encode::BlendMode;
+
+    let sample = get_sample().to_rgb8();
+    let (width, height) = (sample.width(), sample.height());
+    let patch = vec![255u8; 10 * 20 * 3];
+    let mut encoder = encoder_builder().build()?;
+
+    let result: EncoderResult<u8> = encoder
+        .multiple(width, height)?
+        .add_layer(
+            &EncoderFrame::new(sample.as_raw()),
+            (0, 0),
+            (width, height),
+            BlendMode::Replace,
+        )?
+        .add_layer(&EncoderFrame::new(&patch), (5, 8), (10, 20), BlendMode::Add)?
+        .encode()?;
+
+    let mut decoder = decoder_builder().coalescing(false).build()?;
+    let (info, frames) = decoder.decode_frames::<u8>(&result)?;
+    assert_eq!(info.have_animation, JxlBool::False);
+    assert_eq!(
+        frames
+            .iter()
+            .map(|f| (f.x, f.y, f.width, f.height, f.blend_mode))
+            .collect::<Vec<_>>(),
+        [
+            (0, 0, width, height, BlendMode::Replace),
+            (5, 8, 10, 20, BlendMode::Add)
+        ]
+    );
+
+    // Merged into a single image
+    decoder.coalescing = None;
+    let (_, frames) = decoder.decode_frames::<u8>(&result)?;
+    assert_eq!(frames.len(), 1);
+    assert_eq!((frames[0].x, frames[0].width), (0, width));
+    assert_eq!(frames[0].blend_mode, BlendMode::Replace);
+
+    Ok(())
+}
+
+#[test]
+fn frames_iter() -> TestResult {
+    use crate::encode::Animation;
+
+    let sample = get_sample().to_rgb8();
+    let mut encoder = encoder_builder()
+        .animation(Animation::milliseconds(0))
+        .build()?;
+
+    let frames = (1..=3).map(|i| (sample.to_vec(), i * 100));
+    let result: EncoderResult<u8> =
+        encoder.encode_frames_iter(frames, sample.width(), sample.height())?;
+
+    let mut decoder = decoder_builder().build()?;
+    let (_, frames) = decoder.decode_frames::<u8>(&result)?;
+    assert_eq!(
+        frames.iter().map(|f| f.duration).collect::<Vec<_>>(),
+        [100, 200, 300]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn color_transform() -> TestResult {
+    use crate::encode::ColorTransform;
+
+    let sample = get_sample().to_rgb8();
+    let mut encoder = encoder_builder()
+        .lossless(true)
+        .uses_original_profile(true)
+        .color_transform(ColorTransform::None)
+        .build()?;
+
+    let result: EncoderResult<u8> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+
+    let decoder = decoder_builder().build()?;
+    let (_, data) = decoder.decode_with::<u8>(&result)?;
+    assert_eq!(data, sample.as_raw().as_slice());
+
+    Ok(())
+}
+
+#[test]
+fn epf_iters() -> TestResult {
+    let sample = get_sample().to_rgb8();
+    let decoder = decoder_builder().build()?;
+
+    for iters in [0, 3] {
+        let mut encoder = encoder_builder().epf_iters(iters).build()?;
+        let result: EncoderResult<u8> =
+            encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+        decoder.decode(&result)?;
+    }
+
+    let mut encoder = encoder_builder().epf_iters(4).build()?;
+    assert!(encoder
+        .encode::<u8, u8>(sample.as_raw(), sample.width(), sample.height())
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn to_writer() -> TestResult {
+    let sample = get_sample().to_rgb8();
+    let mut encoder = encoder_builder().build()?;
+
+    let frame = EncoderFrame::new(sample.as_raw());
+    let expected: EncoderResult<u8> =
+        encoder.encode_frame(&frame, sample.width(), sample.height())?;
+
+    let mut output = vec![];
+    encoder.encode_to_writer::<u8, u8, _>(&frame, sample.width(), sample.height(), &mut output)?;
+    assert_eq!(output, *expected);
+
+    // The encoder is reset after writing
+    let mut output = vec![];
+    encoder.encode_to_writer::<u8, u8, _>(&frame, sample.width(), sample.height(), &mut output)?;
+    assert_eq!(output, *expected);
+
+    // Including when the writer fails
+    let mut output = [0; 16];
+    assert!(matches!(
+        encoder.encode_to_writer::<u8, u8, _>(
+            &frame,
+            sample.width(),
+            sample.height(),
+            &mut output[..]
+        ),
+        Err(EncodeError::Io(_))
+    ));
+    let mut output = vec![];
+    encoder.encode_to_writer::<u8, u8, _>(&frame, sample.width(), sample.height(), &mut output)?;
+    assert_eq!(output, *expected);
+
+    // Or when the frame is rejected
+    let short = EncoderFrame::new(&sample.as_raw()[..10]);
+    assert!(encoder
+        .encode_frame::<u8, u8>(&short, sample.width(), sample.height())
+        .is_err());
+    let result: EncoderResult<u8> =
+        encoder.encode_frame(&frame, sample.width(), sample.height())?;
+    assert_eq!(*result, *expected);
+
+    Ok(())
+}
+
+#[test]
+fn invalid_options() -> TestResult {
+    let sample = get_sample().to_rgb8();
+
+    for quality in [-1.0, 15.5, f32::NAN] {
+        let mut encoder = encoder_builder().quality(quality).build()?;
+        assert!(matches!(
+            encoder.encode::<u8, u8>(sample.as_raw(), sample.width(), sample.height()),
+            Err(EncodeError::InvalidQuality(_))
+        ));
+    }
+
+    let mut encoder = encoder_builder().decoding_speed(5).build()?;
+    assert!(matches!(
+        encoder.encode::<u8, u8>(sample.as_raw(), sample.width(), sample.height()),
+        Err(EncodeError::InvalidDecodingSpeed(5))
+    ));
+
+    let mut encoder = encoder_builder().quality(15.0).decoding_speed(4).build()?;
+    let _: EncoderResult<u8> = encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+
+    Ok(())
+}
+
+#[test]
+fn premultiplied_alpha() -> TestResult {
+    use jpegxl_sys::types::JxlBool;
+
+    let sample = get_sample().to_rgba8();
+    let frame = EncoderFrame::new(sample.as_raw()).num_channels(4);
+    let decoder = decoder_builder().build()?;
+
+    for premultiplied in [false, true] {
+        let mut encoder = encoder_builder()
+            .has_alpha(true)
+            .premultiplied_alpha(premultiplied)
+            .build()?;
+        let result: EncoderResult<u8> =
+            encoder.encode_frame(&frame, sample.width(), sample.height())?;
+
+        let info = decoder.info(&result)?;
+        assert_eq!(info.alpha_premultiplied, JxlBool::from(premultiplied));
+    }
+
+    Ok(())
+}
+
 #[test]
 fn gray() -> TestResult {
     let sample = get_sample().to_luma8();
@@ -193,3 +563,47 @@ fn initial_buffer() -> TestResult {
     let _: EncoderResult<f32> = encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
     Ok(())
 }
+
+#[test]
+#[allow(clippy::float_cmp)]
+fn target_size() -> TestResult {
+    let sample = get_sample().to_rgb8();
+    let mut encoder = encoder_builder().quality(2.0).build()?;
+    let reference: EncoderResult<u8> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+
+    let target = reference.len();
+    encoder.quality = 1.0;
+    encoder.target_size = Some(target);
+    let result: EncoderResult<u8> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+    assert!(result.len() <= target);
+    assert!(result.len() >= target - target / 20);
+    assert_eq!(encoder.quality, 1.0);
+
+    let decoder = decoder_builder().build()?;
+    _ = decoder.decode(&result)?;
+
+    Ok(())
+}
+
+#[test]
+fn encode_batch() -> TestResult {
+    let sample = get_sample().to_rgb8();
+    let cropped = image::imageops::crop_imm(&sample, 0, 0, 20, 10).to_image();
+    let inputs = [
+        (sample.clone().into_raw(), sample.width(), sample.height()),
+        (cropped.into_raw(), 20, 10),
+    ];
+
+    let results = crate::encode::encode_batch(&inputs, 2, || encoder_builder().build());
+    assert_eq!(results.len(), 2);
+
+    let decoder = decoder_builder().build()?;
+    for (res, (_, width, height)) in results.into_iter().zip(&inputs) {
+        let (metadata, _) = decoder.decode(&res?)?;
+        assert_eq!((metadata.width, metadata.height), (*width, *height));
+    }
+
+    Ok(())
+}
@@ -19,13 +19,24 @@ along with jpegxl-rs.  If not, see <https://www.gnu.org/licenses/>.
 
 use jpegxl_sys::decode::{JxlSignature, JxlSignatureCheck};
 
+/// Result of [`check_signature`]
+pub type Signature = JxlSignature;
+
+/// Check the signature of the input, telling a bare codestream from a container, e.g. to
+/// sniff whether bytes are JPEG XL before creating a decoder.
+/// Only the first 12 bytes at most are read.
+#[must_use]
+pub fn check_signature(buf: &[u8]) -> Signature {
+    unsafe { JxlSignatureCheck(buf.as_ptr(), buf.len()) }
+}
+
 /// Check if the signature of the input is valid.
 /// Return `None` if it needs more data.
 #[must_use]
 pub fn check_valid_signature(buf: &[u8]) -> Option<bool> {
     use JxlSignature::{Codestream, Container, Invalid, NotEnoughBytes};
 
-    match unsafe { JxlSignatureCheck(buf.as_ptr(), buf.len()) } {
+    match check_signature(buf) {
         NotEnoughBytes => None,
         Invalid => Some(false),
         Codestream | Container => Some(true),
@@ -44,5 +55,13 @@ mod tests {
         assert!(check_valid_signature(&[]).is_none());
         assert_eq!(check_valid_signature(&[0; 64]), Some(false));
         assert_eq!(check_valid_signature(SAMPLE_JXL), Some(true));
+
+        assert_eq!(check_signature(&[]), Signature::NotEnoughBytes);
+        assert_eq!(check_signature(&[0; 64]), Signature::Invalid);
+        assert_eq!(check_signature(SAMPLE_JXL), Signature::Codestream);
+        assert_eq!(
+            check_signature(&[0, 0, 0, 0x0c, b'J', b'X', b'L', b' ', 0x0d, 0x0a, 0x87, 0x0a]),
+            Signature::Container
+        );
     }
 }